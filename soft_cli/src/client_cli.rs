@@ -50,6 +50,7 @@ pub fn client_main(matches: ArgMatches) {
     let migration_interval: Option<Duration> = matches
         .value_of("migrate")
         .map(|str| Duration::from_millis(str.parse().expect("invalid m argument")));
+    let scavenger = matches.is_present("background");
 
     if first_loss_probability == 0.0 {
         first_loss_probability = repeated_loss_probability;
@@ -82,7 +83,7 @@ pub fn client_main(matches: ArgMatches) {
             continue;
         }
         let cloned_socket = socket.try_clone().expect("Unable to clone socket");
-        download_file(cloned_socket, filename, migration_interval);
+        download_file(cloned_socket, filename, migration_interval, scavenger);
     }
 }
 
@@ -117,8 +118,8 @@ fn setup_udp_socket(ip: IpAddr, port: u16, p: f64, q: f64) -> LossSimulationUdpS
     socket
 }
 
-fn download_file(socket: LossSimulationUdpSocket, filename: &str, migration: Option<Duration>) {
-    let client = Arc::new(Client::init(socket, filename.to_string(), migration));
+fn download_file(socket: LossSimulationUdpSocket, filename: &str, migration: Option<Duration>, scavenger: bool) {
+    let client = Arc::new(Client::init(socket, filename.to_string(), migration, scavenger));
     if client.state() == ClientStateType::Downloaded {
         return;
     }
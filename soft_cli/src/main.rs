@@ -91,6 +91,186 @@ fn main() {
                 .help("specify the migration interval in milliseconds")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("background")
+                .short("b")
+                .long("background")
+                .conflicts_with("server")
+                .help("Request a low priority transfer that yields to other traffic")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("scavenger_path")
+                .long("scavenger-path")
+                .value_name("PATH")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Always serve files below this path, relative to the served directory, as low priority transfers")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("checksum_store")
+                .long("checksum-store")
+                .value_name("PATH")
+                .requires("server")
+                .conflicts_with("host")
+                .help("The file the checksums of served files are persisted in (default: .soft_checksums in the served directory)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("precompute_checksums")
+                .long("precompute-checksums")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Compute the checksums of all served files at start and whenever they are modified")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("max_concurrent_checksums")
+                .long("max-concurrent-checksums")
+                .value_name("NUMBER")
+                .requires("server")
+                .conflicts_with("host")
+                .help("The number of files that are hashed at the same time")
+                .default_value_if("server", None, "2")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("snapshots")
+                .long("snapshots")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Serve snapshots of the files, so they can be modified in place during transfers")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("snapshot_dir")
+                .long("snapshot-dir")
+                .value_name("PATH")
+                .requires("snapshots")
+                .help("The directory the snapshots are stored in (default: .soft_snapshots in the served directory)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("snapshot_retention")
+                .long("snapshot-retention")
+                .value_name("SECONDS")
+                .requires("snapshots")
+                .help("Remove snapshots that have not been requested for this time (default: 3600)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("archives")
+                .long("archives")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Serve the members of tar and zip archives, e.g. bundle.tar/inner/file.bin")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("symlinks")
+                .long("symlinks")
+                .value_name("POLICY")
+                .possible_values(&["deny", "within-root", "follow"])
+                .requires("server")
+                .conflicts_with("host")
+                .help("Serve files behind symbolic links never, only if they point into the served directory, or always (default: within-root)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("hide_dot_files")
+                .long("hide-dot-files")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Do not serve files and directories whose name starts with a dot")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("mount")
+                .long("mount")
+                .value_name("PREFIX=DIR[,ro][,precompute][,scavenger]")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Serve the directory under the prefix, read-only without snapshots, with precomputed checksums or as low priority transfers")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("acl")
+                .long("acl")
+                .value_name("RULE")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Allow or deny files for clients, the first matching rule applies, e.g. \"allow lab/** 10.1.0.0/16\" \"deny lab/**\", files behind symbolic links are not served if rules are set")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("metrics_port")
+                .long("metrics-port")
+                .value_name("PORT")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Serve the server statistics in the Prometheus text format on this local TCP port")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("metrics_per_connection")
+                .long("metrics-per-connection")
+                .requires("metrics_port")
+                .help("Also serve the statistics of every active connection, labeled with its client address")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("access_log")
+                .long("access-log")
+                .value_name("FILE")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Append one line per finished or failed transfer to this file, reopened on SIGHUP")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("access_log_format")
+                .long("access-log-format")
+                .value_name("FORMAT")
+                .possible_values(&["common", "json"])
+                .requires("access_log")
+                .help("The format of the access log lines (default: common)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("shutdown_timeout")
+                .long("shutdown-timeout")
+                .value_name("SECONDS")
+                .requires("server")
+                .conflicts_with("host")
+                .help("After Ctrl-C, let active transfers finish for this time before they are stopped")
+                .default_value_if("server", None, "10")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("shards")
+                .long("shards")
+                .value_name("NUMBER")
+                .requires("server")
+                .conflicts_with("host")
+                .help("The number of receive loops, each with its own socket on the port (default: 1)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .requires("server")
+                .conflicts_with("host")
+                .help("Read the server configuration from this TOML file, options on the command line take precedence; reloaded on SIGHUP")
+                .takes_value(true)
+        )
         .get_matches();
 
         if matches.is_present("server") {
@@ -1,11 +1,14 @@
 use clap::ArgMatches;
-use std::path::PathBuf;
-use log::{LevelFilter, info};
+use std::io;
+use std::path::{Path, PathBuf};
+use log::{LevelFilter, info, warn};
 use std::convert::TryFrom;
-use std::net::{SocketAddrV4, Ipv4Addr};
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
 use soft_server_async_lib::server::Server;
+use soft_server_async_lib::server_config::ServerConfig;
+use soft_server_async_lib::mount_table::check_mounts;
 use signal_hook::iterator::Signals;
-use signal_hook::consts::SIGINT;
+use signal_hook::consts::{SIGHUP, SIGINT};
 use std::time::Duration;
 use std::thread::sleep;
 
@@ -14,9 +17,6 @@ pub fn server_main(matches: ArgMatches) {
         .value_of("port").expect("port not specified")
         .parse().expect("invalid port");
 
-    let served_dir = PathBuf::try_from(matches.value_of("served_directory").unwrap())
-        .expect("invalid served directory");
-
     let log_level = match matches.occurrences_of("verbose") {
         0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
@@ -26,6 +26,40 @@ pub fn server_main(matches: ArgMatches) {
 
     env_logger::builder().filter_level(log_level).init();
 
+    let config = server_config(&matches).unwrap_or_else(|e| panic!("invalid server configuration, caused by: {}", e));
+    let shutdown_timeout = Duration::from_secs(matches.value_of("shutdown_timeout").unwrap()
+        .parse().expect("invalid shutdown timeout"));
+
+    let server = Server::start_with_config(
+        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port),
+        config,
+    );
+
+    info!("Press Ctrl-C to stop server...");
+    wait_for_ctrl_c(&server, &matches);
+
+    info!("Stopping server, active transfers have {} seconds to finish...", shutdown_timeout.as_secs());
+    server.shutdown(shutdown_timeout);
+}
+
+/// the config file, if any, with the options of the command line
+///
+/// options with default values only override the config file if they are given,
+/// the served directory can not be overridden
+fn server_config(matches: &ArgMatches) -> io::Result<ServerConfig> {
+    let config_file = matches.is_present("config");
+    if config_file && matches.occurrences_of("served_directory") > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the served directory is set in the config file"));
+    }
+    let mut config = match matches.value_of("config") {
+        Some(config_file) => ServerConfig::load(Path::new(config_file))?,
+        None => {
+            let served_dir = PathBuf::try_from(matches.value_of("served_directory").unwrap())
+                .expect("invalid served directory");
+            ServerConfig::new(served_dir)
+        }
+    };
+
     let mut first_loss_probability: f64 = matches.value_of("first_loss_probability").unwrap()
         .parse().expect("invalid p argument");
     let mut repeated_loss_probability: f64 = matches.value_of("repeated_loss_probability").unwrap()
@@ -39,24 +73,85 @@ pub fn server_main(matches: ArgMatches) {
         repeated_loss_probability = first_loss_probability;
     }
 
-    let server = Server::start(
-        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port),
-        served_dir.clone(),
-        first_loss_probability,
-        repeated_loss_probability
-    );
-
-    info!("Press Ctrl-C to stop server...");
-    wait_for_ctrl_c();
-
-    drop(server); // stop server
+    config.first_loss_probability = first_loss_probability;
+    config.repeated_loss_probability = repeated_loss_probability;
+    if let Some(scavenger_paths) = matches.values_of("scavenger_path") {
+        config.scavenger_paths = scavenger_paths.map(PathBuf::from).collect();
+    }
+    if let Some(checksum_store_path) = matches.value_of("checksum_store") {
+        config.checksum_store_path = Some(PathBuf::from(checksum_store_path));
+    }
+    if matches.is_present("precompute_checksums") {
+        config.precompute_checksums = true;
+    }
+    if !config_file || matches.occurrences_of("max_concurrent_checksums") > 0 {
+        config.max_concurrent_checksums = matches.value_of("max_concurrent_checksums").unwrap()
+            .parse().expect("invalid number of concurrent checksums");
+        assert!(config.max_concurrent_checksums > 0, "at least one concurrent checksum is required");
+    }
+    if matches.is_present("snapshots") {
+        config.enable_snapshots();
+    }
+    if let Some(snapshot_dir) = matches.value_of("snapshot_dir") {
+        config.snapshot_dir = Some(PathBuf::from(snapshot_dir));
+    }
+    if let Some(snapshot_retention) = matches.value_of("snapshot_retention") {
+        config.snapshot_retention = Duration::from_secs(snapshot_retention.parse().expect("invalid snapshot retention"));
+    }
+    if matches.is_present("archives") {
+        config.serve_archive_members = true;
+    }
+    if let Some(symlinks) = matches.value_of("symlinks") {
+        config.symlink_policy = symlinks.parse().unwrap();
+    }
+    if matches.is_present("hide_dot_files") {
+        config.serve_dot_files = false;
+    }
+    if let Some(mounts) = matches.values_of("mount") {
+        config.mounts = mounts.map(|mount| mount.parse().unwrap_or_else(|e| panic!("invalid mount: {}", e))).collect();
+        check_mounts(&config.mounts).unwrap_or_else(|e| panic!("invalid mount: {}", e));
+    }
+    if let Some(access_rules) = matches.values_of("acl") {
+        config.access_rules = access_rules.map(|rule| rule.parse().unwrap_or_else(|e| panic!("invalid access rule: {}", e))).collect();
+    }
+    if let Some(metrics_port) = matches.value_of("metrics_port") {
+        let metrics_port = metrics_port.parse().expect("invalid metrics port");
+        config.metrics_addr = Some(SocketAddr::from((Ipv4Addr::LOCALHOST, metrics_port)));
+    }
+    if matches.is_present("metrics_per_connection") {
+        config.metrics_per_connection = true;
+    }
+    if let Some(access_log) = matches.value_of("access_log") {
+        config.access_log_path = Some(PathBuf::from(access_log));
+    }
+    if let Some(access_log_format) = matches.value_of("access_log_format") {
+        config.access_log_format = access_log_format.parse().unwrap();
+    }
+    if let Some(shards) = matches.value_of("shards") {
+        config.shards = shards.parse().expect("invalid number of shards");
+        assert!(config.shards > 0, "at least one shard is required");
+    }
+    Ok(config)
 }
 
-fn wait_for_ctrl_c(){
-    let mut signals = Signals::new(&[SIGINT]).unwrap();
+/// reopens the access log on SIGHUP, so it can be rotated, and reloads the config file
+fn wait_for_ctrl_c(server: &Server, matches: &ArgMatches){
+    let mut signals = Signals::new(&[SIGINT, SIGHUP]).unwrap();
     loop {
-        if signals.pending().count() != 0 {
-            return
+        for signal in signals.pending() {
+            if signal == SIGINT {
+                return
+            }
+            match server.reopen_access_log() {
+                Ok(()) => info!("reopened access log"),
+                Err(e) => warn!("failed to reopen access log, caused by: {}", e),
+            }
+            if matches.is_present("config") {
+                match server_config(matches) {
+                    Ok(config) => server.reload(config),
+                    Err(e) => warn!("failed to reload config, caused by: {}", e),
+                }
+            }
         }
         sleep(Duration::from_secs(1));
     }
@@ -12,7 +12,7 @@ use soft_shared_lib::packet::packet_buf::PacketBuf;
 use soft_shared_lib::packet::req_packet::ReqPacket;
 use std::cmp::max;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, ErrorKind, Read, Write};
 use std::os::unix::prelude::MetadataExt;
 use std::path::Path;
 use std::sync::atomic::Ordering::SeqCst;
@@ -20,7 +20,17 @@ use std::sync::Arc;
 use std::time::{Instant, Duration};
 use std::thread;
 use std::net::UdpSocket;
-use soft_shared_lib::times::ack_packet_retransmission_timeout;
+use soft_shared_lib::times::{ack_packet_retransmission_timeout, checksum_retry_delay, INITIAL_RTT};
+use soft_shared_lib::soft_error_code::SoftErrorCode;
+use soft_shared_lib::constants::REQ_FLAG_SCAVENGER;
+use soft_shared_lib::rtt_estimator::RttEstimator;
+use soft_shared_lib::field_types::{RequestFlags, ReceiveWindow, ConnectionId, NextSequenceNumber, CeCount};
+use soft_shared_lib::helper::ecn_helper::Ecn;
+use soft_shared_lib::packet::data_packet::DataPacket;
+use crate::rtt_sampler::RttSampler;
+use crate::disk_writer::DiskWriter;
+use crate::reorder_buffer::ReorderBuffer;
+use std::sync::Mutex;
 
 pub const SUPPORTED_PROTOCOL_VERSION: u8 = 1;
 const MAX_PACKET_SIZE: usize = 1200;
@@ -34,17 +44,23 @@ pub struct Client {
     migration: Option<Duration>,
     initial_ack: Atomic<Option<Instant>>,
     last_migration: Atomic<Option<Instant>>,
+    /// request a low priority transfer that yields to other traffic
+    scavenger: bool,
+    rtt_sampler: Mutex<RttSampler>,
+    /// number of received DATA packets with the ECN codepoint CE
+    ce_count: Atomic<CeCount>,
 }
 
 impl Client {
     //TODO: Implement timeout for case of server unreachability
-    pub fn init(socket: LossSimulationUdpSocket, filename: String, migration: Option<Duration>) -> Client {
+    pub fn init(socket: LossSimulationUdpSocket, filename: String, migration: Option<Duration>, scavenger: bool) -> Client {
         let state = Arc::new(ClientState::new(socket));
         let download_buffer: File;
         let offset = Atomic::new(0);
         let migration = migration;
 
         log::debug!("Creating client to get file {}", filename);
+        Client::enable_ecn_reception(&state);
         state.state_type.store(ClientStateType::Preparing, SeqCst);
 
         if Path::new(&filename).exists() {
@@ -79,6 +95,9 @@ impl Client {
             migration,
             initial_ack: Atomic::new(None),
             last_migration: Atomic::new(None),
+            scavenger,
+            rtt_sampler: Mutex::new(RttSampler::new()),
+            ce_count: Atomic::new(0),
         }
     }
 
@@ -132,9 +151,17 @@ impl Client {
         if self.state.state_type.load(SeqCst) == ClientStateType::Stopped {
             return;
         }
-        self.handshake();
+        loop {
+            self.handshake();
 
-        self.do_file_transfer();
+            self.do_file_transfer();
+
+            if !self.state.file_changed.swap(false, SeqCst) {
+                break;
+            }
+            // the server has sent FILE_CHANGED during the transfer
+            self.reset_download();
+        }
 
         self.validate_download();
 
@@ -164,16 +191,35 @@ impl Client {
         // TODO: Add handshake timeout
         self.make_handshake();
 
-        if self.state.file_changed.load(SeqCst) == true {
-            // File's changed, the checksums are different, set the offset to 0 and re handshake.
-            self.offset.store(0, SeqCst);
+        if self.state.file_changed.swap(false, SeqCst) {
+            // File's changed, the checksums are different, the offset is 0 now, re handshake.
             self.make_handshake();
         }
     }
 
+    /// discard the partially downloaded file, the next handshake requests the file from offset 0
+    fn reset_download(&self) {
+        Client::clean_checksum(&self.filename);
+        self.state.checksum.store(None, SeqCst);
+        self.state.sequence_nr.store(0, SeqCst);
+        self.state.transferred_bytes.store(0, SeqCst);
+        self.offset.store(0, SeqCst);
+        // the CE count and the ACK tokens belong to the old connection
+        self.ce_count.store(0, SeqCst);
+        self.rtt_sampler.lock().unwrap().reset();
+        // the server may be busy generating the checksum of the changed file, like for the initial handshake
+        self.state.socket.read().unwrap().set_read_timeout(Some(INITIAL_RTT)).unwrap();
+        if Path::new(&self.filename).exists() {
+            fs::remove_file(&self.filename).expect("delete failed");
+        }
+    }
+
     fn handle_error(&self, e: &mut ErrPacket) {
         match e.error_code() {
-            soft_shared_lib::soft_error_code::SoftErrorCode::Stop => todo!(),
+            soft_shared_lib::soft_error_code::SoftErrorCode::Stop => {
+                // the checksum file is kept, so the download can be resumed
+                log::error!("Server stopped the transfer, the download of {} can be resumed later", self.filename);
+            }
             soft_shared_lib::soft_error_code::SoftErrorCode::Internal => {
                 log::error!("Unknown Internal Error Occured, aborting");
             }
@@ -184,9 +230,8 @@ impl Client {
                 );
             }
             soft_shared_lib::soft_error_code::SoftErrorCode::ChecksumNotReady => {
-                log::info!("Checksum Not Ready, retrying download of {} in 5 seconds", self.filename);
-                thread::sleep(Duration::from_secs(5));
-                self.run();
+                // only expected during the handshake
+                log::error!("Checksum Not Ready, aborting download of {}", self.filename);
             }
             soft_shared_lib::soft_error_code::SoftErrorCode::InvalidOffset => {
                 log::error!(
@@ -202,7 +247,16 @@ impl Client {
                 );
             }
             soft_shared_lib::soft_error_code::SoftErrorCode::FileChanged => {
-                log::error!("File Changed, aborting download of {}", self.filename);
+                log::info!("File changed on the server, restarting download of {}", self.filename);
+                self.state.file_changed.store(true, SeqCst);
+                self.state.state_type.store(ClientStateType::Preparing, SeqCst);
+                return;
+            }
+            soft_shared_lib::soft_error_code::SoftErrorCode::AccessDenied => {
+                log::error!("Access denied by the server, aborting download of {}", self.filename);
+            }
+            soft_shared_lib::soft_error_code::SoftErrorCode::ServerUnavailable => {
+                log::error!("Server is shutting down, aborting download of {}", self.filename);
             }
             soft_shared_lib::soft_error_code::SoftErrorCode::BadPacket => {
                 log::error!("Bad packet found, aborting download of {}", self.filename);
@@ -211,19 +265,38 @@ impl Client {
         self.state.state_type.store(ClientStateType::Error, SeqCst);
     }
 
+    fn request_flags(&self) -> RequestFlags {
+        let mut flags = 0;
+        if self.scavenger {
+            flags |= REQ_FLAG_SCAVENGER;
+        }
+        flags
+    }
+
+    /// repeats the request until the checksum of the server is ready
     fn make_handshake(&self) {
+        while let Some(retry_delay) = self.try_handshake() {
+            thread::sleep(retry_delay);
+        }
+    }
+
+    /// the delay until the next request, if the checksum of the server is not ready
+    fn try_handshake(&self) -> Option<Duration> {
         if self.state.state_type.load(SeqCst) == ClientStateType::Stopped
             || self.state.state_type.load(SeqCst) == ClientStateType::Error
         {
-            return;
+            return None;
         }
         let mut recv_buf = [0; MAX_PACKET_SIZE];
         let mut send_buf: PacketBuf;
 
-        send_buf = PacketBuf::Req(ReqPacket::new_buf(
+        // the server might still retain the version that has been partially downloaded
+        send_buf = PacketBuf::Req(ReqPacket::new_buf_with_resume_checksum(
             MAX_PACKET_SIZE as u16,
             &self.filename,
             self.offset.load(SeqCst),
+            self.request_flags(),
+            self.state.checksum.load(SeqCst),
         ));
 
         self.state
@@ -243,12 +316,12 @@ impl Client {
             Err(e) if e.kind() == ErrorKind::WouldBlock => {
                 log::error!("Connection Timed out");
                 self.state.state_type.store(ClientStateType::Error, SeqCst);
-                return;
+                return None;
             }
             Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
                 log::error!("Host not reachable");
                 self.state.state_type.store(ClientStateType::Error, SeqCst);
-                return;
+                return None;
             }
             Err(_) => (),
         }
@@ -262,7 +335,7 @@ impl Client {
                     version
                 );
                 self.state.state_type.store(ClientStateType::Error, SeqCst);
-                return;
+                return None;
             }
             Ok(Acc(p)) => {
                 log::trace!("{}: received {}", self.state.connection_id.load(SeqCst), p);
@@ -272,15 +345,9 @@ impl Client {
                             "File changed, re-handshaking to downloading latest file. {}",
                             self.filename
                         );
-                        self.clean_up();
-                        // Reset checksum
-                        self.state.checksum.store(None, SeqCst);
-                        self.state.sequence_nr.store(0, SeqCst);
+                        self.reset_download();
                         self.state.file_changed.store(true, SeqCst);
-                        self.state.transferred_bytes.store(0, SeqCst);
-                        // Delete old file
-                        fs::remove_file(&self.filename).expect("delete failed");
-                        return;
+                        return None;
                     } else {
                         log::debug!("Partial file checksums are equal. Continuing download");
                     }
@@ -295,11 +362,11 @@ impl Client {
                 log::debug!("Connection ID: {}", p.connection_id());
                 log::debug!("File Size: {}", p.file_size());
                 log::debug!("Checksum: {}", sha256_to_hex_string(p.checksum()));
-                send_buf = PacketBuf::Ack(AckPacket::new_buf(
+                send_buf = self.new_ack_buf(
                     RECEIVE_WINDOW_THRESH as u16,
                     self.state.connection_id.load(SeqCst),
                     0,
-                ));
+                );
 
                 log::trace!("{}: sending {}", self.state.connection_id.load(SeqCst), send_buf);
                 self.state
@@ -315,9 +382,14 @@ impl Client {
 
                 log::debug!("Handshake successfully completed");
             }
+            Ok(Packet::Err(error_packet)) if error_packet.error_code() == SoftErrorCode::ChecksumNotReady => {
+                let retry_delay = checksum_retry_delay(error_packet.retry_after());
+                log::info!("Checksum Not Ready, retrying download of {} in {:?}", self.filename, retry_delay);
+                return Some(retry_delay);
+            }
             Ok(Packet::Err(error_packet)) => {
                 self.handle_error(error_packet);
-                return;
+                return None;
             }
             // Discard other packets types we encounter.
             _ => {}
@@ -326,8 +398,9 @@ impl Client {
         if self.state.checksum.load(SeqCst).is_none() {
             log::error!("Handshake failed");
             self.state.state_type.store(ClientStateType::Error, SeqCst);
-            return;
+            return None;
         }
+        None
     }
 
     fn validate_download(&self) {
@@ -373,15 +446,14 @@ impl Client {
             .store(ClientStateType::Downloading, SeqCst);
         log::debug!("Starting download");
 
+        // the file is either empty or contains the bytes up to the offset
         let download_file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.filename)
             .expect("Unable to open file for downloading.");
-        let mut download_buffer = BufWriter::with_capacity(MB_1, download_file);
-        download_buffer
-            .seek(SeekFrom::Start(self.offset.load(SeqCst)))
-            .expect("Unable to seek to offset");
+        let mut disk_writer = DiskWriter::new(download_file);
+        let mut reorder_buffer = ReorderBuffer::new();
 
         let mut receive_window;
         let mut recv_buf = [0; MAX_PACKET_SIZE];
@@ -395,20 +467,10 @@ impl Client {
             let packet_size = self.state.socket
                 .read()
                 .unwrap()
-                .recv(&mut recv_buf);
+                .recv_with_ecn(&mut recv_buf);
 
             match packet_size {
-                Ok(packet_size) => {
-                    // Store rtt measurement on the socket and set socket timeout
-                    if self.state.sequence_nr.load(SeqCst) == 0 {
-                        self.state.rtt.store(
-                            Some(self.initial_ack.load(SeqCst).unwrap().elapsed()),
-                            SeqCst,
-                        );
-                        self.state.socket.read().unwrap().set_read_timeout(Some(ack_packet_retransmission_timeout(self.state.rtt.load(SeqCst).unwrap()))).unwrap();
-                        log::debug!("Initial RTT measurement: {:?}", self.state.rtt.load(SeqCst).unwrap());
-                    }
-
+                Ok((packet_size, ecn)) => {
                     if !self.migration.is_none() && self.last_migration.load(SeqCst).unwrap().elapsed() > self.migration.unwrap() {
                         self.migrate();
                     }
@@ -416,7 +478,7 @@ impl Client {
                     let unchecked_packet = Packet::from_buf(&mut recv_buf[0..packet_size]);
 
                     // Calculate current receive window
-                    receive_window = self.calculate_recv_window(&mut download_buffer);
+                    receive_window = self.calculate_recv_window(&disk_writer);
 
                     match unchecked_packet {
                         Err(UnsupportedSoftVersion(_)) => {
@@ -424,32 +486,46 @@ impl Client {
                         }
                         Ok(Data(p)) => {
                             log::trace!("{}: received {}", p.connection_id(), p);
+                            self.sample_rtt(p);
+                            if ecn == Ecn::Ce {
+                                // echoed in the ACK packets, so the server reduces its congestion window
+                                self.ce_count.fetch_add(1, SeqCst);
+                            }
                             if p.sequence_number() == self.state.sequence_nr.load(SeqCst) {
                                 // This matches if the received packets matches the expected packet
-                                self.state.sequence_nr.store(p.sequence_number() + 1, SeqCst);
-
-                                download_buffer.write_all(p.data()).unwrap();
-
-                                let send_buf = PacketBuf::Ack(AckPacket::new_buf(
+                                let mut next_sequence_number = p.sequence_number() + 1;
+                                disk_writer.write(p.data());
+                                self.state.transferred_bytes.fetch_add(p.data().len() as u64, SeqCst);
+                                // packets that have been received ahead are in order now
+                                while let Some(data) = reorder_buffer.pop(next_sequence_number) {
+                                    disk_writer.write(&data);
+                                    self.state.transferred_bytes.fetch_add(data.len() as u64, SeqCst);
+                                    next_sequence_number += 1;
+                                }
+                                self.state.sequence_nr.store(next_sequence_number, SeqCst);
+
+                                let send_buf = self.new_ack_buf(
                                     receive_window as u16,
                                     connection_id,
-                                    p.sequence_number() + 1,
-                                ));
+                                    next_sequence_number,
+                                );
 
                                 log::trace!("{}: sending {}", p.connection_id(), send_buf);
                                 self.state.socket
                                     .read()
                                     .unwrap()
                                     .send(send_buf.buf()).unwrap();
-
-                                self.state.transferred_bytes.fetch_add(p.data().len() as u64, SeqCst);
                             } else if p.sequence_number() > self.state.sequence_nr.load(SeqCst) {
                                 log::trace!("Received unexpected data packet: Expected {:?}, Got: {:?}", self.state.sequence_nr.load(SeqCst), p.sequence_number());
-                                let packet = PacketBuf::Ack(AckPacket::new_buf(
+                                if reorder_buffer.bytes() < MB_1 {
+                                    reorder_buffer.insert(p.sequence_number(), p.data());
+                                }
+                                // duplicate ACK to notify the server about the lost packet
+                                let packet = self.new_ack_buf(
                                     receive_window as u16,
                                     connection_id,
                                     self.state.sequence_nr.load(SeqCst),
-                                ));
+                                );
                                 log::trace!("{}: sending {}", p.connection_id(), packet);
                                 self.state.socket.read().unwrap().send(packet.buf()).unwrap();
                             }
@@ -462,12 +538,12 @@ impl Client {
                     // The ACK Retransmission Timeout is important for migration
                     log::debug!("ACK Retransmission Timeout, resending ACK [sequence_number: {:?}]", self.state.sequence_nr.load(SeqCst));
                     // Calculate current receive window
-                    receive_window = self.calculate_recv_window(&mut download_buffer);
-                    let send_buf = PacketBuf::Ack(AckPacket::new_buf(
+                    receive_window = self.calculate_recv_window(&disk_writer);
+                    let send_buf = self.new_ack_buf(
                         receive_window as u16,
                         connection_id,
                         self.state.sequence_nr.load(SeqCst),
-                    ));
+                    );
 
                     self.state.socket
                         .read()
@@ -479,8 +555,8 @@ impl Client {
                 }
             }
         }
-        download_buffer
-            .flush()
+        disk_writer
+            .finish()
             .expect("Error occured when flushing writer");
         return;
     }
@@ -506,20 +582,65 @@ impl Client {
         lock.swap_socket(new_socket);
         drop(lock);
         self.state.socket.read().unwrap().connect(server_address).expect("Reconnection to server failed");
+        Client::enable_ecn_reception(&self.state);
         self.last_migration.store(Some(Instant::now()), SeqCst);
-        self.state.socket.read().unwrap().set_read_timeout(Some(3 * self.state.rtt.load(SeqCst).unwrap())).unwrap();
+        // the rtt of the old path is meaningless for the new path
+        self.state.rtt_estimator.store(RttEstimator::new(), SeqCst);
+        self.rtt_sampler.lock().unwrap().reset();
+        self.state.socket.read().unwrap().set_read_timeout(Some(self.ack_retransmission_timeout())).unwrap();
+    }
+
+    /// ACK packet with a new token for RTT measurement and the CE count
+    fn new_ack_buf(&self, receive_window: ReceiveWindow, connection_id: ConnectionId, next_sequence_number: NextSequenceNumber) -> PacketBuf {
+        let token = self.rtt_sampler.lock().unwrap().next_token();
+        let ce_count = self.ce_count.load(SeqCst);
+        PacketBuf::Ack(AckPacket::new_buf_with_extension(receive_window, connection_id, next_sequence_number, token, ce_count))
+    }
+
+    /// without ECN the server can only detect congestion by packet loss
+    fn enable_ecn_reception(state: &ClientState) {
+        if let Err(e) = state.socket.read().unwrap().enable_ecn_reception() {
+            log::warn!("failed to enable ECN, caused by: {}", e);
+        }
     }
 
-    fn calculate_recv_window(&self, download_buffer: &mut BufWriter<File>) -> usize{
-        let capacity = MB_1;
-        let receive_window: usize;
-        let bytes_buffered = download_buffer.buffer().len();
-        if capacity - bytes_buffered < MAX_PACKET_SIZE {
-            download_buffer.flush().expect("Unable to flush data from writer buffer");
-            receive_window = capacity / MAX_PACKET_SIZE;
+    /// take a RTT sample if the DATA packet echoes an ACK token for the first time
+    ///
+    /// servers that do not echo tokens only provide the initial sample between ACK 0 and DATA 0
+    fn sample_rtt(&self, data: &DataPacket) {
+        let rtt_sample = if data.echo_token() != 0 {
+            self.rtt_sampler.lock().unwrap().on_echo(data.echo_token())
+        } else if self.state.sequence_nr.load(SeqCst) == 0 && !self.state.rtt_estimator.load(SeqCst).has_samples() {
+            self.initial_ack.load(SeqCst).map(|instant| instant.elapsed())
         } else {
-            receive_window = (capacity - bytes_buffered) / MAX_PACKET_SIZE;
+            None
+        };
+        if let Some(rtt_sample) = rtt_sample {
+            self.apply_rtt_sample(rtt_sample);
+            log::trace!("RTT sample: {:?}, smoothed RTT: {:?}", rtt_sample, self.state.rtt_estimator.load(SeqCst).smoothed_rtt());
         }
-        return max(receive_window, RECEIVE_WINDOW_THRESH);
+    }
+
+    /// update the rtt estimation and the socket timeout
+    fn apply_rtt_sample(&self, rtt_sample: Duration) {
+        let mut rtt_estimator = self.state.rtt_estimator.load(SeqCst);
+        rtt_estimator.apply_sample(rtt_sample);
+        self.state.rtt_estimator.store(rtt_estimator, SeqCst);
+        self.state.socket.read().unwrap().set_read_timeout(Some(self.ack_retransmission_timeout())).unwrap();
+    }
+
+    /// never shorter than the RTO of the rtt estimation
+    fn ack_retransmission_timeout(&self) -> Duration {
+        let rtt_estimator = self.state.rtt_estimator.load(SeqCst);
+        max(ack_packet_retransmission_timeout(rtt_estimator.smoothed_rtt()), rtt_estimator.rto())
+    }
+
+    /// free space of the disk writer queue in packets
+    ///
+    /// 0 if the disk can not keep up with the received data
+    ///
+    /// packets in the reorder buffer are part of the window, because the window starts at the acknowledged packet
+    fn calculate_recv_window(&self, disk_writer: &DiskWriter) -> usize {
+        MB_1.saturating_sub(disk_writer.pending_bytes()) / MAX_PACKET_SIZE
     }
 }
@@ -1,7 +1,7 @@
 use atomic::Atomic;
 use soft_shared_lib::field_types::Checksum;
 use soft_shared_lib::general::loss_simulation_udp_socket::LossSimulationUdpSocket;
-use std::time::Duration;
+use soft_shared_lib::rtt_estimator::RttEstimator;
 use std::sync::RwLock;
 
 pub struct ClientState {
@@ -15,7 +15,7 @@ pub struct ClientState {
     pub filesize: Atomic<u64>,
     // Describes if the file has changed during download resumption.
     pub file_changed: Atomic<bool>,
-    pub rtt: Atomic<Option<Duration>>,
+    pub rtt_estimator: Atomic<RttEstimator>,
 }
 
 impl ClientState {
@@ -29,7 +29,7 @@ impl ClientState {
             checksum: Atomic::new(None),
             filesize: Atomic::new(0),
             file_changed: Atomic::new(false),
-            rtt: Atomic::new(None),
+            rtt_estimator: Atomic::new(RttEstimator::new()),
         }
     }
 }
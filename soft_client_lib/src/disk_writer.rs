@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::thread::{self, JoinHandle};
+
+const WRITE_BUFFER_SIZE: usize = 2usize.pow(16);
+
+/// # Disk Writer
+///
+/// writes the received data in a background thread, so a slow disk does not block the receive loop
+///
+/// the number of pending bytes that have not been written yet determines the receive window
+pub struct DiskWriter {
+    /// None after finish
+    sender: Option<Sender<Vec<u8>>>,
+    pending_bytes: Arc<AtomicUsize>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl DiskWriter {
+
+    /// data is appended to the file
+    pub fn new(file: File) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let pending_bytes = Arc::new(AtomicUsize::new(0));
+        let thread_pending_bytes = pending_bytes.clone();
+        let handle = thread::spawn(move || Self::run(file, receiver, thread_pending_bytes));
+        Self {
+            sender: Some(sender),
+            pending_bytes,
+            handle: Some(handle),
+        }
+    }
+
+    fn run(file: File, receiver: Receiver<Vec<u8>>, pending_bytes: Arc<AtomicUsize>) -> io::Result<()> {
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+        while let Ok(data) = receiver.recv() {
+            writer.write_all(&data)?;
+            pending_bytes.fetch_sub(data.len(), SeqCst);
+        }
+        writer.flush()
+    }
+
+    /// queue data for writing
+    ///
+    /// write errors are returned by finish
+    pub fn write(&self, data: &[u8]) {
+        self.pending_bytes.fetch_add(data.len(), SeqCst);
+        if let Some(sender) = &self.sender {
+            // fails only if the writer thread stopped because of an error
+            let _ = sender.send(data.to_vec());
+        }
+    }
+
+    /// number of queued bytes that have not been written yet
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes.load(SeqCst)
+    }
+
+    /// wait until all queued data is written and flushed
+    pub fn finish(&mut self) -> io::Result<()> {
+        drop(self.sender.take());
+        match self.handle.take() {
+            Some(handle) => handle.join().expect("disk writer thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DiskWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disk_writer::DiskWriter;
+    use tempdir::TempDir;
+    use std::fs::{File, OpenOptions};
+
+    #[test]
+    fn write_all() {
+        let dir = TempDir::new("soft_test").unwrap();
+        let path = dir.path().join("download");
+        File::create(&path).unwrap();
+        let mut disk_writer = DiskWriter::new(OpenOptions::new().append(true).open(&path).unwrap());
+        disk_writer.write(b"hello ");
+        disk_writer.write(b"world");
+        disk_writer.finish().unwrap();
+        assert_eq!(disk_writer.pending_bytes(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+    }
+}
@@ -1,5 +1,8 @@
 pub mod client;
 pub mod client_state;
+mod rtt_sampler;
+mod disk_writer;
+mod reorder_buffer;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+use soft_shared_lib::field_types::SequenceNumber;
+
+/// # Reorder Buffer
+///
+/// stores DATA packets that are received ahead of the next expected packet,
+/// so the server only has to retransmit the lost packets
+pub struct ReorderBuffer {
+    packets: BTreeMap<SequenceNumber, Vec<u8>>,
+    /// sum of the data sizes of all stored packets
+    bytes: usize,
+}
+
+impl ReorderBuffer {
+
+    pub fn new() -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            bytes: 0,
+        }
+    }
+
+    /// duplicates are ignored
+    pub fn insert(&mut self, sequence_number: SequenceNumber, data: &[u8]) {
+        if !self.packets.contains_key(&sequence_number) {
+            self.bytes += data.len();
+            self.packets.insert(sequence_number, data.to_vec());
+        }
+    }
+
+    /// remove the data of the packet if it has been received
+    pub fn pop(&mut self, sequence_number: SequenceNumber) -> Option<Vec<u8>> {
+        let data = self.packets.remove(&sequence_number)?;
+        self.bytes -= data.len();
+        Some(data)
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reorder_buffer::ReorderBuffer;
+
+    #[test]
+    fn reorder() {
+        let mut reorder_buffer = ReorderBuffer::new();
+        reorder_buffer.insert(3, b"world");
+        reorder_buffer.insert(2, b"hello ");
+        reorder_buffer.insert(3, b"world");
+        assert_eq!(reorder_buffer.bytes(), 11);
+        assert_eq!(reorder_buffer.pop(1), None);
+        assert_eq!(reorder_buffer.pop(2).unwrap(), b"hello ");
+        assert_eq!(reorder_buffer.pop(3).unwrap(), b"world");
+        assert_eq!(reorder_buffer.bytes(), 0);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use soft_shared_lib::field_types::AckToken;
+
+/// number of sent ACK tokens to remember
+const MAX_PENDING_TOKENS: usize = 64;
+
+/// # RTT Sampler
+///
+/// every ACK packet carries a new token and the server echoes the token of the latest received ACK in its DATA packets
+///
+/// the sampler remembers when the latest tokens were sent,
+/// the first DATA packet that echoes a token results in an RTT sample
+pub struct RttSampler {
+    last_token: AckToken,
+    /// tokens of sent ACK packets that have not been echoed yet, oldest at the front
+    pending: VecDeque<(AckToken, Instant)>,
+}
+
+impl RttSampler {
+
+    pub fn new() -> Self {
+        Self {
+            last_token: 0,
+            pending: VecDeque::with_capacity(MAX_PENDING_TOKENS),
+        }
+    }
+
+    /// token for the next sent ACK packet
+    ///
+    /// never 0
+    pub fn next_token(&mut self) -> AckToken {
+        self.last_token = self.last_token.checked_add(1).unwrap_or(1);
+        if self.pending.len() == MAX_PENDING_TOKENS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((self.last_token, Instant::now()));
+        self.last_token
+    }
+
+    /// the RTT sample if the token is echoed for the first time
+    ///
+    /// older tokens are dropped, because their ACKs have been overtaken
+    pub fn on_echo(&mut self, token: AckToken) -> Option<Duration> {
+        let position = self.pending.iter().position(|(t, _)| *t == token)?;
+        let (_, send_instant) = self.pending[position];
+        self.pending.drain(..=position);
+        Some(send_instant.elapsed())
+    }
+
+    /// forget all sent tokens
+    ///
+    /// e.g. after a migration, because the echoes would mix both paths
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtt_sampler::RttSampler;
+
+    #[test]
+    fn sample_once() {
+        let mut sampler = RttSampler::new();
+        let first = sampler.next_token();
+        let second = sampler.next_token();
+        assert_ne!(first, 0);
+        assert!(sampler.on_echo(second).is_some());
+        // echoed again in the next DATA packet
+        assert!(sampler.on_echo(second).is_none());
+        // overtaken by the second token
+        assert!(sampler.on_echo(first).is_none());
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+    Allow,
+    Deny,
+}
+
+/// an IPv4 or IPv6 address range, e.g. `10.1.0.0/16`
+///
+/// IPv4 ranges also contain the IPv4-mapped IPv6 addresses of a dual stack socket
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, String> {
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(format!("prefix length {} of {} is too long", prefix_len, addr));
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            IpAddr::V4(_) => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// a single address is a network with the full prefix length
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid address {}", addr))?;
+        let prefix_len = match (prefix_len, addr) {
+            (Some(prefix_len), _) => prefix_len.parse().map_err(|_| format!("invalid prefix length {}", prefix_len))?,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+        Self::new(addr, prefix_len)
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// allows or denies the files matching the path glob, for all clients or only for clients in the network
+///
+/// path globs are matched against the requested file name:
+/// `*` matches any characters except `/`, `**` matches any number of directories and `?` matches one character
+///
+/// written as `allow|deny PATH_GLOB [NETWORK]`, e.g. `allow lab/** 10.1.0.0/16`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    access: Access,
+    path_glob: String,
+    /// None for all clients
+    network: Option<IpNetwork>,
+}
+
+impl AccessRule {
+
+    pub fn new(access: Access, path_glob: &str, network: Option<IpNetwork>) -> Self {
+        Self { access, path_glob: path_glob.trim_matches('/').to_string(), network }
+    }
+
+    pub fn allow(path_glob: &str, network: Option<IpNetwork>) -> Self {
+        Self::new(Access::Allow, path_glob, network)
+    }
+
+    pub fn deny(path_glob: &str, network: Option<IpNetwork>) -> Self {
+        Self::new(Access::Deny, path_glob, network)
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    /// true if the rule applies to the request
+    pub fn matches(&self, file_name: &str, addr: IpAddr) -> bool {
+        let in_network = self.network.is_none_or(|network| network.contains(addr));
+        in_network && glob_matches(self.path_glob.as_bytes(), normalize(file_name).as_bytes())
+    }
+}
+
+impl FromStr for AccessRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let access = match words.next() {
+            Some("allow") => Access::Allow,
+            Some("deny") => Access::Deny,
+            _ => return Err(format!("access rule {:?} does not start with allow or deny", s)),
+        };
+        let path_glob = words.next().ok_or_else(|| format!("access rule {:?} has no path", s))?;
+        let network = words.next().map(IpNetwork::from_str).transpose()?;
+        if words.next().is_some() {
+            return Err(format!("access rule {:?} has too many fields", s));
+        }
+        Ok(Self::new(access, path_glob, network))
+    }
+}
+
+impl Display for AccessRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            Access::Allow => "allow",
+            Access::Deny => "deny",
+        };
+        match &self.network {
+            Some(network) => write!(f, "{} {} {}", access, self.path_glob, network),
+            None => write!(f, "{} {}", access, self.path_glob),
+        }
+    }
+}
+
+/// the access of the first matching rule, requests that match no rule are allowed
+pub fn check_access(rules: &[AccessRule], file_name: &str, addr: IpAddr) -> Access {
+    rules.iter()
+        .find(|rule| rule.matches(file_name, addr))
+        .map_or(Access::Allow, AccessRule::access)
+}
+
+/// equal file names, e.g. `lab//file.bin` and `lab/file.bin`, must match the same rules
+fn normalize(file_name: &str) -> String {
+    let names: Vec<_> = Path::new(file_name).components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    names.join("/")
+}
+
+/// the matches are memoized by the positions in the glob and the name,
+/// so the wildcards of a glob do not backtrack exponentially over the requested name
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    let mut failed = vec![false; (glob.len() + 1) * (name.len() + 1)];
+    glob_matches_at(glob, name, 0, 0, &mut failed)
+}
+
+/// true if the glob from position g matches the name from position n
+fn glob_matches_at(glob: &[u8], name: &[u8], g: usize, n: usize, failed: &mut [bool]) -> bool {
+    let state = g * (name.len() + 1) + n;
+    if failed[state] {
+        return false;
+    }
+    let rest = &name[n..];
+    let matches = match &glob[g..] {
+        [] => rest.is_empty(),
+        // `**/` also matches no directory
+        [b'*', b'*', b'/', ..] => {
+            glob_matches_at(glob, name, g + 3, n, failed) || (0..rest.len())
+                .any(|i| rest[i] == b'/' && glob_matches_at(glob, name, g + 3, n + i + 1, failed))
+        }
+        [b'*', b'*', ..] => (n..=name.len()).any(|i| glob_matches_at(glob, name, g + 2, i, failed)),
+        [b'*', ..] => {
+            let component_len = rest.iter().position(|c| *c == b'/').unwrap_or(rest.len());
+            (0..=component_len).any(|i| glob_matches_at(glob, name, g + 1, n + i, failed))
+        }
+        [b'?', ..] => !rest.is_empty() && rest[0] != b'/' && glob_matches_at(glob, name, g + 1, n + 1, failed),
+        [c, ..] => rest.first() == Some(c) && glob_matches_at(glob, name, g + 1, n + 1, failed),
+    };
+    if !matches {
+        failed[state] = true;
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use test_case::test_case;
+    use crate::access_control::{check_access, glob_matches, Access, AccessRule, IpNetwork};
+
+    #[test_case("*.bin", "file.bin", true; "star")]
+    #[test_case("*.bin", "dir/file.bin", false; "star not in subdirectory")]
+    #[test_case("lab/*", "lab/file.bin", true; "star in directory")]
+    #[test_case("lab/*", "lab/dir/file.bin", false; "star not below directory")]
+    #[test_case("lab/**", "lab/dir/file.bin", true; "double star below directory")]
+    #[test_case("lab/**", "laboratory/file.bin", false; "double star other directory")]
+    #[test_case("**/*.bin", "file.bin", true; "double star no directory")]
+    #[test_case("**/*.bin", "a/b/file.bin", true; "double star directories")]
+    #[test_case("**/secret/**", "a/secret/file.bin", true; "double star around directory")]
+    #[test_case("file?.bin", "file1.bin", true; "question mark")]
+    #[test_case("file?.bin", "file/.bin", false; "question mark not slash")]
+    #[test_case("**", "any/file.bin", true; "double star all")]
+    fn globs(glob: &str, name: &str, expected: bool) {
+        assert_eq!(glob_matches(glob.as_bytes(), name.as_bytes()), expected);
+    }
+
+    #[test]
+    fn many_wildcards() {
+        // backtracking over every split of the name would not finish
+        let glob = "**a".repeat(20) + "b";
+        let name = "a".repeat(480);
+        assert!(!glob_matches(glob.as_bytes(), name.as_bytes()));
+        assert!(glob_matches(glob.as_bytes(), (name + "b").as_bytes()));
+    }
+
+    #[test_case("10.1.0.0/16", "10.1.2.3", true; "ipv4 in network")]
+    #[test_case("10.1.0.0/16", "10.2.0.1", false; "ipv4 not in network")]
+    #[test_case("10.1.0.0/16", "::ffff:10.1.2.3", true; "ipv4 mapped")]
+    #[test_case("10.1.2.3", "10.1.2.3", true; "ipv4 address")]
+    #[test_case("10.1.2.3", "10.1.2.4", false; "ipv4 other address")]
+    #[test_case("0.0.0.0/0", "192.168.0.1", true; "ipv4 all")]
+    #[test_case("fd00::/8", "fd12::1", true; "ipv6 in network")]
+    #[test_case("fd00::/8", "fe80::1", false; "ipv6 not in network")]
+    #[test_case("fd00::/8", "10.1.2.3", false; "ipv6 network ipv4 address")]
+    fn networks(network: &str, addr: &str, expected: bool) {
+        let network: IpNetwork = network.parse().unwrap();
+        assert_eq!(network.contains(addr.parse().unwrap()), expected);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: AccessRule = "allow lab/** 10.1.0.0/16".parse().unwrap();
+        assert_eq!(rule, AccessRule::allow("lab/**", Some("10.1.0.0/16".parse().unwrap())));
+        assert_eq!(rule.to_string(), "allow lab/** 10.1.0.0/16");
+        assert_eq!("deny  *.key ".parse::<AccessRule>().unwrap(), AccessRule::deny("*.key", None));
+        assert!("permit lab/**".parse::<AccessRule>().is_err());
+        assert!("deny".parse::<AccessRule>().is_err());
+        assert!("deny lab/** 10.1.0.0/33".parse::<AccessRule>().is_err());
+        assert!("deny lab/** 10.1.0.0/16 extra".parse::<AccessRule>().is_err());
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules = vec![
+            "allow lab/** 10.1.0.0/16".parse().unwrap(),
+            "deny lab/**".parse().unwrap(),
+        ];
+        let lab: IpAddr = "10.1.2.3".parse().unwrap();
+        let other: IpAddr = "192.168.0.1".parse().unwrap();
+        assert_eq!(check_access(&rules, "lab/data.bin", lab), Access::Allow);
+        assert_eq!(check_access(&rules, "lab/data.bin", other), Access::Deny);
+        assert_eq!(check_access(&rules, "lab//data.bin", other), Access::Deny);
+        assert_eq!(check_access(&rules, "public/data.bin", other), Access::Allow);
+        assert_eq!(check_access(&[], "lab/data.bin", other), Access::Allow);
+    }
+}
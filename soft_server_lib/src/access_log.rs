@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use soft_shared_lib::field_types::ConnectionId;
+use soft_shared_lib::soft_error_code::SoftErrorCode;
+use crate::observer::{ServerObserver, TransferInfo};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum AccessLogFormat {
+    /// like the common log format of web servers, followed by the fields of the transfer:
+    ///
+    /// `CLIENT - - [TIME] "REQ FILE OFFSET" OUTCOME BYTES_SENT DURATION RETRANSMISSIONS MIGRATED_ADDRESSES`
+    ///
+    /// rejected requests are logged with the outcome `rejected:ERROR_CODE` and without a transfer
+    #[default]
+    Common,
+    /// one JSON object per line
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "common" => Ok(Self::Common),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown access log format {:?}", s)),
+        }
+    }
+}
+
+/// how a connection has been closed, or that the request has not been accepted
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Outcome {
+    Finished,
+    TimedOut,
+    Failed(SoftErrorCode),
+    Rejected(SoftErrorCode),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Finished => "finished",
+            Outcome::TimedOut => "timed_out",
+            Outcome::Failed(_) => "failed",
+            Outcome::Rejected(_) => "rejected",
+        }
+    }
+
+    fn error_code(&self) -> Option<SoftErrorCode> {
+        match self {
+            Outcome::Failed(error_code) | Outcome::Rejected(error_code) => Some(*error_code),
+            _ => None,
+        }
+    }
+}
+
+/// the work of the writer thread
+enum Command {
+    Write(String),
+    /// answered when the file has been opened again
+    Reopen(Sender<io::Result<()>>),
+}
+
+/// a connection that has not been closed yet
+struct OpenTransfer {
+    started: SystemTime,
+    start_instant: Instant,
+    /// the first address and the addresses the client migrated to
+    addresses: Vec<SocketAddr>,
+}
+
+/// # Access Log
+///
+/// writes one line per closed connection, see AccessLogFormat
+///
+/// the log is an observer of the server, see ServerConfig::access_log_path
+///
+/// the lines are written by an own thread, so the tasks of the server are not blocked by the file system,
+/// lines that are still queued are written when the log is dropped
+pub struct AccessLog {
+    format: AccessLogFormat,
+    transfers: Mutex<HashMap<ConnectionId, OpenTransfer>>,
+    /// None once the log is dropped
+    commands: Option<Sender<Command>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AccessLog {
+
+    /// appends to the file
+    pub fn open(path: &Path, format: AccessLogFormat) -> io::Result<Self> {
+        let file = Self::open_file(path)?;
+        let (commands, receiver) = mpsc::channel();
+        let path = path.to_path_buf();
+        let writer = thread::Builder::new()
+            .name(String::from("soft-access-log"))
+            .spawn(move || Self::write_lines(path, file, receiver))?;
+        Ok(Self {
+            format,
+            transfers: Mutex::new(HashMap::new()),
+            commands: Some(commands),
+            writer: Some(writer),
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// runs until the log is dropped
+    fn write_lines(path: PathBuf, mut file: File, receiver: Receiver<Command>) {
+        for command in receiver {
+            match command {
+                // the whole line is written at once, so lines of concurrent connections are not interleaved
+                Command::Write(line) => if let Err(e) = file.write_all(line.as_bytes()) {
+                    log::warn!("failed to write access log {:?}, caused by: {}", path, e);
+                },
+                Command::Reopen(result) => {
+                    let reopened = Self::open_file(&path).map(|reopened| file = reopened);
+                    let _ = result.send(reopened);
+                }
+            }
+        }
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            // the writer only stops when the log is dropped
+            let _ = commands.send(command);
+        }
+    }
+
+    /// open the file again, e.g. after it has been rotated
+    ///
+    /// returns after the lines of the closed connections have been written to the previous file
+    pub fn reopen(&self) -> io::Result<()> {
+        let (result, reopened) = mpsc::channel();
+        self.send(Command::Reopen(result));
+        reopened.recv().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::BrokenPipe, "access log writer stopped")))
+    }
+
+    fn on_closed(&self, transfer: &TransferInfo, outcome: Outcome) {
+        let open_transfer = self.transfers.lock().unwrap().remove(&transfer.connection_id);
+        let open_transfer = match open_transfer {
+            Some(open_transfer) => open_transfer,
+            // accepted before the log has been opened
+            None => return,
+        };
+        self.write(transfer, &open_transfer, outcome);
+    }
+
+    fn write(&self, transfer: &TransferInfo, open_transfer: &OpenTransfer, outcome: Outcome) {
+        let line = match self.format {
+            AccessLogFormat::Common => Self::common_line(transfer, open_transfer, outcome),
+            AccessLogFormat::Json => Self::json_line(transfer, open_transfer, outcome),
+        };
+        self.send(Command::Write(line));
+    }
+
+    fn common_line(transfer: &TransferInfo, open_transfer: &OpenTransfer, outcome: Outcome) -> String {
+        let migrated_addresses: Vec<String> = open_transfer.addresses[1..].iter().map(SocketAddr::to_string).collect();
+        let outcome = match outcome.error_code() {
+            Some(error_code) => format!("{}:{}", outcome.name(), error_code),
+            None => outcome.name().to_string(),
+        };
+        format!(
+            "{} - - [{}] \"REQ {} {}\" {} {} {:.3} {} {}\n",
+            open_transfer.addresses[0],
+            common_time(open_transfer.started),
+            escape(&transfer.file_name),
+            transfer.offset,
+            outcome,
+            transfer.bytes_sent,
+            open_transfer.start_instant.elapsed().as_secs_f64(),
+            transfer.retransmissions,
+            if migrated_addresses.is_empty() { String::from("-") } else { migrated_addresses.join(",") },
+        )
+    }
+
+    fn json_line(transfer: &TransferInfo, open_transfer: &OpenTransfer, outcome: Outcome) -> String {
+        let addresses: Vec<String> = open_transfer.addresses.iter().map(|addr| format!("\"{}\"", addr)).collect();
+        // rejected requests have neither a connection nor a known file size
+        let (connection_id, file_size) = match outcome {
+            Outcome::Rejected(_) => (String::from("null"), String::from("null")),
+            _ => (transfer.connection_id.to_string(), transfer.file_size.to_string()),
+        };
+        let mut line = format!(
+            "{{\"time\":\"{}\",\"connection_id\":{},\"addresses\":[{}],\"file\":\"{}\",\"offset\":{},\"file_size\":{},\"bytes_sent\":{},\"bytes_acknowledged\":{},\"duration\":{:.3},\"retransmissions\":{},\"outcome\":\"{}\"",
+            rfc3339_time(open_transfer.started),
+            connection_id,
+            addresses.join(","),
+            escape(&transfer.file_name),
+            transfer.offset,
+            file_size,
+            transfer.bytes_sent,
+            transfer.bytes_acknowledged,
+            open_transfer.start_instant.elapsed().as_secs_f64(),
+            transfer.retransmissions,
+            outcome.name(),
+        );
+        if let Some(error_code) = outcome.error_code() {
+            let _ = write!(line, ",\"error\":\"{}\"", error_code);
+        }
+        line.push_str("}\n");
+        line
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        // the writer stops after the queued lines when the channel is closed
+        self.commands.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl ServerObserver for AccessLog {
+
+    fn on_rejected(&self, peer: SocketAddr, file_name: &str, offset: u64, error_code: SoftErrorCode) {
+        let transfer = TransferInfo {
+            connection_id: 0,
+            peer,
+            file_name: file_name.to_string(),
+            file_size: 0,
+            offset,
+            bytes_sent: 0,
+            retransmissions: 0,
+            bytes_acknowledged: 0,
+        };
+        let request = OpenTransfer { started: SystemTime::now(), start_instant: Instant::now(), addresses: vec![peer] };
+        self.write(&transfer, &request, Outcome::Rejected(error_code));
+    }
+
+    fn on_accepted(&self, transfer: &TransferInfo) {
+        self.transfers.lock().unwrap().insert(transfer.connection_id, OpenTransfer {
+            started: SystemTime::now(),
+            start_instant: Instant::now(),
+            addresses: vec![transfer.peer],
+        });
+    }
+
+    fn on_migrated(&self, transfer: &TransferInfo, _previous_peer: SocketAddr) {
+        if let Some(open_transfer) = self.transfers.lock().unwrap().get_mut(&transfer.connection_id) {
+            open_transfer.addresses.push(transfer.peer);
+        }
+    }
+
+    fn on_finished(&self, transfer: &TransferInfo) {
+        self.on_closed(transfer, Outcome::Finished);
+    }
+
+    fn on_timed_out(&self, transfer: &TransferInfo) {
+        self.on_closed(transfer, Outcome::TimedOut);
+    }
+
+    fn on_failed(&self, transfer: &TransferInfo, error_code: SoftErrorCode) {
+        self.on_closed(transfer, Outcome::Failed(error_code));
+    }
+}
+
+/// file names are requested by the clients, so they must not break the line or the quoted field
+///
+/// the escapes are valid in JSON strings
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => { let _ = write!(escaped, "\\u{:04x}", c as u32); }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// year, month, day, hour, minute, second and millisecond in UTC
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86400) as i64;
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60, since_epoch.subsec_millis())
+}
+
+/// e.g. `19/Oct/2026:03:20:26 +0000`
+fn common_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second, _) = utc(time);
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", day, MONTHS[month as usize - 1], year, hour, minute, second)
+}
+
+/// e.g. `2026-10-19T03:20:26.153Z`
+fn rfc3339_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second, millis) = utc(time);
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, hour, minute, second, millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempdir::TempDir;
+    use soft_shared_lib::soft_error_code::SoftErrorCode;
+    use crate::access_log::{common_time, escape, rfc3339_time, AccessLog, AccessLogFormat};
+    use crate::observer::{ServerObserver, TransferInfo};
+
+    fn transfer_info(connection_id: u32, peer: &str) -> TransferInfo {
+        TransferInfo {
+            connection_id,
+            peer: peer.parse().unwrap(),
+            file_name: String::from("lab/\"data\".bin"),
+            file_size: 100,
+            offset: 10,
+            bytes_sent: 120,
+            retransmissions: 2,
+            bytes_acknowledged: 90,
+        }
+    }
+
+    #[test]
+    fn times() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_380_026_153);
+        assert_eq!(common_time(time), "19/Oct/2026:03:20:26 +0000");
+        assert_eq!(rfc3339_time(time), "2026-10-19T03:20:26.153Z");
+        assert_eq!(rfc3339_time(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\u000ad");
+    }
+
+    #[test]
+    fn lines() {
+        let dir = TempDir::new("soft_test").unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(&path, AccessLogFormat::Common).unwrap();
+        log.on_accepted(&transfer_info(1, "10.1.2.3:5000"));
+        log.on_migrated(&transfer_info(1, "10.1.2.4:6000"), "10.1.2.3:5000".parse().unwrap());
+        log.on_finished(&transfer_info(1, "10.1.2.4:6000"));
+        log.on_accepted(&transfer_info(2, "10.1.2.3:5001"));
+        log.on_failed(&transfer_info(2, "10.1.2.3:5001"), SoftErrorCode::FileChanged);
+        // not accepted
+        log.on_timed_out(&transfer_info(3, "10.1.2.3:5002"));
+
+        log.on_rejected("10.1.2.3:5003".parse().unwrap(), "lab/secret.bin", 0, SoftErrorCode::AccessDenied);
+        // the queued lines have been written when the file is reopened
+        log.reopen().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("10.1.2.3:5000 - - ["));
+        assert!(lines[0].contains("] \"REQ lab/\\\"data\\\".bin 10\" finished 120 "));
+        assert!(lines[0].ends_with(" 2 10.1.2.4:6000"));
+        assert!(lines[1].contains(" failed:FileChanged 120 "));
+        assert!(lines[1].ends_with(" 2 -"));
+        assert!(lines[2].starts_with("10.1.2.3:5003 - - ["));
+        assert!(lines[2].ends_with("] \"REQ lab/secret.bin 0\" rejected:AccessDenied 0 0.000 0 -"));
+
+        // the rotated file is kept, new lines are written to a new file
+        let rotated_path = dir.path().join("access.log.1");
+        std::fs::rename(&path, &rotated_path).unwrap();
+        log.reopen().unwrap();
+        log.on_accepted(&transfer_info(4, "10.1.2.3:5004"));
+        log.on_finished(&transfer_info(4, "10.1.2.3:5004"));
+        drop(log);
+        assert_eq!(std::fs::read_to_string(&rotated_path).unwrap(), content);
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("10.1.2.3:5004 - - ["));
+    }
+
+    #[test]
+    fn json_lines() {
+        let dir = TempDir::new("soft_test").unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(&path, AccessLogFormat::Json).unwrap();
+        log.on_accepted(&transfer_info(4, "[fd00::1]:5000"));
+        log.on_timed_out(&transfer_info(4, "[fd00::1]:5000"));
+        log.on_rejected("[fd00::1]:5001".parse().unwrap(), "hello.txt", 20, SoftErrorCode::InvalidOffset);
+        // the queued lines are written before the log is dropped
+        drop(log);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"time\":\""));
+        assert!(lines[0].contains("\"connection_id\":4,\"addresses\":[\"[fd00::1]:5000\"],\"file\":\"lab/\\\"data\\\".bin\",\"offset\":10,\"file_size\":100,\"bytes_sent\":120,\"bytes_acknowledged\":90,"));
+        assert!(lines[0].ends_with(",\"retransmissions\":2,\"outcome\":\"timed_out\"}"));
+        assert!(lines[1].contains("\"connection_id\":null,\"addresses\":[\"[fd00::1]:5001\"],\"file\":\"hello.txt\",\"offset\":20,\"file_size\":null,"));
+        assert!(lines[1].ends_with(",\"outcome\":\"rejected\",\"error\":\"InvalidOffset\"}"));
+    }
+}
@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use flate2::{Decompress, FlushDecompress, Status};
+use futures_util::future::{BoxFuture, FutureExt};
+use log::{debug, warn};
+use tokio::sync::Mutex;
+use crate::file_source::{not_ready, FileIdentity, FileSource, SourceFile};
+
+const TAR_BLOCK_SIZE: u64 = 512;
+/// long names and pax extended headers are small, larger ones are rejected instead of allocated
+const TAR_MAX_EXTENSION_SIZE: u64 = 8 * 1024;
+const ZIP_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP_CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP_LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const ZIP_LOCAL_HEADER_SIZE: usize = 30;
+const ZIP_MAX_COMMENT_SIZE: usize = u16::MAX as usize;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+const INFLATE_BUFFER_SIZE: usize = 2usize.pow(16);
+/// requests for members are retried after this time while the index of their archive is read
+const INDEX_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// the file name extensions of the supported archives
+const ARCHIVE_EXTENSIONS: [&str; 2] = [".tar", ".zip"];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Compression {
+    Stored,
+    /// raw deflate stream of that size
+    Deflated(u64),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ArchiveEntry {
+    /// the offset of the data in a tar archive, the offset of the local header in a zip archive
+    offset: u64,
+    /// the uncompressed size
+    size: u64,
+    compression: Compression,
+    /// the data follows the local header, whose size is only known after reading it
+    local_header: bool,
+}
+
+type ArchiveIndex = HashMap<String, ArchiveEntry>;
+
+enum IndexState {
+    /// the index is read in its own task
+    Reading,
+    Ready(Arc<ArchiveIndex>),
+    /// the archive is invalid, the error is returned until the archive is modified
+    Failed(io::ErrorKind, String),
+}
+
+/// # Archive File Source
+///
+/// serves the members of tar and zip archives of another source without extracting them,
+/// e.g. `inner/file.bin` of `bundle.tar` is requested as `bundle.tar/inner/file.bin`
+///
+/// other files are served by the inner source, files take precedence over members of the same name
+///
+/// members of tar archives and stored members of zip archives are read directly from the archive,
+/// deflated members of zip archives are inflated while reading,
+/// so resuming them inflates the member up to the offset once
+///
+/// the identity of a member changes with the identity of its archive,
+/// so each member has its own checksum, which is discarded when the archive is modified
+///
+/// the index of an archive is read in its own task, because large archives take a while,
+/// members are NotReady until then
+pub struct ArchiveFileSource {
+    inner: Arc<dyn FileSource>,
+    /// the member index by archive name, valid as long as the archive has that identity
+    indexes: Arc<std::sync::Mutex<HashMap<String, (FileIdentity, IndexState)>>>,
+}
+
+impl ArchiveFileSource {
+
+    pub fn new(inner: Arc<dyn FileSource>) -> Self {
+        Self {
+            inner,
+            indexes: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// the opened archive and the entry of the member
+    async fn open_member(&self, file_name: &str) -> io::Result<(Arc<dyn SourceFile>, FileIdentity, ArchiveEntry)> {
+        for (archive_name, member_name) in archive_paths(file_name) {
+            let archive: Arc<dyn SourceFile> = match self.inner.open(archive_name).await {
+                Ok(archive) => Arc::from(archive),
+                Err(e) if is_not_found(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            let archive_identity = archive.identity().await?;
+            let index = self.index(archive_name, archive.clone(), archive_identity)?;
+            return match index.get(member_name) {
+                Some(entry) => Ok((archive, archive_identity, *entry)),
+                None => Err(not_found()),
+            };
+        }
+        Err(not_found())
+    }
+
+    /// the cached index, the archive is only read again if it has been modified
+    ///
+    /// NotReady while the index is read
+    fn index(&self, archive_name: &str, archive: Arc<dyn SourceFile>, archive_identity: FileIdentity) -> io::Result<Arc<ArchiveIndex>> {
+        let mut indexes = self.indexes.lock().unwrap();
+        match indexes.get(archive_name) {
+            Some((identity, IndexState::Reading)) if *identity == archive_identity => return Err(not_ready(INDEX_RETRY_DELAY)),
+            Some((identity, IndexState::Ready(index))) if *identity == archive_identity => return Ok(index.clone()),
+            Some((identity, IndexState::Failed(kind, message))) if *identity == archive_identity => return Err(io::Error::new(*kind, message.clone())),
+            _ => {}
+        }
+        indexes.insert(archive_name.to_string(), (archive_identity, IndexState::Reading));
+        let indexes = self.indexes.clone();
+        let archive_name = archive_name.to_string();
+        tokio::spawn(async move {
+            let index = if archive_name.to_lowercase().ends_with(".zip") {
+                read_zip_index(archive.as_ref(), archive_identity.size()).await
+            } else {
+                read_tar_index(archive.as_ref()).await
+            };
+            let state = match index {
+                Ok(index) => {
+                    debug!("indexed {} members of {}", index.len(), archive_name);
+                    IndexState::Ready(Arc::new(index))
+                }
+                Err(e) => {
+                    warn!("failed to index archive {}, caused by: {}", archive_name, e);
+                    IndexState::Failed(e.kind(), e.to_string())
+                }
+            };
+            let mut indexes = indexes.lock().unwrap();
+            // a newer version of the archive might be read in the meantime
+            if let Some((identity, reading @ IndexState::Reading)) = indexes.get_mut(&archive_name) {
+                if *identity == archive_identity {
+                    *reading = state;
+                }
+            }
+        });
+        Err(not_ready(INDEX_RETRY_DELAY))
+    }
+}
+
+impl FileSource for ArchiveFileSource {
+
+    fn open<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<Box<dyn SourceFile>>> {
+        async move {
+            match self.inner.open(file_name).await {
+                Err(e) if is_not_found(&e) => {}
+                result => return result,
+            }
+            let (archive, _, entry) = self.open_member(file_name).await?;
+            let offset = match entry.local_header {
+                true => zip_data_offset(archive.as_ref(), entry.offset).await?,
+                false => entry.offset,
+            };
+            let inflater = match entry.compression {
+                Compression::Stored => None,
+                Compression::Deflated(compressed_size) => Some(Mutex::new(Inflater::new(compressed_size))),
+            };
+            let member: Box<dyn SourceFile> = Box::new(ArchiveMember { archive, offset, size: entry.size, inflater });
+            Ok(member)
+        }.boxed()
+    }
+
+    fn stat<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<FileIdentity>> {
+        async move {
+            match self.inner.stat(file_name).await {
+                Err(e) if is_not_found(&e) => {}
+                result => return result,
+            }
+            let (_, archive_identity, entry) = self.open_member(file_name).await?;
+            Ok(member_identity(archive_identity, entry.size))
+        }.boxed()
+    }
+}
+
+/// the possible archive and member names of a file name, e.g. `bundle.tar` and `inner/file.bin`
+pub(crate) fn archive_paths(file_name: &str) -> impl Iterator<Item = (&str, &str)> {
+    file_name.match_indices('/').filter_map(move |(i, _)| {
+        let archive_name = &file_name[..i];
+        let lowercase = archive_name.to_lowercase();
+        if ARCHIVE_EXTENSIONS.iter().any(|extension| lowercase.ends_with(extension)) {
+            Some((archive_name, &file_name[i + 1..]))
+        } else {
+            None
+        }
+    })
+}
+
+/// the identity of a member, it changes with the identity of the archive
+pub(crate) fn member_identity(archive_identity: FileIdentity, size: u64) -> FileIdentity {
+    FileIdentity::new(size, archive_identity.mtime(), archive_identity.mtime_nsec(), archive_identity.inode())
+}
+
+/// paths below a file are not found as well
+fn is_not_found(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::NotADirectory
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "archive member not found")
+}
+
+fn invalid_archive(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// a member of an opened archive
+struct ArchiveMember {
+    archive: Arc<dyn SourceFile>,
+    /// the offset of the data in the archive
+    offset: u64,
+    size: u64,
+    /// None if the member is stored
+    inflater: Option<Mutex<Inflater>>,
+}
+
+impl SourceFile for ArchiveMember {
+
+    fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>> {
+        async move {
+            Ok(member_identity(self.archive.identity().await?, self.size))
+        }.boxed()
+    }
+
+    fn read_at<'a>(&'a self, buf: &'a mut [u8], offset: u64) -> BoxFuture<'a, io::Result<usize>> {
+        async move {
+            if offset >= self.size {
+                return Ok(0);
+            }
+            let size = buf.len().min((self.size - offset) as usize);
+            match &self.inflater {
+                None => self.archive.read_at(&mut buf[..size], self.offset + offset).await,
+                Some(inflater) => inflater.lock().await.read_at(self.archive.as_ref(), self.offset, &mut buf[..size], offset).await,
+            }
+        }.boxed()
+    }
+}
+
+/// inflates a deflated member sequentially, reading at an earlier offset starts again
+struct Inflater {
+    decompress: Decompress,
+    compressed_size: u64,
+    /// compressed bytes read from the archive
+    read_offset: u64,
+    input: Vec<u8>,
+    /// the not yet inflated part of the input
+    input_start: usize,
+    /// uncompressed bytes inflated so far
+    position: u64,
+}
+
+impl Inflater {
+
+    fn new(compressed_size: u64) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            compressed_size,
+            read_offset: 0,
+            input: Vec::with_capacity(INFLATE_BUFFER_SIZE),
+            input_start: 0,
+            position: 0,
+        }
+    }
+
+    async fn read_at(&mut self, archive: &dyn SourceFile, data_offset: u64, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset < self.position {
+            *self = Self::new(self.compressed_size);
+        }
+        let mut skip_buf = vec![0u8; INFLATE_BUFFER_SIZE];
+        while self.position < offset {
+            let skip = ((offset - self.position) as usize).min(skip_buf.len());
+            self.inflate(archive, data_offset, &mut skip_buf[..skip]).await?;
+        }
+        self.inflate(archive, data_offset, buf).await
+    }
+
+    /// inflate at least one byte into buf
+    async fn inflate(&mut self, archive: &dyn SourceFile, data_offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.input_start == self.input.len() && self.read_offset < self.compressed_size {
+                let size = INFLATE_BUFFER_SIZE.min((self.compressed_size - self.read_offset) as usize);
+                self.input.resize(size, 0);
+                let size = archive.read_at(&mut self.input, data_offset + self.read_offset).await?;
+                if size == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated archive member"));
+                }
+                self.input.truncate(size);
+                self.input_start = 0;
+                self.read_offset += size as u64;
+            }
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            let status = self.decompress.decompress(&self.input[self.input_start..], buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.input_start += (self.decompress.total_in() - total_in) as usize;
+            let size = (self.decompress.total_out() - total_out) as usize;
+            self.position += size as u64;
+            if size > 0 {
+                return Ok(size);
+            }
+            if status == Status::StreamEnd || (self.input_start == self.input.len() && self.read_offset == self.compressed_size) {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated archive member"));
+            }
+        }
+    }
+}
+
+/// false if the archive ends before buf is filled
+async fn read_exact_at(archive: &dyn SourceFile, buf: &mut [u8], offset: u64) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let size = archive.read_at(&mut buf[filled..], offset + filled as u64).await?;
+        if size == 0 {
+            return Ok(false);
+        }
+        filled += size;
+    }
+    Ok(true)
+}
+
+/// regular files of ustar, GNU and pax archives, other entries are skipped
+async fn read_tar_index(archive: &dyn SourceFile) -> io::Result<ArchiveIndex> {
+    let mut index = HashMap::new();
+    let mut header = [0u8; TAR_BLOCK_SIZE as usize];
+    let mut offset = 0;
+    // set by the preceding GNU long name or pax extended header
+    let mut long_name: Option<String> = None;
+    let mut pax_size: Option<u64> = None;
+    loop {
+        if !read_exact_at(archive, &mut header, offset).await? {
+            return Err(invalid_archive("truncated tar archive"));
+        }
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        if !tar_checksum_valid(&header) {
+            return Err(invalid_archive("invalid tar header checksum"));
+        }
+        let data_offset = offset + TAR_BLOCK_SIZE;
+        let header_size = parse_tar_number(&header[124..136])?;
+        let mut size = header_size;
+        match header[156] {
+            // the global pax header and the GNU long link name are ignored
+            b'g' | b'K' => {}
+            b'L' | b'x' => {
+                if header_size > TAR_MAX_EXTENSION_SIZE {
+                    return Err(invalid_archive("tar extended header too large"));
+                }
+                let mut data = vec![0u8; header_size as usize];
+                if !read_exact_at(archive, &mut data, data_offset).await? {
+                    return Err(invalid_archive("truncated tar archive"));
+                }
+                if header[156] == b'L' {
+                    long_name = Some(tar_string(&data));
+                } else {
+                    for (key, value) in parse_pax_records(&data) {
+                        match key {
+                            "path" => long_name = Some(value.to_string()),
+                            "size" => pax_size = value.parse().ok(),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            typeflag => {
+                // the data size of a regular file
+                size = pax_size.take().unwrap_or(header_size);
+                let name = match long_name.take() {
+                    Some(name) => name,
+                    None => tar_name(&header),
+                };
+                if typeflag == b'0' || typeflag == 0 || typeflag == b'7' {
+                    let name = name.trim_start_matches("./").to_string();
+                    index.insert(name, ArchiveEntry { offset: data_offset, size, compression: Compression::Stored, local_header: false });
+                }
+            }
+        }
+        offset = size.div_ceil(TAR_BLOCK_SIZE).checked_mul(TAR_BLOCK_SIZE)
+            .and_then(|padded_size| data_offset.checked_add(padded_size))
+            .ok_or_else(|| invalid_archive("invalid tar entry size"))?;
+    }
+    Ok(index)
+}
+
+/// the checksum field counts as spaces
+fn tar_checksum_valid(header: &[u8]) -> bool {
+    let expected = match parse_tar_number(&header[148..156]) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+    let sum: u64 = header.iter().enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+        .sum();
+    sum == expected
+}
+
+/// octal, or base-256 if the highest bit is set
+fn parse_tar_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold((field[0] & 0x7f) as u64, |n, b| (n << 8) | *b as u64));
+    }
+    let digits = tar_string(field);
+    let digits = digits.trim();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| invalid_archive("invalid tar number"))
+}
+
+/// NUL terminated
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// the name, with the prefix of ustar archives
+fn tar_name(header: &[u8]) -> String {
+    let name = tar_string(&header[0..100]);
+    if &header[257..262] != b"ustar" {
+        return name;
+    }
+    let prefix = tar_string(&header[345..500]);
+    match prefix.is_empty() {
+        true => name,
+        false => format!("{}/{}", prefix, name),
+    }
+}
+
+/// `length key=value\n` records
+fn parse_pax_records(data: &[u8]) -> Vec<(&str, &str)> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while let Some(space) = rest.iter().position(|b| *b == b' ') {
+        let length: usize = match std::str::from_utf8(&rest[..space]).ok().and_then(|length| length.parse().ok()) {
+            Some(length) if length > space + 1 && length <= rest.len() => length,
+            _ => break,
+        };
+        let record = &rest[space + 1..length];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Ok(record) = std::str::from_utf8(record) {
+            if let Some((key, value)) = record.split_once('=') {
+                records.push((key, value));
+            }
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// stored and deflated files of the central directory, including zip64 archives
+///
+/// directories, encrypted files and other compression methods are skipped
+async fn read_zip_index(archive: &dyn SourceFile, archive_size: u64) -> io::Result<ArchiveIndex> {
+    let tail_size = (archive_size as usize).min(ZIP_END_OF_CENTRAL_DIRECTORY_SIZE + ZIP_MAX_COMMENT_SIZE);
+    let tail_offset = archive_size - tail_size as u64;
+    let mut tail = vec![0u8; tail_size];
+    if tail_size < ZIP_END_OF_CENTRAL_DIRECTORY_SIZE || !read_exact_at(archive, &mut tail, tail_offset).await? {
+        return Err(invalid_archive("truncated zip archive"));
+    }
+    let end = (0..=tail_size - ZIP_END_OF_CENTRAL_DIRECTORY_SIZE).rev()
+        .find(|i| u32_at(&tail, *i) == ZIP_END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+        .ok_or_else(|| invalid_archive("zip end of central directory not found"))?;
+    let mut entries = u16_at(&tail, end + 10) as u64;
+    let mut directory_size = u32_at(&tail, end + 12) as u64;
+    let mut directory_offset = u32_at(&tail, end + 16) as u64;
+    if entries == u16::MAX as u64 || directory_size == u32::MAX as u64 || directory_offset == u32::MAX as u64 {
+        let locator = end.checked_sub(20).filter(|locator| u32_at(&tail, *locator) == ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIGNATURE)
+            .ok_or_else(|| invalid_archive("zip64 end of central directory locator not found"))?;
+        let mut zip64_end = [0u8; 56];
+        if !read_exact_at(archive, &mut zip64_end, u64_at(&tail, locator + 8)).await? || u32_at(&zip64_end, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+            return Err(invalid_archive("zip64 end of central directory not found"));
+        }
+        entries = u64_at(&zip64_end, 32);
+        directory_size = u64_at(&zip64_end, 40);
+        directory_offset = u64_at(&zip64_end, 48);
+    }
+    if directory_offset.checked_add(directory_size).filter(|directory_end| *directory_end <= archive_size).is_none() {
+        return Err(invalid_archive("invalid zip central directory"));
+    }
+
+    let mut directory = vec![0u8; directory_size as usize];
+    if !read_exact_at(archive, &mut directory, directory_offset).await? {
+        return Err(invalid_archive("truncated zip archive"));
+    }
+    let mut index = HashMap::new();
+    let mut position = 0;
+    for _ in 0..entries {
+        if position + 46 > directory.len() || u32_at(&directory, position) != ZIP_CENTRAL_DIRECTORY_HEADER_SIGNATURE {
+            return Err(invalid_archive("invalid zip central directory"));
+        }
+        let header = &directory[position..];
+        let flags = u16_at(header, 8);
+        let method = u16_at(header, 10);
+        let mut compressed_size = u32_at(header, 20) as u64;
+        let mut size = u32_at(header, 24) as u64;
+        let name_length = u16_at(header, 28) as usize;
+        let extra_length = u16_at(header, 30) as usize;
+        let comment_length = u16_at(header, 32) as usize;
+        let mut local_header_offset = u32_at(header, 42) as u64;
+        let record_length = 46 + name_length + extra_length + comment_length;
+        if record_length > header.len() {
+            return Err(invalid_archive("invalid zip central directory"));
+        }
+        let name = String::from_utf8_lossy(&header[46..46 + name_length]).into_owned();
+        // the zip64 extra field contains the values that do not fit, in this order
+        let mut extra = &header[46 + name_length..46 + name_length + extra_length];
+        while extra.len() >= 4 {
+            let id = u16_at(extra, 0);
+            let length = (u16_at(extra, 2) as usize).min(extra.len() - 4);
+            if id == ZIP64_EXTRA_FIELD_ID {
+                let mut fields = extra[4..4 + length].chunks_exact(8).map(|field| u64_at(field, 0));
+                for value in [&mut size, &mut compressed_size, &mut local_header_offset] {
+                    if *value == u32::MAX as u64 {
+                        *value = fields.next().ok_or_else(|| invalid_archive("invalid zip64 extra field"))?;
+                    }
+                }
+            }
+            extra = &extra[4 + length..];
+        }
+        position += record_length;
+
+        let compression = match method {
+            0 => Compression::Stored,
+            8 => Compression::Deflated(compressed_size),
+            _ => continue,
+        };
+        let encrypted = flags & 0x1 != 0;
+        if encrypted || name.ends_with('/') {
+            continue;
+        }
+        index.insert(name, ArchiveEntry { offset: local_header_offset, size, compression, local_header: true });
+    }
+    Ok(index)
+}
+
+/// the data follows the local header, its extra field may differ from the central directory
+async fn zip_data_offset(archive: &dyn SourceFile, local_header_offset: u64) -> io::Result<u64> {
+    let mut header = [0u8; ZIP_LOCAL_HEADER_SIZE];
+    if !read_exact_at(archive, &mut header, local_header_offset).await? || u32_at(&header, 0) != ZIP_LOCAL_HEADER_SIGNATURE {
+        return Err(invalid_archive("invalid zip local header"));
+    }
+    let name_length = u16_at(&header, 26) as u64;
+    let extra_length = u16_at(&header, 28) as u64;
+    Ok(local_header_offset + ZIP_LOCAL_HEADER_SIZE as u64 + name_length + extra_length)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{self, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use flate2::Compression;
+    use flate2::write::DeflateEncoder;
+    use crate::archive_source::{archive_paths, ArchiveFileSource};
+    use crate::file_source::{estimated_remaining, FileSource, MemoryFileSource, SourceFile};
+    use futures_util::future::BoxFuture;
+
+    fn tar_header(name: &str, size: usize, typeflag: u8) -> Vec<u8> {
+        tar_header_with_size_field(name, format!("{:011o}\0", size).as_bytes(), typeflag)
+    }
+
+    fn tar_header_with_size_field(name: &str, size_field: &[u8], typeflag: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(size_field);
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        header
+    }
+
+    /// also used by the server tests
+    pub(crate) fn tar(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (name, content) in members {
+            let mut name = name.to_string();
+            if name.len() >= 100 {
+                let long_name = format!("{}\0", name);
+                tar.extend(tar_header("././@LongLink", long_name.len(), b'L'));
+                tar.extend(long_name.as_bytes());
+                tar.resize(tar.len().div_ceil(512) * 512, 0);
+                name.truncate(99);
+            }
+            tar.extend(tar_header(&name, content.len(), b'0'));
+            tar.extend(*content);
+            tar.resize(tar.len().div_ceil(512) * 512, 0);
+        }
+        tar.resize(tar.len() + 1024, 0);
+        tar
+    }
+
+    /// members are deflated if `deflate` is true
+    fn zip(members: &[(&str, &[u8])], deflate: bool) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for (name, content) in members {
+            let (method, data) = match deflate {
+                true => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(content).unwrap();
+                    (8u16, encoder.finish().unwrap())
+                }
+                false => (0u16, content.to_vec()),
+            };
+            let offset = zip.len() as u32;
+            zip.extend(0x0403_4b50u32.to_le_bytes());
+            zip.extend([20, 0, 0, 0]);
+            zip.extend(method.to_le_bytes());
+            zip.extend([0u8; 8]);
+            zip.extend((data.len() as u32).to_le_bytes());
+            zip.extend((content.len() as u32).to_le_bytes());
+            zip.extend((name.len() as u16).to_le_bytes());
+            zip.extend(0u16.to_le_bytes());
+            zip.extend(name.as_bytes());
+            zip.extend(&data);
+
+            directory.extend(0x0201_4b50u32.to_le_bytes());
+            directory.extend([20, 0, 20, 0, 0, 0]);
+            directory.extend(method.to_le_bytes());
+            directory.extend([0u8; 8]);
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((content.len() as u32).to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0u8; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = zip.len() as u32;
+        zip.extend(&directory);
+        zip.extend(0x0605_4b50u32.to_le_bytes());
+        zip.extend([0u8; 4]);
+        zip.extend((members.len() as u16).to_le_bytes());
+        zip.extend((members.len() as u16).to_le_bytes());
+        zip.extend((directory.len() as u32).to_le_bytes());
+        zip.extend(directory_offset.to_le_bytes());
+        zip.extend(0u16.to_le_bytes());
+        zip
+    }
+
+    /// retries while the index of the archive is read
+    async fn ready<'a, T, F: Fn() -> BoxFuture<'a, io::Result<T>>>(f: F) -> io::Result<T> {
+        loop {
+            match f().await {
+                Err(e) if estimated_remaining(&e).is_some() => tokio::time::sleep(Duration::from_millis(1)).await,
+                result => return result,
+            }
+        }
+    }
+
+    async fn read_all(file: &dyn SourceFile, offset: u64) -> Vec<u8> {
+        let mut content = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            let size = file.read_at(&mut buf, offset + content.len() as u64).await.unwrap();
+            if size == 0 {
+                return content;
+            }
+            content.extend(&buf[..size]);
+        }
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(archive_paths("bundle.tar/inner/file.bin").collect::<Vec<_>>(), vec![("bundle.tar", "inner/file.bin")]);
+        assert_eq!(archive_paths("a.zip/b.TAR/c").collect::<Vec<_>>(), vec![("a.zip", "b.TAR/c"), ("a.zip/b.TAR", "c")]);
+        assert_eq!(archive_paths("bundle.tar").count(), 0);
+        assert_eq!(archive_paths("dir/file.bin").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn members() {
+        let long_name = format!("{}/file.bin", "d".repeat(120));
+        let large: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let members: Vec<(&str, &[u8])> = vec![("inner/hello.txt", b"hello world"), (&long_name, b"long"), ("large.bin", &large)];
+        let inner = Arc::new(MemoryFileSource::new());
+        inner.insert("bundle.tar", tar(&members));
+        inner.insert("stored.zip", zip(&members, false));
+        inner.insert("deflated.zip", zip(&members, true));
+        inner.insert("plain.txt", b"plain".to_vec());
+        let source = ArchiveFileSource::new(inner.clone());
+        // the index is read in the background
+        let e = source.open("bundle.tar/inner/hello.txt").await.err().unwrap();
+        assert!(estimated_remaining(&e).is_some());
+
+        for archive in ["bundle.tar", "stored.zip", "deflated.zip"] {
+            for (name, content) in &members {
+                let file_name = format!("{}/{}", archive, name);
+                let file = ready(|| source.open(&file_name)).await.unwrap();
+                assert_eq!(read_all(file.as_ref(), 0).await, *content, "{}", file_name);
+                // resumption and reading backwards
+                assert_eq!(read_all(file.as_ref(), 3).await, content[3..], "{}", file_name);
+                assert_eq!(file.identity().await.unwrap().size(), content.len() as u64);
+                assert_eq!(file.identity().await.unwrap(), ready(|| source.stat(&file_name)).await.unwrap());
+            }
+            let missing = format!("{}/missing.txt", archive);
+            assert!(ready(|| source.open(&missing)).await.is_err());
+        }
+        assert_eq!(read_all(ready(|| source.open("plain.txt")).await.unwrap().as_ref(), 0).await, b"plain");
+        assert!(ready(|| source.open("plain.txt/hello.txt")).await.is_err());
+
+        // modifying the archive changes the identity of its members
+        let file = ready(|| source.open("bundle.tar/inner/hello.txt")).await.unwrap();
+        let version = file.identity().await.unwrap();
+        inner.insert("bundle.tar", tar(&[("inner/hello.txt", b"HELLO WORLD")]));
+        assert_ne!(ready(|| source.stat("bundle.tar/inner/hello.txt")).await.unwrap(), version);
+        let file = ready(|| source.open("bundle.tar/inner/hello.txt")).await.unwrap();
+        assert_eq!(read_all(file.as_ref(), 0).await, b"HELLO WORLD");
+        assert!(ready(|| source.open("bundle.tar/large.bin")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_archive() {
+        let inner = Arc::new(MemoryFileSource::new());
+        inner.insert("invalid.tar", vec![1u8; 2048]);
+        inner.insert("invalid.zip", vec![1u8; 2048]);
+        // a long name that is too large to be allocated
+        let mut huge_name = tar_header("././@LongLink", 1 << 30, b'L');
+        huge_name.resize(4096, 0);
+        inner.insert("huge_name.tar", huge_name);
+        // the padded size overflows
+        let mut huge_size = tar_header_with_size_field("hello.txt", &[0xff; 12], b'0');
+        huge_size.resize(4096, 0);
+        inner.insert("huge_size.tar", huge_size);
+        let source = ArchiveFileSource::new(inner);
+        for archive in ["invalid.tar", "invalid.zip", "huge_name.tar", "huge_size.tar"] {
+            let file_name = format!("{}/hello.txt", archive);
+            assert!(ready(|| source.open(&file_name)).await.is_err(), "{}", archive);
+        }
+    }
+
+    #[tokio::test]
+    async fn long_link_name() {
+        let link_name = format!("{}\0", "t".repeat(120));
+        let mut tar = tar_header("././@LongLink", link_name.len(), b'K');
+        tar.extend(link_name.as_bytes());
+        tar.resize(1024, 0);
+        tar.extend(tar_header("link", 0, b'2'));
+        tar.extend(&self::tar(&[("hello.txt", b"hello")]));
+        let inner = Arc::new(MemoryFileSource::new());
+        inner.insert("links.tar", tar);
+        let source = ArchiveFileSource::new(inner);
+        // the long link name neither names the link nor the following member
+        assert!(ready(|| source.open("links.tar/link")).await.is_err());
+        assert_eq!(read_all(ready(|| source.open("links.tar/hello.txt")).await.unwrap().as_ref(), 0).await, b"hello");
+    }
+}
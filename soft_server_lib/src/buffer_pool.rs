@@ -0,0 +1,66 @@
+use std::sync::Mutex;
+
+/// # Receive Buffer Pool
+///
+/// the received packets keep their buffers until they have been handled, the buffers are returned to the pool afterwards,
+/// so the packets are neither copied nor is a buffer allocated per packet
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    /// the capacity of every buffer
+    buffer_size: usize,
+    /// further returned buffers are dropped
+    max_buffers: usize,
+}
+
+impl BufferPool {
+
+    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            buffer_size,
+            max_buffers,
+        }
+    }
+
+    /// an empty buffer with a capacity of buffer_size, allocated if the pool is empty
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.buffer_size))
+    }
+
+    /// the buffer can be taken again, unless the pool is full
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() < self.buffer_size {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer_pool::BufferPool;
+
+    #[test]
+    fn reuse() {
+        let pool = BufferPool::new(16, 1);
+        let mut first = pool.take();
+        assert_eq!(first.capacity(), 16);
+        first.extend_from_slice(b"hello");
+        let pointer = first.as_ptr();
+        let second = pool.take();
+        pool.give(first);
+        // the pool is full
+        pool.give(second);
+        let reused = pool.take();
+        assert_eq!(reused.as_ptr(), pointer);
+        assert!(reused.is_empty());
+        // smaller buffers are not taken back
+        pool.give(Vec::with_capacity(8));
+        assert_eq!(pool.take().capacity(), 16);
+    }
+}
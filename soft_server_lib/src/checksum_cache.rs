@@ -1,80 +1,331 @@
 use soft_shared_lib::field_types::Checksum;
-use tokio::io::BufReader;
-use tokio::fs::File;
-use soft_shared_async_lib::helper::sha256_helper::generate_checksum;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use std::os::linux::fs::MetadataExt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use tokio::sync::{Mutex, Semaphore};
 use ttl_cache::TtlCache;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use crate::checksum_store::ChecksumStore;
+use crate::file_source::{FileIdentity, SourceFile};
 
 const MAX_ENTRIES: usize = 100;
-/// TODO increase for production use
-const ENTRY_TTL: Duration = Duration::from_secs(120);
+/// ready checksums are persisted in the checksum store, the in-memory cache only avoids its lookups
+pub(crate) const DEFAULT_ENTRY_TTL: Duration = Duration::from_secs(120);
+/// bytes per second, to estimate the hashing time before the first bytes are hashed
+const ASSUMED_HASH_RATE: f64 = 100_000_000.0;
+const HASH_BUFFER_SIZE: usize = 2usize.pow(16);
 
-type MTime = i64;
+/// resolves to None if the file changed while its checksum was generated
+pub type ChecksumFuture = Shared<BoxFuture<'static, Option<Checksum>>>;
+
+pub enum ChecksumState {
+    Ready(Checksum),
+    /// the checksum is generated, the duration is the estimated time until it is ready
+    Pending(ChecksumFuture, Duration),
+}
+
+/// tracks the generation of a checksum to estimate its completion time
+struct HashProgress {
+    file_size: u64,
+    hashed_bytes: AtomicU64,
+    /// None while waiting for a hashing permit
+    started: std::sync::Mutex<Option<Instant>>,
+}
+
+impl HashProgress {
+
+    fn new(file_size: u64) -> Self {
+        Self {
+            file_size,
+            hashed_bytes: AtomicU64::new(0),
+            started: std::sync::Mutex::new(None),
+        }
+    }
+
+    fn start(&self) {
+        *self.started.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// extrapolated from the hashing rate so far
+    fn estimated_remaining(&self) -> Duration {
+        let hashed_bytes = self.hashed_bytes.load(Relaxed);
+        let remaining_bytes = self.file_size.saturating_sub(hashed_bytes) as f64;
+        match *self.started.lock().unwrap() {
+            Some(started) if hashed_bytes > 0 => started.elapsed().mul_f64(remaining_bytes / hashed_bytes as f64),
+            _ => Duration::from_secs_f64(remaining_bytes / ASSUMED_HASH_RATE),
+        }
+    }
+}
 
 enum CacheEntry {
-    Generating(MTime),
-    Ready(Checksum, MTime),
+    Generating(FileIdentity, ChecksumFuture, Arc<HashProgress>),
+    Ready(Checksum, FileIdentity),
 }
 
 pub struct ChecksumCache {
     cache: Arc<Mutex<TtlCache<String, CacheEntry>>>,
+    /// None if checksums are not persisted
+    store: Option<std::sync::Mutex<ChecksumStore>>,
+    /// the precomputed checksums if they are not persisted, kept without a limit and ttl,
+    /// so they are not hashed again after they expired from the cache
+    precomputed: std::sync::Mutex<HashMap<String, (Checksum, FileIdentity)>>,
+    /// limits the number of files that are hashed at the same time, so hashing does not starve transfers
+    hashing_permits: Semaphore,
+    /// requests for known checksums, see ServerStats
+    hits: AtomicU64,
+    /// requests that wait for the generation of the checksum
+    misses: AtomicU64,
+    /// changed by Server::reload
+    entry_ttl: std::sync::Mutex<Duration>,
 }
 
 impl ChecksumCache {
-    pub fn new() -> Arc<ChecksumCache> {
+    pub fn new(store: Option<ChecksumStore>, max_concurrent_checksums: usize, entry_ttl: Duration) -> Arc<ChecksumCache> {
         Arc::new(ChecksumCache {
             cache: Arc::new(Mutex::new(TtlCache::new(MAX_ENTRIES))),
+            store: store.map(std::sync::Mutex::new),
+            precomputed: std::sync::Mutex::new(HashMap::new()),
+            hashing_permits: Semaphore::new(max_concurrent_checksums),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            entry_ttl: std::sync::Mutex::new(entry_ttl),
         })
     }
 
-    /// Generate the checksum for that file or read if from cache
-    /// None if still processing
-    /// TODO make function return a shared Future instead of an Option type
-    pub async fn get_checksum(self: Arc<Self>, file_name: &str, file: File) -> Option<Checksum> {
-        let mut reader = BufReader::new(file);
-        let current_mtime = reader.get_ref().metadata().await.unwrap().st_mtime();
+    /// applies to entries that are inserted or refreshed afterwards
+    pub fn set_entry_ttl(&self, entry_ttl: Duration) {
+        *self.entry_ttl.lock().unwrap() = entry_ttl;
+    }
+
+    fn entry_ttl(&self) -> Duration {
+        *self.entry_ttl.lock().unwrap()
+    }
+
+    /// Generate the checksum for that version of the file or read if from cache
+    ///
+    /// file might be a snapshot of that version of the file
+    ///
+    /// all requests for a file that is still processing share the same generation,
+    /// requests are not held until it finishes, because the receive loop would stall:
+    /// they are answered with ChecksumNotReady and a retry time estimated from the progress of the shared generation
+    pub async fn get_checksum_of_version(self: Arc<Self>, file_name: &str, current_identity: FileIdentity, file: Arc<dyn SourceFile>) -> io::Result<ChecksumState> {
+        let state = self.clone().lookup(file_name, current_identity, file).await?;
+        match state {
+            ChecksumState::Ready(_) => self.hits.fetch_add(1, Relaxed),
+            ChecksumState::Pending(_, _) => self.misses.fetch_add(1, Relaxed),
+        };
+        Ok(state)
+    }
+
+    /// the number of requests for known checksums and the number of requests that waited for their generation
+    ///
+    /// precomputed checksums are not counted
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits.load(Relaxed), self.misses.load(Relaxed))
+    }
+
+    async fn lookup(self: Arc<Self>, file_name: &str, current_identity: FileIdentity, file: Arc<dyn SourceFile>) -> io::Result<ChecksumState> {
+        let reader_identity = file.identity().await?;
         let mut cache = self.cache.lock().await;
         match cache.get(file_name) {
-            Some(CacheEntry::Generating(cache_mtime)) => {
-                if *cache_mtime == current_mtime {
-                    return None;
-                } else {
-                    log::debug!("file {} changed", file_name);
-                    cache.remove(file_name);
-                }
+            Some(CacheEntry::Generating(cache_identity, future, progress)) if *cache_identity == current_identity => {
+                return Ok(ChecksumState::Pending(future.clone(), progress.estimated_remaining()));
             }
-            Some(CacheEntry::Ready(checksum, cache_mtime)) => {
-                if *cache_mtime == current_mtime {
-                    return Some(checksum.clone());
-                } else {
-                    log::debug!("file {} changed", file_name);
-                    cache.remove(file_name);
-                }
+            Some(CacheEntry::Ready(checksum, cache_identity)) if *cache_identity == current_identity => {
+                return Ok(ChecksumState::Ready(checksum.clone()));
             }
-            _ => {}
+            Some(_) => {
+                log::debug!("file {} changed", file_name);
+                cache.remove(file_name);
+            }
+            None => {}
+        }
+
+        if let Some(checksum) = self.persisted(file_name, &current_identity) {
+            cache.insert(String::from(file_name), CacheEntry::Ready(checksum, current_identity), self.entry_ttl());
+            return Ok(ChecksumState::Ready(checksum));
         }
 
         // start generating checksum in own task
-        let entry = CacheEntry::Generating(current_mtime);
-        cache.insert(String::from(file_name), entry, ENTRY_TTL);
-        drop(cache); // unlock
-        let checksum_cache = self.clone();
-        let file_name= String::from(file_name);
-        tokio::spawn(async move {
-            log::debug!("generating checksum for {}", file_name);
-            let checksum = generate_checksum(&mut reader).await;
-            let mut cache = checksum_cache.cache.lock().await;
-            if let Some(&mut CacheEntry::Generating(mtime)) = cache.get_mut(&file_name) {
-                if current_mtime == mtime {
-                    cache.insert(file_name.clone(), CacheEntry::Ready(checksum, current_mtime), ENTRY_TTL);
-                    log::debug!("checksum for {} is ready", file_name);
+        let progress = Arc::new(HashProgress::new(current_identity.size()));
+        let handle = tokio::spawn(self.clone().generate(String::from(file_name), file, reader_identity, current_identity, progress.clone()));
+        let future = handle.map(|result| result.ok().flatten()).boxed().shared();
+        cache.insert(String::from(file_name), CacheEntry::Generating(current_identity, future.clone(), progress.clone()), self.entry_ttl());
+        Ok(ChecksumState::Pending(future, progress.estimated_remaining()))
+    }
+
+    /// the checksum of that version of the file from the store, or from the precomputed checksums if there is no store
+    fn persisted(&self, file_name: &str, identity: &FileIdentity) -> Option<Checksum> {
+        match &self.store {
+            Some(store) => store.lock().unwrap().get(file_name, identity),
+            None => self.precomputed.lock().unwrap().get(file_name)
+                .filter(|(_, precomputed_identity)| precomputed_identity == identity)
+                .map(|(checksum, _)| *checksum),
+        }
+    }
+
+    /// generate the checksum for that file unless it is known
+    ///
+    /// returns when the checksum is ready, so the caller can precompute one file after another
+    pub async fn precompute(self: Arc<Self>, file_name: &str, file: Arc<dyn SourceFile>) -> io::Result<()> {
+        let current_identity = file.identity().await?;
+        let checksum = match self.clone().lookup(file_name, current_identity, file).await? {
+            ChecksumState::Ready(checksum) => Some(checksum),
+            ChecksumState::Pending(future, _) => future.await,
+        };
+        if let (None, Some(checksum)) = (&self.store, checksum) {
+            self.precomputed.lock().unwrap().insert(String::from(file_name), (checksum, current_identity));
+        }
+        Ok(())
+    }
+
+    async fn generate(self: Arc<Self>, file_name: String, file: Arc<dyn SourceFile>, reader_identity: FileIdentity, current_identity: FileIdentity, progress: Arc<HashProgress>) -> Option<Checksum> {
+        let permit = self.hashing_permits.acquire().await.expect("semaphore closed");
+        log::debug!("generating checksum for {}", file_name);
+        progress.start();
+        let checksum = Self::hash(file.as_ref(), &progress.hashed_bytes).await;
+        drop(permit);
+        // hashing large files can outlast the cache entry, so the identity is checked on the file itself
+        let checksum = match (checksum, file.identity().await) {
+            (Ok(checksum), Ok(identity)) if identity == reader_identity => Some(checksum),
+            (Err(e), _) | (_, Err(e)) => {
+                log::warn!("failed to generate checksum for {}, caused by: {}", file_name, e);
+                None
+            }
+            _ => {
+                log::debug!("file {} changed while generating its checksum", file_name);
+                None
+            }
+        };
+        let checksum = match checksum {
+            Some(checksum) => checksum,
+            None => {
+                // the next request tries again
+                let mut cache = self.cache.lock().await;
+                if let Some(CacheEntry::Generating(identity, _, _)) = cache.get(&file_name) {
+                    if current_identity == *identity {
+                        cache.remove(&file_name);
+                    }
                 }
+                return None;
+            }
+        };
+        let mut cache = self.cache.lock().await;
+        if let Some(CacheEntry::Generating(identity, _, _)) = cache.get(&file_name) {
+            if current_identity != *identity {
+                // a newer version of the file is processing
+                return Some(checksum);
             }
-        });
+        }
+        cache.insert(file_name.clone(), CacheEntry::Ready(checksum, current_identity), self.entry_ttl());
+        if let Some(store) = &self.store {
+            store.lock().unwrap().insert(&file_name, current_identity, checksum);
+        }
+        log::debug!("checksum for {} is ready", file_name);
+        Some(checksum)
+    }
+
+    /// the number of hashed bytes is added to progress
+    async fn hash(file: &dyn SourceFile, progress: &AtomicU64) -> io::Result<Checksum> {
+        let mut buf = vec![0u8; HASH_BUFFER_SIZE];
+        let mut hasher = Sha256::new();
+        let mut offset = 0;
+        loop {
+            let size = file.read_at(&mut buf, offset).await?;
+            if size == 0 {
+                break;
+            }
+            hasher.update(&buf[..size]);
+            offset += size as u64;
+            progress.fetch_add(size as u64, Relaxed);
+        }
+        let mut checksum = Checksum::default();
+        checksum.copy_from_slice(&hasher.finalize());
+        Ok(checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum_cache::{ChecksumCache, ChecksumState, HashProgress, ASSUMED_HASH_RATE, DEFAULT_ENTRY_TTL};
+    use crate::file_source::{FileIdentity, SourceFile};
+    use futures_util::future::{self, BoxFuture, FutureExt};
+    use soft_shared_lib::helper::sha256_helper::sha256_from_bytes;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Duration;
+
+    /// a file that can not be read
+    struct BrokenFile;
+
+    struct HelloFile;
+
+    impl SourceFile for HelloFile {
+        fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>> {
+            future::ready(Ok(FileIdentity::new(5, 0, 0, 1))).boxed()
+        }
+
+        fn read_at<'a>(&'a self, buf: &'a mut [u8], offset: u64) -> BoxFuture<'a, io::Result<usize>> {
+            let content = &b"hello"[(offset as usize).min(5)..];
+            buf[..content.len()].copy_from_slice(content);
+            future::ready(Ok(content.len())).boxed()
+        }
+    }
+
+    impl SourceFile for BrokenFile {
+        fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>> {
+            future::ready(Ok(FileIdentity::new(10, 0, 0, 1))).boxed()
+        }
+
+        fn read_at<'a>(&'a self, _buf: &'a mut [u8], _offset: u64) -> BoxFuture<'a, io::Result<usize>> {
+            future::ready(Err(io::Error::other("broken"))).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_generation() {
+        let cache = ChecksumCache::new(None, 1, DEFAULT_ENTRY_TTL);
+        let identity = FileIdentity::new(10, 0, 0, 1);
+        for _ in 0..2 {
+            // the failed generation is not shared with the next request
+            match cache.clone().get_checksum_of_version("broken", identity, Arc::new(BrokenFile)).await.unwrap() {
+                ChecksumState::Pending(future, _) => assert_eq!(future.await, None),
+                ChecksumState::Ready(_) => panic!("checksum of a broken file"),
+            }
+            assert!(cache.cache.lock().await.get("broken").is_none());
+        }
+        assert_eq!(cache.hits_and_misses(), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn precomputed_without_store() {
+        let cache = ChecksumCache::new(None, 1, DEFAULT_ENTRY_TTL);
+        cache.clone().precompute("hello", Arc::new(HelloFile)).await.unwrap();
+        // expired from the cache
+        cache.cache.lock().await.remove("hello");
+        match cache.clone().get_checksum_of_version("hello", FileIdentity::new(5, 0, 0, 1), Arc::new(HelloFile)).await.unwrap() {
+            ChecksumState::Ready(checksum) => assert_eq!(checksum, sha256_from_bytes(b"hello")),
+            ChecksumState::Pending(_, _) => panic!("precomputed checksum is generated again"),
+        }
+        // another version is generated
+        assert!(matches!(cache.get_checksum_of_version("hello", FileIdentity::new(5, 0, 0, 2), Arc::new(HelloFile)).await.unwrap(), ChecksumState::Pending(_, _)));
+    }
 
-        return None;
+    #[test]
+    fn estimated_remaining() {
+        let progress = HashProgress::new(ASSUMED_HASH_RATE as u64 * 2);
+        assert_eq!(progress.estimated_remaining(), Duration::from_secs(2));
+        progress.start();
+        std::thread::sleep(Duration::from_millis(100));
+        // a quarter in 100ms
+        progress.hashed_bytes.store(ASSUMED_HASH_RATE as u64 / 2, Relaxed);
+        let estimated_remaining = progress.estimated_remaining();
+        assert!(estimated_remaining >= Duration::from_millis(300));
+        assert!(estimated_remaining < Duration::from_secs(2));
     }
 }
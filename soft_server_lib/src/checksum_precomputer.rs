@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures_util::StreamExt;
+use inotify::{Inotify, WatchMask, WatchDescriptor, EventMask, Watches};
+use log::{debug, warn};
+use crate::checksum_cache::ChecksumCache;
+use crate::file_sandbox::FileSandbox;
+use crate::mount_table::Mount;
+
+const EVENT_BUFFER_SIZE: usize = 4096;
+
+/// files are hashed after they have been written or moved into a watched directory,
+/// created directories are watched as well
+fn watch_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::DONT_FOLLOW
+}
+
+/// # Checksum Precomputer
+///
+/// computes the checksums of all served files when the server starts
+/// and recomputes them when files are modified, so clients do not have to wait for ChecksumNotReady
+///
+/// the mounted directory and its subdirectories are watched with inotify, symbolic links are not followed
+///
+/// files are hashed one after another, so lazily requested checksums still get a hashing permit
+pub struct ChecksumPrecomputer {
+    mount: Mount,
+    file_sandbox: Arc<FileSandbox>,
+    checksum_cache: Arc<ChecksumCache>,
+    /// watched directories, relative to the mounted directory
+    watched_dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl ChecksumPrecomputer {
+
+    pub fn new(mount: Mount, file_sandbox: Arc<FileSandbox>, checksum_cache: Arc<ChecksumCache>) -> Self {
+        Self {
+            mount,
+            file_sandbox,
+            checksum_cache,
+            watched_dirs: HashMap::new(),
+        }
+    }
+
+    /// scan and watch the mounted directory until the server stops
+    pub async fn run(mut self) -> io::Result<()> {
+        let mut events = Inotify::init()?.into_event_stream([0u8; EVENT_BUFFER_SIZE])?;
+        let mut watches = events.watches();
+
+        // watch before scanning, so no modification is missed
+        for file_name in self.watch_recursive(&mut watches, PathBuf::new()) {
+            self.precompute(&file_name).await;
+        }
+        debug!("precomputed checksums of {:?}", self.mount.dir);
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                // events have been dropped, unchanged files are not hashed again
+                warn!("inotify queue overflow, rescan {:?}", self.mount.dir);
+                for file_name in self.watch_recursive(&mut watches, PathBuf::new()) {
+                    self.precompute(&file_name).await;
+                }
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                // directory has been removed
+                self.watched_dirs.remove(&event.wd);
+                continue;
+            }
+            let (dir, name) = match (self.watched_dirs.get(&event.wd), event.name) {
+                (Some(dir), Some(name)) => (dir, name),
+                _ => continue,
+            };
+            let file_name = dir.join(name);
+            if event.mask.contains(EventMask::ISDIR) {
+                if event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                    for file_name in self.watch_recursive(&mut watches, file_name) {
+                        self.precompute(&file_name).await;
+                    }
+                }
+            } else if event.mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+                self.precompute(&file_name).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// watch the directory and all of its subdirectories
+    ///
+    /// returns the files in these directories, relative to the mounted directory
+    fn watch_recursive(&mut self, watches: &mut Watches, dir: PathBuf) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            let path = self.mount.dir.join(&dir);
+            let file_name = self.mount.file_name(&dir);
+            let file_name = match file_name.to_str() {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            if self.file_sandbox.is_hidden(file_name) {
+                // e.g. the snapshots
+                continue;
+            }
+            if !self.is_mounted(file_name) {
+                // the directory is shadowed by another mount
+                continue;
+            }
+            match watches.add(&path, watch_mask()) {
+                Ok(watch_descriptor) => {
+                    self.watched_dirs.insert(watch_descriptor, dir.clone());
+                }
+                Err(e) => {
+                    warn!("failed to watch {:?}, caused by: {}", path, e);
+                    continue;
+                }
+            }
+            let entries = match std::fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("failed to scan {:?}, caused by: {}", path, e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => dirs.push(dir.join(entry.file_name())),
+                    Ok(file_type) if file_type.is_file() => files.push(dir.join(entry.file_name())),
+                    _ => {}
+                }
+            }
+        }
+        files
+    }
+
+    /// false if the file is served from another mount
+    fn is_mounted(&self, file_name: &str) -> bool {
+        let mount = self.file_sandbox.mount_table().resolve(file_name).map(|(mount, _)| mount);
+        mount == Some(&self.mount)
+    }
+
+    /// files that are not served, e.g. the checksum store, are skipped
+    async fn precompute(&self, relative_path: &Path) {
+        let file_name = match self.mount.file_name(relative_path).to_str() {
+            Some(file_name) => file_name.to_string(),
+            None => return,
+        };
+        let file = match self.file_sandbox.get_file(&file_name).await {
+            Ok(file) => file,
+            Err(_) => {
+                debug!("skip precomputing checksum of {}", file_name);
+                return;
+            }
+        };
+        if let Err(e) = self.checksum_cache.clone().precompute(&file_name, file).await {
+            warn!("failed to precompute checksum of {}, caused by: {}", file_name, e);
+        }
+    }
+}
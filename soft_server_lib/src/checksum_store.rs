@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use soft_shared_lib::field_types::Checksum;
+use soft_shared_lib::helper::sha256_helper::{sha256_to_hex_string, sha256_from_hex_string};
+use crate::file_source::FileIdentity;
+use crate::archive_source::{archive_paths, member_identity};
+use crate::mount_table::MountTable;
+
+/// default file name of the store, in the served directory
+pub const CHECKSUM_STORE_FILE_NAME: &str = ".soft_checksums";
+
+/// # Checksum Store
+///
+/// persists the checksums of the served files across restarts,
+/// so large files do not have to be hashed again
+///
+/// the store is an append only log with one line per checksum, later lines replace earlier lines of the same file:
+/// `size mtime mtime_nsec inode checksum file_name`
+///
+/// stale entries are discarded and the log is compacted when the store is opened
+pub struct ChecksumStore {
+    path: PathBuf,
+    entries: HashMap<String, (FileIdentity, Checksum)>,
+    log: File,
+}
+
+impl ChecksumStore {
+
+    /// load the store from path or create a new one
+    ///
+    /// file names are resolved in the mounted directories
+    pub fn open(path: &Path, mount_table: &MountTable) -> io::Result<Self> {
+        let mut entries = match File::open(path) {
+            Ok(file) => Self::read_log(file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        entries.retain(|file_name, (identity, _)| {
+            let valid = Self::is_current(mount_table, file_name, identity);
+            if !valid {
+                debug!("discard stale checksum of {}", file_name);
+            }
+            valid
+        });
+
+        // compact, rename is atomic, so a crash leaves either the old or the new log
+        let compacted_path = Self::compacted_path(path);
+        let mut compacted = File::create(&compacted_path)?;
+        for (file_name, (identity, checksum)) in &entries {
+            compacted.write_all(Self::log_line(file_name, identity, checksum).as_bytes())?;
+        }
+        compacted.sync_all()?;
+        fs::rename(&compacted_path, path)?;
+
+        let log = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+            log,
+        })
+    }
+
+    /// all files the store writes, they must not be served
+    pub fn files(path: &Path) -> [PathBuf; 2] {
+        [path.to_path_buf(), Self::compacted_path(path)]
+    }
+
+    /// the log is compacted into this file and then renamed to path
+    fn compacted_path(path: &Path) -> PathBuf {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".tmp");
+        path.with_file_name(file_name)
+    }
+
+    /// the members of archives are valid as long as the archive has not been modified
+    fn is_current(mount_table: &MountTable, file_name: &str, identity: &FileIdentity) -> bool {
+        let metadata = |file_name: &str| mount_table.local_path(file_name).and_then(|path| fs::metadata(path).ok());
+        if let Some(metadata) = metadata(file_name) {
+            return FileIdentity::from(&metadata) == *identity;
+        }
+        archive_paths(file_name).any(|(archive_name, _)| {
+            metadata(archive_name).is_some_and(|metadata| member_identity(FileIdentity::from(&metadata), identity.size()) == *identity)
+        })
+    }
+
+    /// invalid lines, e.g. the last line after a crash, are skipped
+    fn read_log(file: File) -> io::Result<HashMap<String, (FileIdentity, Checksum)>> {
+        let mut entries = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match Self::parse_line(&line) {
+                Some((file_name, identity, checksum)) => {
+                    entries.insert(file_name, (identity, checksum));
+                }
+                None => warn!("skip invalid checksum store line: {}", line),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn parse_line(line: &str) -> Option<(String, FileIdentity, Checksum)> {
+        let mut fields = line.splitn(6, ' ');
+        let identity = FileIdentity::new(
+            fields.next()?.parse().ok()?,
+            fields.next()?.parse().ok()?,
+            fields.next()?.parse().ok()?,
+            fields.next()?.parse().ok()?,
+        );
+        let checksum = sha256_from_hex_string(fields.next()?)?;
+        let file_name = fields.next()?.to_string();
+        Some((file_name, identity, checksum))
+    }
+
+    fn log_line(file_name: &str, identity: &FileIdentity, checksum: &Checksum) -> String {
+        format!(
+            "{} {} {} {} {} {}\n",
+            identity.size(),
+            identity.mtime(),
+            identity.mtime_nsec(),
+            identity.inode(),
+            sha256_to_hex_string(*checksum),
+            file_name
+        )
+    }
+
+    /// None if the checksum is unknown or the file has changed
+    pub fn get(&self, file_name: &str, identity: &FileIdentity) -> Option<Checksum> {
+        match self.entries.get(file_name) {
+            Some((stored_identity, checksum)) if stored_identity == identity => Some(*checksum),
+            _ => None,
+        }
+    }
+
+    /// failures are only logged, the checksum is still kept in memory
+    pub fn insert(&mut self, file_name: &str, identity: FileIdentity, checksum: Checksum) {
+        self.entries.insert(file_name.to_string(), (identity, checksum));
+        if file_name.contains('\n') {
+            return;
+        }
+        if let Err(e) = self.log.write_all(Self::log_line(file_name, &identity, &checksum).as_bytes()) {
+            warn!("failed to persist checksum of {} in {:?}, caused by: {}", file_name, self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum_store::{ChecksumStore, CHECKSUM_STORE_FILE_NAME};
+    use crate::file_source::FileIdentity;
+    use crate::archive_source::member_identity;
+    use crate::mount_table::{Mount, MountTable};
+    use tempdir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn persist_and_discard_stale() {
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let store_path = served_dir.path().join(CHECKSUM_STORE_FILE_NAME);
+        let mount_table = MountTable::new(vec![Mount::new("", served_dir.path().to_path_buf())]);
+        fs::write(served_dir.path().join("hello.txt"), "hello").unwrap();
+        fs::write(served_dir.path().join("world.txt"), "world").unwrap();
+        let hello = FileIdentity::from(&fs::metadata(served_dir.path().join("hello.txt")).unwrap());
+        let world = FileIdentity::from(&fs::metadata(served_dir.path().join("world.txt")).unwrap());
+
+        let mut store = ChecksumStore::open(&store_path, &mount_table).unwrap();
+        store.insert("hello.txt", hello, [1; 32]);
+        store.insert("world.txt", world, [2; 32]);
+        store.insert("world.txt", world, [3; 32]);
+        drop(store);
+
+        // restart
+        fs::write(served_dir.path().join("hello.txt"), "hello world").unwrap();
+        let store = ChecksumStore::open(&store_path, &mount_table).unwrap();
+        let changed = FileIdentity::from(&fs::metadata(served_dir.path().join("hello.txt")).unwrap());
+        assert_eq!(store.get("hello.txt", &hello), None);
+        assert_eq!(store.get("hello.txt", &changed), None);
+        assert_eq!(store.get("world.txt", &world), Some([3; 32]));
+        assert_eq!(fs::read_to_string(&store_path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn archive_members() {
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let store_path = served_dir.path().join(CHECKSUM_STORE_FILE_NAME);
+        let mount_table = MountTable::new(vec![Mount::new("", served_dir.path().to_path_buf())]);
+        fs::write(served_dir.path().join("bundle.tar"), "archive").unwrap();
+        let archive = FileIdentity::from(&fs::metadata(served_dir.path().join("bundle.tar")).unwrap());
+        let member = member_identity(archive, 5);
+
+        let mut store = ChecksumStore::open(&store_path, &mount_table).unwrap();
+        store.insert("bundle.tar/inner/hello.txt", member, [1; 32]);
+        drop(store);
+
+        // restart
+        let store = ChecksumStore::open(&store_path, &mount_table).unwrap();
+        assert_eq!(store.get("bundle.tar/inner/hello.txt", &member), Some([1; 32]));
+        drop(store);
+
+        // restart after the archive has been replaced
+        fs::remove_file(served_dir.path().join("bundle.tar")).unwrap();
+        fs::write(served_dir.path().join("bundle.tar"), "modified archive").unwrap();
+        let store = ChecksumStore::open(&store_path, &mount_table).unwrap();
+        assert_eq!(store.get("bundle.tar/inner/hello.txt", &member), None);
+    }
+}
@@ -0,0 +1,274 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use serde::Deserialize;
+use crate::mount_table::check_mounts;
+use crate::server_config::ServerConfig;
+
+/// the TOML representation of a ServerConfig, missing settings keep the defaults of ServerConfig::new
+///
+/// ```toml
+/// served_dir = "/srv/soft"
+/// mounts = ["lab=/data/lab,scavenger"]
+/// acl = ["deny lab/** 0.0.0.0/0"]
+///
+/// [limits]
+/// max_connections = 200
+///
+/// [timeouts]
+/// min_connection_timeout = 10.0
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    served_dir: PathBuf,
+    /// same syntax as the --mount option of the CLI
+    #[serde(default)]
+    mounts: Vec<String>,
+    #[serde(default)]
+    scavenger_paths: Vec<PathBuf>,
+    checksum_store: Option<PathBuf>,
+    /// false to keep checksums only in memory
+    #[serde(default = "enabled")]
+    persist_checksums: bool,
+    #[serde(default)]
+    precompute_checksums: bool,
+    #[serde(default)]
+    snapshots: bool,
+    snapshot_dir: Option<PathBuf>,
+    /// in seconds
+    snapshot_retention: Option<u64>,
+    #[serde(default)]
+    archives: bool,
+    symlinks: Option<String>,
+    /// false to hide files and directories whose name starts with a dot
+    #[serde(default = "enabled")]
+    dot_files: bool,
+    /// same syntax as the --acl option of the CLI
+    #[serde(default)]
+    acl: Vec<String>,
+    metrics_addr: Option<SocketAddr>,
+    #[serde(default)]
+    metrics_per_connection: bool,
+    access_log: Option<PathBuf>,
+    access_log_format: Option<String>,
+    shards: Option<usize>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    timeouts: Timeouts,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Limits {
+    max_connections: Option<usize>,
+    max_concurrent_checksums: Option<usize>,
+    file_reader_buffer_size: Option<usize>,
+    /// in seconds
+    checksum_cache_ttl: Option<f64>,
+}
+
+/// in seconds
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Timeouts {
+    initial_rtt: Option<f64>,
+    min_connection_timeout: Option<f64>,
+    min_path_cache_timeout: Option<f64>,
+    max_persist_timeout: Option<f64>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl ServerConfig {
+
+    /// read the config from a TOML file
+    ///
+    /// relative paths are relative to the working directory, like the options of the CLI
+    pub fn load(path: &Path) -> io::Result<ServerConfig> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text).map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))
+    }
+
+    /// InvalidData if the text is no valid config, e.g. because of unknown or misspelled settings
+    pub fn from_toml(text: &str) -> io::Result<ServerConfig> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| invalid_data(e.to_string()))?;
+        let mut config = ServerConfig::new(file.served_dir);
+        config.mounts = parse_all(&file.mounts, "mount")?;
+        check_mounts(&config.mounts).map_err(invalid_data)?;
+        config.scavenger_paths = file.scavenger_paths;
+        if let Some(checksum_store) = file.checksum_store {
+            config.checksum_store_path = Some(checksum_store);
+        }
+        if !file.persist_checksums {
+            config.checksum_store_path = None;
+        }
+        config.precompute_checksums = file.precompute_checksums;
+        if file.snapshots {
+            config.enable_snapshots();
+        }
+        if let Some(snapshot_dir) = file.snapshot_dir {
+            config.snapshot_dir = Some(snapshot_dir);
+        }
+        if let Some(snapshot_retention) = file.snapshot_retention {
+            config.snapshot_retention = Duration::from_secs(snapshot_retention);
+        }
+        config.serve_archive_members = file.archives;
+        if let Some(symlinks) = file.symlinks {
+            config.symlink_policy = symlinks.parse().map_err(invalid_data)?;
+        }
+        config.serve_dot_files = file.dot_files;
+        config.access_rules = parse_all(&file.acl, "access rule")?;
+        config.metrics_addr = file.metrics_addr;
+        config.metrics_per_connection = file.metrics_per_connection;
+        config.access_log_path = file.access_log;
+        if let Some(access_log_format) = file.access_log_format {
+            config.access_log_format = access_log_format.parse().map_err(invalid_data)?;
+        }
+        config.shards = positive(file.shards, "shards")?.unwrap_or(config.shards);
+
+        let limits = file.limits;
+        config.max_connections = positive(limits.max_connections, "max_connections")?.unwrap_or(config.max_connections);
+        config.max_concurrent_checksums = positive(limits.max_concurrent_checksums, "max_concurrent_checksums")?.unwrap_or(config.max_concurrent_checksums);
+        config.file_reader_buffer_size = positive(limits.file_reader_buffer_size, "file_reader_buffer_size")?.unwrap_or(config.file_reader_buffer_size);
+        config.checksum_cache_ttl = seconds(limits.checksum_cache_ttl, "checksum_cache_ttl")?.unwrap_or(config.checksum_cache_ttl);
+
+        let timeouts = file.timeouts;
+        let defaults = config.timeouts;
+        config.timeouts.initial_rtt = seconds(timeouts.initial_rtt, "initial_rtt")?.unwrap_or(defaults.initial_rtt);
+        config.timeouts.min_connection_timeout = seconds(timeouts.min_connection_timeout, "min_connection_timeout")?.unwrap_or(defaults.min_connection_timeout);
+        config.timeouts.min_path_cache_timeout = seconds(timeouts.min_path_cache_timeout, "min_path_cache_timeout")?.unwrap_or(defaults.min_path_cache_timeout);
+        config.timeouts.max_persist_timeout = seconds(timeouts.max_persist_timeout, "max_persist_timeout")?.unwrap_or(defaults.max_persist_timeout);
+        Ok(config)
+    }
+}
+
+fn parse_all<T: FromStr<Err = String>>(values: &[String], kind: &str) -> io::Result<Vec<T>> {
+    values.iter()
+        .map(|value| value.parse().map_err(|e| invalid_data(format!("invalid {}: {}", kind, e))))
+        .collect()
+}
+
+fn positive(value: Option<usize>, name: &str) -> io::Result<Option<usize>> {
+    match value {
+        Some(0) => Err(invalid_data(format!("{} must be greater than 0", name))),
+        _ => Ok(value),
+    }
+}
+
+fn seconds(value: Option<f64>, name: &str) -> io::Result<Option<Duration>> {
+    value.map(|seconds| Duration::try_from_secs_f64(seconds).ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| invalid_data(format!("{} must be a positive number of seconds", name))))
+        .transpose()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use crate::file_source::SymlinkPolicy;
+    use crate::server_config::{ServerConfig, Timeouts};
+    use crate::access_log::AccessLogFormat;
+    use crate::server::MAX_SIMULTANEOUS_CONNECTIONS;
+
+    #[test]
+    fn defaults() {
+        let config = ServerConfig::from_toml("served_dir = \"/srv/soft\"").unwrap();
+        assert_eq!(config.served_dir, PathBuf::from("/srv/soft"));
+        assert_eq!(config.checksum_store_path, Some(PathBuf::from("/srv/soft/.soft_checksums")));
+        assert_eq!(config.max_connections, MAX_SIMULTANEOUS_CONNECTIONS);
+        assert_eq!(config.timeouts, Timeouts::default());
+        assert!(config.mounts.is_empty());
+        assert!(config.restart_required_changes(&ServerConfig::new(PathBuf::from("/srv/soft"))).is_empty());
+    }
+
+    #[test]
+    fn all_settings() {
+        let config = ServerConfig::from_toml(r#"
+            served_dir = "/srv/soft"
+            mounts = ["lab=/data/lab,scavenger"]
+            scavenger_paths = ["backups"]
+            persist_checksums = false
+            precompute_checksums = true
+            snapshots = true
+            snapshot_retention = 600
+            archives = true
+            symlinks = "deny"
+            dot_files = false
+            acl = ["deny lab/** 0.0.0.0/0"]
+            metrics_addr = "127.0.0.1:9100"
+            metrics_per_connection = true
+            access_log = "/var/log/soft/access.log"
+            access_log_format = "json"
+            shards = 4
+
+            [limits]
+            max_connections = 200
+            max_concurrent_checksums = 4
+            file_reader_buffer_size = 1048576
+            checksum_cache_ttl = 30
+
+            [timeouts]
+            initial_rtt = 0.5
+            min_connection_timeout = 10.0
+            min_path_cache_timeout = 2.5
+            max_persist_timeout = 30.0
+        "#).unwrap();
+        assert_eq!(config.mounts.len(), 1);
+        assert_eq!(config.mounts[0].dir, PathBuf::from("/data/lab"));
+        assert_eq!(config.scavenger_paths, vec![PathBuf::from("backups")]);
+        assert_eq!(config.checksum_store_path, None);
+        assert!(config.precompute_checksums);
+        assert_eq!(config.snapshot_dir, Some(PathBuf::from("/srv/soft/.soft_snapshots")));
+        assert_eq!(config.snapshot_retention, Duration::from_secs(600));
+        assert!(config.serve_archive_members);
+        assert_eq!(config.symlink_policy, SymlinkPolicy::Deny);
+        assert!(!config.serve_dot_files);
+        assert!(!config.is_allowed("lab/data.bin", "10.0.0.1".parse().unwrap()));
+        assert_eq!(config.metrics_addr, Some("127.0.0.1:9100".parse().unwrap()));
+        assert!(config.metrics_per_connection);
+        assert_eq!(config.access_log_path, Some(PathBuf::from("/var/log/soft/access.log")));
+        assert_eq!(config.access_log_format, AccessLogFormat::Json);
+        assert_eq!(config.shards, 4);
+        assert_eq!(config.max_connections, 200);
+        assert_eq!(config.max_concurrent_checksums, 4);
+        assert_eq!(config.file_reader_buffer_size, 1048576);
+        assert_eq!(config.checksum_cache_ttl, Duration::from_secs(30));
+        assert_eq!(config.timeouts, Timeouts {
+            initial_rtt: Duration::from_millis(500),
+            min_connection_timeout: Duration::from_secs(10),
+            min_path_cache_timeout: Duration::from_millis(2500),
+            max_persist_timeout: Duration::from_secs(30),
+        });
+    }
+
+    #[test]
+    fn invalid() {
+        let invalid = [
+            "",
+            "served_dir = \"/srv/soft\"\nmax_conections = 10",
+            "served_dir = \"/srv/soft\"\n[limits]\nmax_connections = 0",
+            "served_dir = \"/srv/soft\"\nshards = 0",
+            "served_dir = \"/srv/soft\"\n[timeouts]\ninitial_rtt = -1.0",
+            "served_dir = \"/srv/soft\"\n[timeouts]\nmax_persist_timeout = 0",
+            "served_dir = \"/srv/soft\"\n[timeouts]\nmin_connection_timeout = 0.0",
+            "served_dir = \"/srv/soft\"\nacl = [\"allow\"]",
+            "served_dir = \"/srv/soft\"\nsymlinks = \"always\"",
+            "served_dir = \"/srv/soft\"\nmounts = [\"lab=/data/lab\", \"lab=/data/other\"]",
+        ];
+        for text in invalid {
+            assert_eq!(ServerConfig::from_toml(text).err().map(|e| e.kind()), Some(ErrorKind::InvalidData), "{}", text);
+        }
+    }
+}
@@ -1,46 +1,56 @@
 use tokio::sync::mpsc::Sender;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
 use log::{debug, trace};
 use std::sync::Arc;
 use soft_shared_lib::packet::acc_packet::AccPacket;
-use soft_shared_lib::field_types::{ConnectionId, SequenceNumber, MaxPacketSize};
+use soft_shared_lib::field_types::{ConnectionId, SequenceNumber, MaxPacketSize, RetryAfter};
 use soft_shared_lib::general::byte_view::ByteView;
-use tokio::io::{BufReader, AsyncSeekExt, SeekFrom, AsyncReadExt};
-use crate::path_cache::{PathCache, CongestionWindow};
-use crate::checksum_cache::ChecksumCache;
+use crate::path_cache::{PathCache, CongestionWindow, CongestionState};
+use crate::checksum_cache::{ChecksumCache, ChecksumState};
+use crate::file_source::{FileIdentity, FileSource, SourceFile};
+use crate::snapshot_store::SnapshotState;
 use tokio::task::JoinHandle;
-use soft_shared_lib::{error, times};
+use soft_shared_lib::error;
 use soft_shared_lib::error::ErrorType;
 use crate::file_sandbox::FileSandbox;
 use soft_shared_lib::packet::err_packet::ErrPacket;
-use soft_shared_lib::soft_error_code::SoftErrorCode::{FileNotFound, InvalidOffset, Internal, ChecksumNotReady};
-use crate::server::FILE_READER_BUFFER_SIZE;
-use soft_shared_lib::packet::packet_buf::{PacketBuf, DataPacketBuf};
-use soft_shared_lib::error::ErrorType::{IOError, Eof};
+use soft_shared_lib::soft_error_code::SoftErrorCode::{self, FileNotFound, InvalidOffset, Internal, ChecksumNotReady, FileChanged, Stop};
+use soft_shared_lib::packet::packet_buf::PacketBuf;
+use soft_shared_lib::error::ErrorType::Eof;
 use soft_shared_lib::packet::ack_packet::AckPacket;
 use std::ops::Deref;
-use tokio::sync::{Mutex};
 use tokio::time::Instant;
-use soft_shared_lib::times::{connection_timeout, INITIAL_RTT};
 use std::net::SocketAddr;
 use std::ops::Range;
 use soft_shared_lib::helper::range_helper::{compare_range, RangeCompare};
 use crate::send_buffer::SendBuffer;
 use std::time::Duration;
-use tokio::fs::File;
 use std::cmp::{min, max};
 use soft_shared_lib::packet::data_packet::DataPacket;
 use soft_shared_lib::packet::packet::Packet;
-use std::io::Write;
 use soft_shared_lib::packet::req_packet::ReqPacket;
 use soft_shared_lib::constants::SOFT_MAX_PACKET_SIZE;
-use std::sync::atomic::AtomicU16;
-use std::sync::atomic::Ordering::SeqCst;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
 use soft_shared_async_lib::general::loss_simulation_udp_socket::LossSimulationUdpSocket;
 use std::convert::TryFrom;
+use crate::ledbat::Ledbat;
+use crate::pacer::Pacer;
+use crate::new_reno::{NewReno, NewAck};
+use crate::stats::{ConnectionStats, TransferCounters};
+use crate::observer::{ServerObserver, TransferInfo};
+use crate::server_config::{CachedConfig, SharedConfig};
+use crate::connection_table::ConnectionPermit;
+use crate::buffer_pool::BufferPool;
+use soft_shared_lib::helper::socket_helper::SendBatch;
+use soft_shared_lib::packet::unchecked_packet::UncheckedPacket;
 
 //TODO replace with non blocking replacing channel
-const PACKET_CHANNEL_SIZE: usize = 20;
+const EVENT_CHANNEL_SIZE: usize = 20;
+const FILE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// the maximum number of new DATA packets that are sent with one system call
+const SEND_BATCH_SIZE: u64 = 64;
 
 /// like normal SequenceNumber
 ///
@@ -49,31 +59,47 @@ const PACKET_CHANNEL_SIZE: usize = 20;
 /// meaningful negative values are used as initial values
 type InternalSequenceNumber = i128;
 
+/// the parts of the server that all connections share
+#[derive(Clone)]
+pub(crate) struct ConnectionContext {
+    pub socket: Arc<LossSimulationUdpSocket>,
+    pub path_cache: Arc<PathCache>,
+    pub checksum_cache: Arc<ChecksumCache>,
+    /// sent packets and received ACKs are also counted in the server counters
+    pub server_counters: Arc<TransferCounters>,
+    /// notified about the request and the events of the transfer
+    pub observer: Arc<dyn ServerObserver>,
+    pub config: Arc<SharedConfig>,
+    /// the buffers of the received packets are returned after handling them
+    pub receive_buffers: Arc<BufferPool>,
+}
+
+/// the events a connection task handles, in the order they are sent
+pub(crate) enum ConnectionEvent {
+    /// a packet of the client, received by the server
+    Packet(PacketBuf, SocketAddr),
+    /// the server stops the transfer, see Connection::stop
+    Stop,
+    /// the task answers with the current state of the connection, see Connection::snapshot
+    Snapshot(oneshot::Sender<ConnectionSnapshot>),
+}
+
+/// the state of a connection at one instant
+#[derive(Debug, Clone)]
+pub struct ConnectionSnapshot {
+    pub stats: ConnectionStats,
+    /// the number of packets that may be in flight, see ConnectionState::max_window
+    pub max_window: u16,
+}
+
+/// the handle of a connection
+///
+/// the state of the transfer is owned by a single task, the handle passes events to it
 pub struct Connection {
     pub connection_id: ConnectionId,
-    pub socket: Arc<LossSimulationUdpSocket>,
-    pub packet_sender: Sender<(PacketBuf, SocketAddr)>,
-    path_cache: Arc<PathCache>,
-    connection_timeout: Mutex<Instant>,
-    client_addr: Mutex<SocketAddr>,
-    /// -1 if no ACK packet has been received yet
-    last_forward_acknowledgement: Mutex<InternalSequenceNumber>,
-    /// -1 if no Data packet has been sent yet
-    last_packet_sent: Mutex<InternalSequenceNumber>,
-    packet_loss_timeout: Mutex<Instant>,
-    /// same size as ReceiveWindow,
-    client_receive_window: AtomicU16,
-    data_send_buffer: Mutex<SendBuffer>,
-    /// None in the beginning, Some after the handshake
-    reader: Mutex<BufReader<File>>,
-    max_packet_size: MaxPacketSize,
-    /// The instant when a data packet is sent
-    ///
-    /// these samples are used to calculate the rtt
-    ///
-    /// SequenceNumber -1 is the instant when the ACC packet is sent
-    data_send_instant_sample: Mutex<(InternalSequenceNumber, Instant)>,
-    filesize: u64,
+    event_sender: Sender<ConnectionEvent>,
+    /// the current rtt in microseconds, published by the task, so the server can refresh the ttl without a round trip
+    rtt: Arc<AtomicU64>,
 }
 
 impl Connection {
@@ -82,15 +108,27 @@ impl Connection {
     ///
     /// a connection instance handles the complete file transfer logic with one client
     ///
-    /// received packets have to be passed to the packet_sender channel
+    /// received packets have to be passed to the connection with send_packet,
+    /// the transfer ends if the connection is dropped
     ///
     /// fails if request is invalid or file is not found
-    pub async fn new(connection_id: ConnectionId, req: &ReqPacket, src_addr: SocketAddr, socket: Arc<LossSimulationUdpSocket>, congestion_cache: Arc<PathCache>, checksum_cache: Arc<ChecksumCache>, file_sandbox: &FileSandbox) -> error::Result<Arc<Connection>> {
-        let (packet_sender, packet_receiver) = tokio::sync::mpsc::channel(PACKET_CHANNEL_SIZE);
-
-        let file = match file_sandbox.get_file(req.file_name()).await {
-            Ok(file) => file,
+    ///
+    /// scavenger connections yield to other traffic
+    ///
+    /// the connection uses the shared parts of the server, see ConnectionContext
+    ///
+    /// the permit is released when the transfer ends
+    pub(crate) async fn new(connection_id: ConnectionId, req: &ReqPacket, src_addr: SocketAddr, context: &ConnectionContext, file_sandbox: &FileSandbox, scavenger: bool, permit: ConnectionPermit) -> error::Result<Arc<Connection>> {
+        let ConnectionContext { socket, path_cache, checksum_cache, server_counters, observer, config, receive_buffers } = context.clone();
+        let served_file = match file_sandbox.open(&req.file_name(), req.resume_checksum()).await {
+            Ok(SnapshotState::Ready(served_file)) => served_file,
+            Ok(SnapshotState::Pending(estimated_remaining)) => {
+                // the checksum is generated after the snapshot has been taken or the source has prepared the file
+                Self::send_checksum_not_ready(&socket, src_addr, estimated_remaining).await?;
+                return Err(ErrorType::ChecksumNotReady);
+            }
             Err(e) => {
+                observer.on_rejected(src_addr, &req.file_name(), req.offset(), FileNotFound);
                 let err = ErrPacket::new_buf(FileNotFound, 0);
                 socket.send_to(err.buf(), src_addr).await?;
                 trace!("sent {} to {}", &err, src_addr);
@@ -98,113 +136,297 @@ impl Connection {
             }
         };
 
-        let file_size = file.metadata().await?.len();
+        let file = served_file.file.clone();
+        let file_identity = file.identity().await?;
+        let file_size = file_identity.size();
         if req.offset() >= file_size {
+            observer.on_rejected(src_addr, &req.file_name(), req.offset(), InvalidOffset);
             let err = ErrPacket::new_buf(InvalidOffset, 0);
             socket.send_to(err.buf(), src_addr).await?;
             trace!("sent {} to {}", &err, src_addr);
             return Err(ErrorType::InvalidRequest);
         }
 
-        let checksum = if let Some(checksum) = checksum_cache.get_checksum(&req.file_name(), file.try_clone().await.unwrap()).await {
-            checksum
-        } else {
-            let err = ErrPacket::new_buf(ChecksumNotReady, 0);
-            socket.send_to(err.buf(), src_addr).await?;
-            trace!("sent {} to {}", &err, src_addr);
-            return Err(error::ErrorType::ChecksumNotReady);
+        let version = served_file.version;
+        let checksum_state = match served_file.checksum {
+            Some(checksum) => ChecksumState::Ready(checksum),
+            None => checksum_cache.get_checksum_of_version(&req.file_name(), version, served_file.file).await?,
+        };
+        let checksum = match checksum_state {
+            ChecksumState::Ready(checksum) => {
+                file_sandbox.set_checksum(&req.file_name(), version, checksum);
+                checksum
+            }
+            ChecksumState::Pending(_, estimated_remaining) => {
+                Self::send_checksum_not_ready(&socket, src_addr, estimated_remaining).await?;
+                return Err(ErrorType::ChecksumNotReady);
+            }
         };
 
-        let mut reader = BufReader::with_capacity(FILE_READER_BUFFER_SIZE, file);
-
-        // set file pointer to offset
-        if let std::io::Result::Err(e) = reader.seek(SeekFrom::Start(req.offset())).await {
-            let err = ErrPacket::new_buf(Internal, 0);
-            socket.send_to(err.buf(), src_addr).await?;
-            trace!("sent {} to {}", &err, src_addr);
-            return Err(IOError(e));
-        }
-
-        debug!("new connection {{ connection_id: {}, src_addr: {} }}", connection_id, src_addr);
+        debug!("new connection {{ connection_id: {}, src_addr: {}, scavenger: {} }}", connection_id, src_addr, scavenger);
         let acc = AccPacket::new_buf(connection_id, file_size, checksum);
         socket.send_to(acc.buf(), src_addr).await?;
         trace!("sent {} to {}", &acc, src_addr);
         let acc_send_instant = Instant::now();
 
-        let connection = Arc::new(Connection {
+        let (event_sender, event_receiver) = tokio::sync::mpsc::channel(EVENT_CHANNEL_SIZE);
+        let path = path_cache.get(src_addr);
+        let rtt = Arc::new(AtomicU64::new(path.current_rtt().as_micros() as u64));
+        let mut config = config.cached();
+        let timeouts = config.get().timeouts;
+        let state = ConnectionState {
             connection_id,
             socket,
-            packet_sender,
-            path_cache: congestion_cache,
-            connection_timeout: Mutex::new(Instant::now() + connection_timeout(INITIAL_RTT)),
-            client_addr: Mutex::new(src_addr),
-            last_forward_acknowledgement: Mutex::new(-1),
-            last_packet_sent: Mutex::new(-1),
-            packet_loss_timeout: Mutex::new(Instant::now()),
-            client_receive_window: AtomicU16::new(0),
-            data_send_buffer: Mutex::new(SendBuffer::new()),
-            filesize: reader.get_ref().metadata().await.unwrap().len(),
-            reader: Mutex::new(reader),
+            path_cache,
+            path,
+            connection_timeout: Instant::now() + timeouts.connection_timeout(timeouts.initial_rtt),
+            client_addr: src_addr,
+            last_forward_acknowledgement: -1,
+            last_packet_sent: -1,
+            new_reno: NewReno::new(),
+            client_receive_window: 0,
+            ack_token: 0,
+            ce_count: 0,
+            window_probes: 0,
+            data_send_buffer: SendBuffer::new(),
+            filesize: file_size,
+            file,
+            read_offset: req.offset(),
+            file_source: served_file.source,
+            source_name: served_file.name,
+            file_identity,
+            last_file_check: Instant::now(),
             max_packet_size: min(req.max_packet_size(), SOFT_MAX_PACKET_SIZE as MaxPacketSize),
-            data_send_instant_sample: Mutex::new((-1, acc_send_instant)),
-        });
+            data_send_instant_sample: Some((-1, acc_send_instant)),
+            ledbat: if scavenger { Some(Ledbat::new()) } else { None },
+            pacer: Pacer::new(),
+            file_name: req.file_name(),
+            counters: TransferCounters::default(),
+            server_counters,
+            offset: req.offset(),
+            observer,
+            config,
+            published_rtt: rtt.clone(),
+            receive_buffers,
+            send_batch: SendBatch::default(),
+            _permit: permit,
+        };
+        state.observer.on_accepted(&state.transfer_info());
+
+        state.spawn(event_receiver);
+
+        Ok(Arc::new(Connection {
+            connection_id,
+            event_sender,
+            rtt,
+        }))
+    }
+
+    /// the client retries when the checksum is expected to be ready
+    async fn send_checksum_not_ready(socket: &LossSimulationUdpSocket, src_addr: SocketAddr, estimated_remaining: Duration) -> error::Result<()> {
+        let retry_after = estimated_remaining.as_millis().clamp(1, RetryAfter::MAX as u128) as RetryAfter;
+        let err = ErrPacket::new_buf_with_retry_after(ChecksumNotReady, 0, retry_after);
+        socket.send_to(err.buf(), src_addr).await?;
+        trace!("sent {} to {}", &err, src_addr);
+        Ok(())
+    }
 
-        connection.clone().spawn(packet_receiver);
+    /// pass a received packet to the connection task
+    pub async fn send_packet(&self, packet: PacketBuf, src_addr: SocketAddr) {
+        // the task might have stopped since the packet has been received
+        let _ = self.event_sender.send(ConnectionEvent::Packet(packet, src_addr)).await;
+    }
+
+    /// the rtt of the client at the last received packet
+    pub fn rtt(&self) -> Duration {
+        Duration::from_micros(self.rtt.load(Relaxed))
+    }
+
+    /// the current state of the connection
+    ///
+    /// None if the connection has been stopped
+    pub async fn snapshot(&self) -> Option<ConnectionSnapshot> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.event_sender.send(ConnectionEvent::Snapshot(reply_sender)).await.ok()?;
+        reply_receiver.await.ok()
+    }
+
+    /// true if connection is no longer active
+    ///
+    /// either because of a successful transfer or error
+    pub fn stopped(&self) -> bool {
+        self.event_sender.is_closed()
+    }
 
-        return Ok(connection);
+    /// stop the transfer, the client receives a STOP error and can resume the download later
+    pub async fn stop(&self) {
+        let _ = self.event_sender.send(ConnectionEvent::Stop).await;
     }
+}
+
+/// the state of a transfer, owned by the task of the connection
+struct ConnectionState {
+    connection_id: ConnectionId,
+    socket: Arc<LossSimulationUdpSocket>,
+    path_cache: Arc<PathCache>,
+    /// the congestion state of the path to the client, stored in the path cache on losses, rtt samples and when the task ends
+    path: CongestionState,
+    connection_timeout: Instant,
+    client_addr: SocketAddr,
+    /// -1 if no ACK packet has been received yet
+    last_forward_acknowledgement: InternalSequenceNumber,
+    /// -1 if no Data packet has been sent yet
+    last_packet_sent: InternalSequenceNumber,
+    new_reno: NewReno,
+    /// same size as ReceiveWindow,
+    client_receive_window: u16,
+    /// token of the latest received ACK packet
+    ///
+    /// echoed in all sent DATA packets, so the client can measure the RTT
+    ack_token: u16,
+    /// the latest CE count of the client
+    ce_count: u32,
+    /// number of window probes sent since the client receive window has been closed
+    window_probes: u32,
+    data_send_buffer: SendBuffer,
+    file: Arc<dyn SourceFile>,
+    /// the offset of the next DATA packet
+    read_offset: u64,
+    /// the source and name of the file, to detect if it is replaced
+    file_source: Arc<dyn FileSource>,
+    source_name: String,
+    /// the served version of the file, the checksum in the ACC packet belongs to this version
+    file_identity: FileIdentity,
+    /// the file is checked for modifications at most once per FILE_CHECK_INTERVAL while it is read
+    last_file_check: Instant,
+    max_packet_size: MaxPacketSize,
+    /// The instant when a data packet is sent
+    ///
+    /// these samples are used to calculate the rtt
+    ///
+    /// SequenceNumber -1 is the instant when the ACC packet is sent
+    ///
+    /// None if no sample is pending,
+    /// retransmitted packets are never sampled (Karn's algorithm)
+    data_send_instant_sample: Option<(InternalSequenceNumber, Instant)>,
+    filesize: u64,
+    /// Some if the connection is a low priority scavenger transfer
+    ///
+    /// scavenger connections use LEDBAT instead of the path cache congestion window
+    ledbat: Option<Ledbat>,
+    pacer: Pacer,
+    /// the requested file name
+    file_name: String,
+    counters: TransferCounters,
+    /// counters of all connections of the server
+    server_counters: Arc<TransferCounters>,
+    /// the offset the client requested the file from
+    offset: u64,
+    observer: Arc<dyn ServerObserver>,
+    /// the timeouts are read from the current config, so reloaded timeouts apply to running transfers
+    config: CachedConfig,
+    /// see Connection::rtt
+    published_rtt: Arc<AtomicU64>,
+    /// see ConnectionContext::receive_buffers
+    receive_buffers: Arc<BufferPool>,
+    /// the message headers of send_new_packets
+    send_batch: SendBatch,
+    /// released when the task ends
+    _permit: ConnectionPermit,
+}
+
+impl ConnectionState {
 
     /// spawn ACK DATA routine in own tokio task
-    fn spawn(self: Arc<Self>, mut packet_receiver: Receiver<(PacketBuf, SocketAddr)>) -> JoinHandle<error::Result<()>> {
+    fn spawn(mut self, mut event_receiver: Receiver<ConnectionEvent>) -> JoinHandle<error::Result<()>> {
         tokio::spawn(async move {
+            let mut retransmission_deadline = Instant::now() + self.timer_timeout();
+            // Some if sending is delayed by the pacer
+            let mut pacing_deadline: Option<Instant> = None;
             loop {
-                match tokio::time::timeout(times::data_packet_retransmission_timeout(self.rtt().await), packet_receiver.recv()).await {
-                    Ok(packet) => {
-                        match packet {
-                            Some((PacketBuf::Ack(ack), src_addr)) => {
-                                self.handle_ack(ack.deref(), src_addr).await;
-                                if self.transfer_finished().await {
+                let mut window_probe = false;
+                tokio::select! {
+                    event = event_receiver.recv() => {
+                        match event {
+                            Some(ConnectionEvent::Packet(PacketBuf::Ack(ack), src_addr)) => {
+                                let result = self.handle_ack(ack.deref(), src_addr).await;
+                                self.receive_buffers.give(ack.into());
+                                if let Err(e) = result {
+                                    self.close_on_error(e).await?;
+                                    break;
+                                }
+                                self.published_rtt.store(self.rtt().as_micros() as u64, Relaxed);
+                                if self.transfer_finished() {
                                     debug!("transfer finished, close connection {}", self.connection_id);
+                                    self.observer.on_finished(&self.transfer_info());
                                     break;
                                 }
                             },
-                            Some((PacketBuf::Err(_), _)) => {
+                            Some(ConnectionEvent::Packet(PacketBuf::Err(err), _)) => {
                                 debug!("close connection {}", self.connection_id);
+                                self.observer.on_failed(&self.transfer_info(), err.error_code());
                                 break;
                             },
-                            Some((_,_)) => {
+                            Some(ConnectionEvent::Packet(_, _)) => {
                                 debug!("unexpected packet, close connection {}", self.connection_id);
                             }
+                            Some(ConnectionEvent::Stop) => {
+                                debug!("server stopped connection {}", self.connection_id);
+                                self.observer.on_failed(&self.transfer_info(), Stop);
+                                self.send_error(Stop).await?;
+                                break;
+                            }
+                            Some(ConnectionEvent::Snapshot(reply_sender)) => {
+                                let _ = reply_sender.send(self.snapshot());
+                                continue;
+                            }
                             None => {
-                                // packet_receiver channel has been closed
+                                // event channel has been closed, the server has dropped the expired connection
                                 debug!("close connection {}", self.connection_id);
+                                self.observer.on_timed_out(&self.transfer_info());
                                 break;
                             }
                         }
+                        retransmission_deadline = Instant::now() + self.timer_timeout();
                     }
-                    Err(_) => {
-                        // timeout
-                        if Instant::now() > *self.connection_timeout.lock().await {
+                    _ = tokio::time::sleep_until(retransmission_deadline) => {
+                        if Instant::now() > self.connection_timeout {
                             // connection timeout
                             debug!("connection timeout, close connection {}", self.connection_id);
+                            self.observer.on_timed_out(&self.transfer_info());
                             break;
+                        } else if self.zero_window() {
+                            // persist timeout, the window update of the client might have been lost
+                            debug!("persist timeout on connection {}", self.connection_id);
+                            window_probe = true;
                         } else {
                             // retransmission timout
                             debug!("retransmission timeout on connection {}", self.connection_id);
-                            self.reset_congestion_window().await;
+                            self.reset_congestion_window();
+                            self.path.back_off_retransmission_timeout();
+                            self.path_cache.store(self.client_addr, &self.path);
+                            self.new_reno.on_timeout(SequenceNumber::try_from(self.last_packet_sent).ok());
                             // reduce in flight packets to trigger retransmission
-                            *self.last_packet_sent.lock().await = max(self.last_packet_acknowledged().await, -1);
+                            self.last_packet_sent = max(self.last_packet_acknowledged(), -1);
+                            self.data_send_instant_sample = None;
                         }
+                        retransmission_deadline = Instant::now() + self.timer_timeout();
+                    }
+                    _ = tokio::time::sleep_until(pacing_deadline.unwrap_or(retransmission_deadline)), if pacing_deadline.is_some() => {
+                        // continue sending
                     }
                 };
-                match self.send_data().await {
-                    Ok(_) => {}
+                let result = if window_probe {
+                    self.send_window_probe().await.map(|_| None)
+                } else {
+                    self.send_data().await
+                };
+                match result {
+                    Ok(deadline) => {
+                        pacing_deadline = deadline;
+                    }
                     Err(e) => {
-                        log::error!("failed to send data, caused by: {}", e);
-                        let client_addr = (*self.client_addr.lock().await).clone();
-                        let err = ErrPacket::new_buf(Internal, self.connection_id);
-                        self.socket.send_to(err.buf(), client_addr).await?;
-                        trace!("sent {} to {}", &err, client_addr);
+                        self.close_on_error(e).await?;
                         break;
                     }
                 };
@@ -213,125 +435,276 @@ impl Connection {
         })
     }
 
-    async fn handle_ack(&self, ack: &AckPacket, src_addr: SocketAddr) {
-        self.reset_connection_timeout().await;
-        {
-            let mut client_addr = self.client_addr.lock().await;
-            if src_addr != *client_addr {
-                // migration
-                *client_addr = src_addr.clone();
-                debug!("connection {} migrated to {}", self.connection_id, src_addr);
-            }
+    /// the connection failed while sending data, the client is informed if possible
+    async fn close_on_error(&self, e: ErrorType) -> error::Result<()> {
+        let error_code = if let ErrorType::FileChanged = e {
+            debug!("file changed, close connection {}", self.connection_id);
+            FileChanged
+        } else {
+            log::error!("failed to send data, caused by: {}", e);
+            Internal
+        };
+        self.observer.on_failed(&self.transfer_info(), error_code);
+        self.send_error(error_code).await
+    }
+
+    /// close the connection on the client side
+    async fn send_error(&self, error_code: SoftErrorCode) -> error::Result<()> {
+        let err = ErrPacket::new_buf(error_code, self.connection_id);
+        self.socket.send_to(err.buf(), self.client_addr).await?;
+        trace!("sent {} to {}", &err, self.client_addr);
+        Ok(())
+    }
+
+    /// return Error if the connection should close and send Err packet
+    async fn handle_ack(&mut self, ack: &AckPacket, src_addr: SocketAddr) -> error::Result<()> {
+        self.reset_connection_timeout();
+        self.ack_token = ack.token();
+        if ack.receive_window() > 0 {
+            self.window_probes = 0;
+        }
+        let previous_addr = std::mem::replace(&mut self.client_addr, src_addr);
+        if src_addr != previous_addr {
+            debug!("connection {} migrated to {}", self.connection_id, src_addr);
+            // the new path has its own congestion state
+            self.path_cache.store(previous_addr, &self.path);
+            self.path = self.path_cache.get(src_addr);
+            self.observer.on_migrated(&self.transfer_info(), previous_addr);
         }
         let ack_next_sequence_number = ack.next_sequence_number();
-        let expected_forward_acks = self.expected_forward_acks().await;
+        if ack.ce_count() > self.ce_count {
+            self.ce_count = ack.ce_count();
+            self.on_congestion_experienced(ack_next_sequence_number);
+        }
+        let expected_forward_acks = self.expected_forward_acks();
         match compare_range(&expected_forward_acks, ack_next_sequence_number) {
             RangeCompare::LOWER => {
-                if ack_next_sequence_number == *(self.last_forward_acknowledgement.lock().await) as SequenceNumber {
+                if ack_next_sequence_number == self.last_forward_acknowledgement as SequenceNumber {
+                    let previous_receive_window = std::mem::replace(&mut self.client_receive_window, ack.receive_window());
+                    if (previous_receive_window == 0 && ack.receive_window() > 0) || self.in_flight_packets() == 0 {
+                        // reopened windows and ACKs without outstanding data do not indicate packet loss
+                        debug!("received window update {} for {}", ack.receive_window(), ack_next_sequence_number);
+                        return Ok(());
+                    }
                     debug!("detected duplicate acks {}", ack_next_sequence_number);
-                    if Instant::now() > *self.packet_loss_timeout.lock().await {
-                        // handle packet lost
-                        *self.packet_loss_timeout.lock().await = Instant::now() + times::packet_loss_timeout(self.rtt().await);
-                        self.decrease_congestion_window().await;
-                        // reduce in flight packets to trigger retransmission
-                        *self.last_packet_sent.lock().await = self.last_packet_acknowledged().await;
+                    self.counters.on_duplicate_ack();
+                    self.server_counters.on_duplicate_ack();
+                    if self.new_reno.on_duplicate_ack(ack_next_sequence_number, self.last_packet_sent as SequenceNumber) {
+                        // fast retransmit
+                        debug!("fast retransmit {} on connection {}", ack_next_sequence_number, self.connection_id);
+                        self.decrease_congestion_window();
+                        self.retransmit(ack_next_sequence_number).await?;
                     }
                 }
                 // ignore lower sequence numbers
             }
             RangeCompare::CONTAINED => {
                 // normal sequential ack
-                self.client_receive_window.store(ack.receive_window(), SeqCst);
-                *self.last_forward_acknowledgement.lock().await = ack_next_sequence_number as i128;
-                self.data_send_buffer.lock().await.drop_before(ack_next_sequence_number);
-                if ack_next_sequence_number != 0 {
-                    self.increase_congestion_window().await;
+                self.client_receive_window = ack.receive_window();
+                let newly_acknowledged = ack_next_sequence_number as i128 - self.last_forward_acknowledgement;
+                let newly_acknowledged = CongestionWindow::try_from(newly_acknowledged).unwrap_or(CongestionWindow::MAX);
+                self.last_forward_acknowledgement = ack_next_sequence_number as i128;
+                self.data_send_buffer.drop_before(ack_next_sequence_number);
+                // the client acknowledged packets that have been sent before a retransmission timeout
+                self.last_packet_sent = max(self.last_packet_sent, ack_next_sequence_number as i128 - 1);
+                match self.new_reno.on_new_ack(ack_next_sequence_number, newly_acknowledged) {
+                    NewAck::Normal => {
+                        if ack_next_sequence_number != 0 {
+                            self.increase_congestion_window();
+                        }
+                    }
+                    NewAck::Partial => {
+                        // the next packet is lost as well
+                        debug!("partial ack, retransmit {} on connection {}", ack_next_sequence_number, self.connection_id);
+                        self.retransmit(ack_next_sequence_number).await?;
+                    }
+                    NewAck::Full => {
+                        debug!("fast recovery finished on connection {}", self.connection_id);
+                    }
                 }
-                let data_send_instant_sample = self.data_send_instant_sample.lock().await;
-                if ack_next_sequence_number as i128 > (*data_send_instant_sample).0 {
-                    // update rtt
-                    let now = Instant::now();
-                    let rtt_sample = now - (*data_send_instant_sample).1;
-                    trace!("measured {:?} rtt for connection {}", rtt_sample, self.connection_id);
-                    self.apply_rtt_sample(rtt_sample).await;
+                if let Some((sequence_number, send_instant)) = self.data_send_instant_sample {
+                    if ack_next_sequence_number as i128 > sequence_number {
+                        // update rtt
+                        let rtt_sample = Instant::now() - send_instant;
+                        trace!("measured {:?} rtt for connection {}", rtt_sample, self.connection_id);
+                        self.apply_rtt_sample(rtt_sample);
+                        self.data_send_instant_sample = None;
+                    }
                 }
             }
             RangeCompare::HIGHER => {
                 // ignore, this might be caused by retransmission
             }
         }
+        Ok(())
+    }
+
+    /// the client has received DATA packets with the ECN codepoint CE
+    ///
+    /// handled like a packet loss without retransmission
+    fn on_congestion_experienced(&mut self, next_sequence_number: SequenceNumber) {
+        let last_packet_sent = max(self.last_packet_sent, 0) as SequenceNumber;
+        if self.new_reno.on_congestion_experienced(next_sequence_number, last_packet_sent) {
+            debug!("congestion experienced on connection {}", self.connection_id);
+            self.decrease_congestion_window();
+        }
     }
 
     /// send data packets until the effective window is 0 again
     ///
+    /// packets are paced over the RTT,
+    /// return the instant when sending should continue if the pacer delays the next packet
+    ///
     /// return Error if connection should close and send Err packet
-    async fn send_data(&self) -> error::Result<()> {
-        while self.effective_window().await > 0 {
-            let sequence_number = (*self.last_packet_sent.lock().await + 1) as SequenceNumber;
-            let mut data_send_buffer = self.data_send_buffer.lock().await;
-            if let Some(buf) = data_send_buffer.get(sequence_number) {
-                let client_addr = (*self.client_addr.lock().await).clone();
-                self.socket.send_to(&buf, client_addr).await.expect("failed to send packet");
-                trace!("sent {} to {}", Packet::from_buf(buf).unwrap(), client_addr);
-                *self.last_packet_sent.lock().await = sequence_number as i128;
-            } else {
-                match self.read_next_data_packet(sequence_number).await {
-                    Err(e) => {
-                        match e {
-                            Eof => {
-                                // ignore sequence number, and stop sending
-                                break
-                            }
-                            _ => {
-                                // unexpected error
-                                return Err(e);
-                            }
-                        }
-
-                    }
-                    Ok(packet) => {
-                        let client_addr = (*self.client_addr.lock().await).clone();
-                        self.socket.send_to(packet.buf(), client_addr).await.expect("failed to send packet");
-                        trace!("sent {} to {}", packet, client_addr);
-                        //TODO circumvent copy
-                        let send_buf = data_send_buffer.add();
-                        send_buf.write(packet.buf()).unwrap();
-                        *self.last_packet_sent.lock().await = sequence_number as i128;
-                    }
+    async fn send_data(&mut self) -> error::Result<Option<Instant>> {
+        while self.effective_window() > 0 {
+            let next_send_instant = self.pacer.next_send_instant(
+                self.congestion_window(),
+                self.rtt(),
+                self.is_slow_start()
+            );
+            if next_send_instant.is_some() {
+                self.send_new_packets().await?;
+                return Ok(next_send_instant);
+            }
+            match self.send_next_packet().await {
+                Ok(()) => {}
+                Err(Eof) => {
+                    // ignore sequence number, and stop sending
+                    break
+                }
+                Err(e) => {
+                    // unexpected error
+                    self.send_new_packets().await?;
+                    return Err(e);
                 }
             }
-            let mut data_send_instant_sample = self.data_send_instant_sample.lock().await;
-            if self.last_packet_acknowledged().await >= (*data_send_instant_sample).0 {
-                *data_send_instant_sample = (sequence_number as i128, Instant::now());
+            self.pacer.on_packet_sent();
+        }
+        self.send_new_packets().await?;
+        Ok(None)
+    }
+
+    /// send the first unacknowledged DATA packet although the client receive window is 0
+    ///
+    /// the client answers with an ACK that contains its current receive window
+    ///
+    /// return Error if connection should close and send Err packet
+    async fn send_window_probe(&mut self) -> error::Result<()> {
+        self.window_probes += 1;
+        // the probe might retransmit a packet in flight
+        self.last_packet_sent = self.last_packet_acknowledged();
+        self.data_send_instant_sample = None;
+        let result = self.send_next_packet().await;
+        self.send_new_packets().await?;
+        match result {
+            Err(Eof) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// send the DATA packet after the last sent packet, from the data send buffer or the file
+    ///
+    /// Eof if all bytes of the file have been sent
+    async fn send_next_packet(&mut self) -> error::Result<()> {
+        let sequence_number = (self.last_packet_sent + 1) as SequenceNumber;
+        if !self.send_buffered_packet(sequence_number).await? {
+            self.read_next_data_packet(sequence_number).await?;
+            if self.data_send_buffer.unsent() >= SEND_BATCH_SIZE {
+                self.send_new_packets().await?;
             }
         }
-        return Ok(());
+        self.last_packet_sent = sequence_number as i128;
+        Ok(())
+    }
+
+    /// send the DATA packets that have been added to the data send buffer since the last call in one batch
+    async fn send_new_packets(&mut self) -> error::Result<()> {
+        let (first_sequence_number, packets) = self.data_send_buffer.take_unsent();
+        if packets.is_empty() {
+            return Ok(());
+        }
+        self.socket.send_batch(&mut self.send_batch, &packets, self.client_addr).await?;
+        trace!("sent DATA packets {}..{} to {}", first_sequence_number, first_sequence_number + packets.len() as SequenceNumber, self.client_addr);
+        for packet in &packets {
+            self.counters.on_packet_sent(packet.len(), false);
+            self.server_counters.on_packet_sent(packet.len(), false);
+        }
+        // only sample packets that are sent for the first time
+        if self.data_send_instant_sample.is_none() {
+            self.data_send_instant_sample = Some((first_sequence_number as i128, Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// send a lost DATA packet again, without resending the following packets
+    async fn retransmit(&mut self, sequence_number: SequenceNumber) -> error::Result<()> {
+        self.send_buffered_packet(sequence_number).await?;
+        self.data_send_instant_sample = None;
+        Ok(())
+    }
+
+    /// send a DATA packet of the data send buffer with the current echo token
+    ///
+    /// false if the packet is not buffered
+    async fn send_buffered_packet(&mut self, sequence_number: SequenceNumber) -> error::Result<bool> {
+        let buf = match self.data_send_buffer.get(sequence_number) {
+            Some(buf) => buf,
+            None => return Ok(false),
+        };
+        UncheckedPacket::from_buf_mut(buf).set_echo_token(self.ack_token);
+        self.socket.send_to(buf, self.client_addr).await?;
+        trace!("sent {} to {}", Packet::from_buf(buf).unwrap(), self.client_addr);
+        self.counters.on_packet_sent(buf.len(), true);
+        self.server_counters.on_packet_sent(buf.len(), true);
+        Ok(true)
     }
 
-    /// Read next Data packet from file
+    /// Read next Data packet from file directly into the data send buffer
     ///
     /// Eof if file is read to end
-    async fn read_next_data_packet(&self, sequence_number: SequenceNumber) -> error::Result<DataPacketBuf> {
-        let max_data_size = self.max_packet_size - (DataPacket::get_required_buffer_size_without_data() as u16);
-        let mut tmp_buf = vec![0u8; max_data_size as usize];
-        let mut reader = self.reader.lock().await;
-        return match reader.read(&mut tmp_buf).await {
-            Ok(size) if size == 0 => {
-                Err(ErrorType::Eof)
+    async fn read_next_data_packet(&mut self, sequence_number: SequenceNumber) -> error::Result<()> {
+        if self.last_file_check.elapsed() >= FILE_CHECK_INTERVAL {
+            self.check_file().await?;
+            self.last_file_check = Instant::now();
+        }
+        let header_size = DataPacket::get_required_buffer_size_without_data();
+        let send_buf = self.data_send_buffer.add();
+        send_buf.resize(self.max_packet_size as usize, 0);
+        DataPacket::write_header(send_buf, self.connection_id, sequence_number);
+        UncheckedPacket::from_buf_mut(send_buf).set_echo_token(self.ack_token);
+        let result = self.file.read_at(&mut send_buf[header_size..], self.read_offset).await;
+        match result {
+            Ok(size) if size > 0 => {
+                send_buf.truncate(header_size + size);
+                self.read_offset += size as u64;
+                Ok(())
             }
-            Ok(size) => {
-                Ok(DataPacket::new_buf(self.connection_id, sequence_number, &tmp_buf[..size]))
+            Ok(_) => {
+                self.data_send_buffer.remove_last();
+                // the end might have been reached early, because the file has been truncated
+                self.check_file().await?;
+                Err(ErrorType::Eof)
             }
             Err(e) => {
+                self.data_send_buffer.remove_last();
                 Err(ErrorType::IOError(e))
             }
         }
     }
 
-    async fn reset_connection_timeout(&self) {
-        let rtt = self.rtt().await;
-        let mut connection_timeout = self.connection_timeout.lock().await;
-        *connection_timeout = Instant::now() + times::connection_timeout(rtt);
+    /// FileChanged if the file has been modified or replaced since the connection has been created
+    async fn check_file(&self) -> error::Result<()> {
+        let opened_identity = self.file.identity().await?;
+        match self.file_source.stat(&self.source_name).await {
+            Ok(current_identity) if current_identity == self.file_identity && opened_identity == self.file_identity => Ok(()),
+            _ => Err(ErrorType::FileChanged),
+        }
+    }
+
+    fn reset_connection_timeout(&mut self) {
+        let timeouts = self.config.get().timeouts;
+        self.connection_timeout = Instant::now() + timeouts.connection_timeout(self.rtt());
     }
 
     /// expected ACK packets to receive
@@ -339,10 +712,13 @@ impl Connection {
     /// packets below the range indicate required retransmission or should be ignored
     ///
     /// packets above the range are bad packets and should lead to an error
-    async fn expected_forward_acks(&self) -> Range<SequenceNumber> {
+    ///
+    /// packets sent before a retransmission timeout might still be acknowledged
+    fn expected_forward_acks(&self) -> Range<SequenceNumber> {
+        let end = max(self.last_packet_sent + 2, self.data_send_buffer.end() as i128 + 1);
         return Range{
-            start: (*(self.last_forward_acknowledgement.lock().await) + 1) as SequenceNumber,
-            end: (*(self.last_packet_sent.lock().await) + 2) as SequenceNumber,
+            start: (self.last_forward_acknowledgement + 1) as SequenceNumber,
+            end: end as SequenceNumber,
         }
     }
 
@@ -353,70 +729,156 @@ impl Connection {
     /// return -2 if no ACK has been received yet
     ///
     /// return -1 if ACK 0 is received
-    async fn last_packet_acknowledged(&self) -> i128 {
-        *self.last_forward_acknowledgement.lock().await - 1
+    fn last_packet_acknowledged(&self) -> i128 {
+        self.last_forward_acknowledgement - 1
     }
 
     /// only increase when congestion_window is smaller than receive_window
-    async fn increase_congestion_window(&self) {
-        let client_addr = *self.client_addr.lock().await;
-        if self.path_cache.congestion_window(client_addr) < self.client_receive_window.load(SeqCst) {
-            self.path_cache.increase_congestion_window(client_addr);
+    ///
+    /// LEDBAT is always informed, its delay based decrease applies to window limited transfers as well
+    fn increase_congestion_window(&mut self) {
+        if let Some(ledbat) = &mut self.ledbat {
+            ledbat.on_ack(self.client_receive_window);
+            return;
         }
+        if self.congestion_window() >= self.client_receive_window {
+            return;
+        }
+        self.path.increase_congestion_window();
     }
 
-    async fn decrease_congestion_window(&self) {
-        self.path_cache.decrease_congestion_window(*self.client_addr.lock().await);
+    fn decrease_congestion_window(&mut self) {
+        if let Some(ledbat) = &mut self.ledbat {
+            ledbat.on_loss();
+        } else {
+            self.path.decrease_congestion_window();
+            self.path_cache.store(self.client_addr, &self.path);
+        }
     }
 
-    async fn reset_congestion_window(&self) {
-        self.path_cache.reset_congestion_window(*self.client_addr.lock().await);
+    fn reset_congestion_window(&mut self) {
+        if let Some(ledbat) = &mut self.ledbat {
+            ledbat.on_timeout();
+        } else {
+            self.path.reset_congestion_window();
+            self.path_cache.store(self.client_addr, &self.path);
+        }
     }
 
-    pub async fn rtt(&self) -> Duration{
-        self.path_cache.current_rtt(*self.client_addr.lock().await)
+    fn rtt(&self) -> Duration{
+        self.path.current_rtt()
+    }
+
+    fn retransmission_timeout(&self) -> Duration{
+        self.path.retransmission_timeout()
+    }
+
+    /// the persist timeout if the client receive window is closed, the retransmission timeout otherwise
+    fn timer_timeout(&mut self) -> Duration {
+        if self.zero_window() {
+            let timeouts = self.config.get().timeouts;
+            timeouts.persist_timeout(self.retransmission_timeout(), self.window_probes)
+        } else {
+            self.retransmission_timeout()
+        }
+    }
+
+    /// true if the client has acknowledged the handshake, but can not receive more data
+    fn zero_window(&self) -> bool {
+        self.last_forward_acknowledgement >= 0 && self.client_receive_window == 0
     }
 
     /// true if all bytes have been read from the file
     ///
     /// there might still be packets in the data send buffer
-    async fn eof(&self) -> bool {
-        let mut reader = self.reader.lock().await;
-        reader.stream_position().await.unwrap() == self.filesize
+    fn eof(&self) -> bool {
+        self.read_offset == self.filesize
     }
 
     /// true if all bytes of the file are transferred and acknowledged by the client
-    async fn transfer_finished(&self) -> bool {
-        self.eof().await && (self.data_send_buffer.lock().await.len() == 0)
+    fn transfer_finished(&self) -> bool {
+        self.eof() && (self.data_send_buffer.len() == 0)
     }
 
-    async fn congestion_window(&self) -> CongestionWindow {
-        return self.path_cache.congestion_window(*self.client_addr.lock().await);
+    fn congestion_window(&self) -> CongestionWindow {
+        if let Some(ledbat) = &self.ledbat {
+            return ledbat.congestion_window();
+        }
+        self.path.congestion_window()
     }
 
-    pub async fn max_window(&self) -> u16 {
-        min(self.client_receive_window.load(SeqCst), self.congestion_window().await)
+    /// scavenger connections have no slow start phase
+    ///
+    /// fast recovery is never part of slow start
+    fn is_slow_start(&self) -> bool {
+        if self.ledbat.is_some() || self.new_reno.in_recovery() {
+            return false;
+        }
+        self.path.is_slow_start()
     }
 
-    async fn effective_window(&self) -> u16 {
-        let max_window = self.max_window().await;
-        let last_packet_sent = *self.last_packet_sent.lock().await;
-        let last_packet_acknowledged = self.last_packet_acknowledged().await;
-        let in_flight_packets = u16::try_from(last_packet_sent.saturating_sub(last_packet_acknowledged)).unwrap_or(u16::MAX);
-        return max_window.saturating_sub(in_flight_packets)
+    /// the congestion window is inflated during fast recovery
+    fn max_window(&self) -> u16 {
+        let congestion_window = self.congestion_window().saturating_add(self.new_reno.inflation());
+        min(self.client_receive_window, congestion_window)
     }
 
-    async fn apply_rtt_sample(&self, rtt_sample: Duration) {
-        self.path_cache.apply_rtt_sample(*self.client_addr.lock().await, rtt_sample);
+    fn effective_window(&self) -> u16 {
+        self.max_window().saturating_sub(self.in_flight_packets())
     }
 
-    /// true if connection is no longer active
-    ///
-    /// either because of a successful transfer or error
-    pub fn stopped(&self) -> bool {
-        self.packet_sender.is_closed()
+    /// number of sent packets that are not acknowledged yet
+    fn in_flight_packets(&self) -> u16 {
+        u16::try_from(self.last_packet_sent.saturating_sub(self.last_packet_acknowledged())).unwrap_or(u16::MAX)
     }
 
-}
+    fn apply_rtt_sample(&mut self, rtt_sample: Duration) {
+        self.path.apply_rtt_sample(rtt_sample);
+        self.path_cache.store(self.client_addr, &self.path);
+        if let Some(ledbat) = &mut self.ledbat {
+            ledbat.apply_rtt_sample(rtt_sample);
+        }
+    }
+
+    fn snapshot(&self) -> ConnectionSnapshot {
+        ConnectionSnapshot {
+            stats: ConnectionStats {
+                connection_id: self.connection_id,
+                client_addr: self.client_addr,
+                file_name: self.file_name.clone(),
+                file_size: self.filesize,
+                bytes_sent: self.counters.bytes_sent(),
+                retransmissions: self.counters.retransmissions(),
+                duplicate_acks: self.counters.duplicate_acks(),
+                congestion_window: self.congestion_window(),
+                rtt: self.rtt(),
+                scavenger: self.ledbat.is_some(),
+            },
+            max_window: self.max_window(),
+        }
+    }
+
+    fn transfer_info(&self) -> TransferInfo {
+        let max_data_size = self.max_packet_size as u64 - DataPacket::get_required_buffer_size_without_data() as u64;
+        // ACKs contain the number of received packets
+        let acknowledged_packets = max(self.last_forward_acknowledgement, 0) as u64;
+        TransferInfo {
+            connection_id: self.connection_id,
+            peer: self.client_addr,
+            file_name: self.file_name.clone(),
+            file_size: self.filesize,
+            offset: self.offset,
+            bytes_sent: self.counters.bytes_sent(),
+            retransmissions: self.counters.retransmissions(),
+            bytes_acknowledged: min(acknowledged_packets.saturating_mul(max_data_size), self.filesize - self.offset),
+        }
+    }
 
+}
 
+impl Drop for ConnectionState {
+    /// the next connection of the client starts with the congestion state of this one
+    fn drop(&mut self) {
+        self.path_cache.store(self.client_addr, &self.path);
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use rand::Rng;
+use tokio::sync::Mutex;
+use ttl_cache::TtlCache;
+use soft_shared_lib::field_types::ConnectionId;
+use crate::connection::Connection;
+
+pub(crate) type Shard = Mutex<TtlCache<ConnectionId, Arc<Connection>>>;
+
+/// the connections of the server, with one shard per receive loop, see ServerConfig::shards
+///
+/// a connection belongs to the shard that received its REQ,
+/// the index of the shard is encoded in the connection id,
+/// so the packets of a client that migrated to another address find the connection,
+/// even if they are received by the socket of another shard
+///
+/// the maximum number of connections applies to all shards together,
+/// requests are rejected at the maximum instead of evicting running transfers, see try_reserve
+pub(crate) struct ConnectionTable {
+    shards: Vec<Shard>,
+    max_connections: usize,
+    /// the connections whose tasks are running, see ConnectionPermit
+    active: Arc<AtomicUsize>,
+}
+
+/// counts a connection against the maximum number of connections until it is dropped
+pub(crate) struct ConnectionPermit(Arc<AtomicUsize>);
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, SeqCst);
+    }
+}
+
+impl ConnectionTable {
+
+    pub fn new(shard_count: usize, max_connections: usize) -> Self {
+        Self {
+            // a shard only holds more than the active connections if stopped connections have not expired yet
+            shards: (0..shard_count).map(|_| Mutex::new(TtlCache::new(max_connections))).collect(),
+            max_connections,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// None if the server already has the maximum number of connections
+    pub fn try_reserve(&self) -> Option<ConnectionPermit> {
+        self.active.fetch_update(SeqCst, SeqCst, |active| (active < self.max_connections).then_some(active + 1)).ok()?;
+        Some(ConnectionPermit(self.active.clone()))
+    }
+
+    /// removes the stopped connections, so a new connection never evicts a running one
+    pub fn remove_stopped(shard: &mut TtlCache<ConnectionId, Arc<Connection>>) {
+        let stopped: Vec<ConnectionId> = shard.iter()
+            .filter(|(_, connection)| connection.stopped())
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        for connection_id in stopped {
+            shard.remove(&connection_id);
+        }
+    }
+
+    pub fn shard(&self, index: usize) -> &Shard {
+        &self.shards[index]
+    }
+
+    /// the shard that owns the connection
+    pub fn shard_of(&self, connection_id: ConnectionId) -> &Shard {
+        &self.shards[connection_id as usize % self.shards.len()]
+    }
+
+    /// a connection id that is not used in the shard and that belongs to it
+    pub fn generate_connection_id(&self, shard: &TtlCache<ConnectionId, Arc<Connection>>, index: usize) -> ConnectionId {
+        let shard_count = self.shards.len() as ConnectionId;
+        let mut rng = rand::thread_rng();
+        loop {
+            let connection_id = rng.gen_range(0..ConnectionId::MAX / shard_count) * shard_count + index as ConnectionId;
+            if !shard.contains_key(&connection_id) {
+                return connection_id;
+            }
+        }
+    }
+
+    /// the connections of all shards that have not been stopped
+    pub async fn active_connections(&self) -> Vec<Arc<Connection>> {
+        let mut active = Vec::new();
+        for shard in &self.shards {
+            let mut connections = shard.lock().await;
+            active.extend(connections.iter()
+                .map(|(_, connection)| connection.clone())
+                .filter(|connection| !connection.stopped()));
+        }
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use crate::connection_table::ConnectionTable;
+
+    #[tokio::test]
+    async fn routing() {
+        let table = ConnectionTable::new(3, 100);
+        for index in 0..3 {
+            let shard = table.shard(index).lock().await;
+            for _ in 0..100 {
+                let connection_id = table.generate_connection_id(&shard, index);
+                assert!(ptr::eq(table.shard_of(connection_id), table.shard(index)));
+            }
+        }
+    }
+
+    #[test]
+    fn max_connections() {
+        let table = ConnectionTable::new(3, 2);
+        let first = table.try_reserve().unwrap();
+        let _second = table.try_reserve().unwrap();
+        assert!(table.try_reserve().is_none());
+        // a stopped connection releases its permit
+        drop(first);
+        assert!(table.try_reserve().is_some());
+    }
+}
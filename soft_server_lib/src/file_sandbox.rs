@@ -1,34 +1,192 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use soft_shared_lib::error;
-use tokio::fs::File;
+use soft_shared_lib::field_types::Checksum;
 use soft_shared_lib::error::ErrorType;
+use crate::file_source::{estimated_remaining, FileIdentity, FileSource, SourceFile};
+use crate::snapshot_store::{SnapshotStore, SnapshotState};
+use crate::mount_table::MountTable;
+
+/// the opened version of a requested file
+pub struct ServedFile {
+    pub file: Arc<dyn SourceFile>,
+    /// the source and name the file has been opened from, to detect if it is replaced
+    ///
+    /// the snapshot store, if snapshots are served
+    pub source: Arc<dyn FileSource>,
+    pub name: String,
+    /// the identity of the requested file, a snapshot has its own identity
+    pub version: FileIdentity,
+    /// known if a retained snapshot has been requested
+    pub checksum: Option<Checksum>,
+}
 
 pub struct FileSandbox {
-    served_dir: PathBuf
+    /// replaced by Server::reload if the symbolic links are resolved differently
+    source: RwLock<Arc<dyn FileSource>>,
+    /// the mounted directories the file names are resolved in
+    mount_table: Arc<MountTable>,
+    /// files and directories of the server itself, e.g. the checksum store
+    hidden_files: Vec<PathBuf>,
+    /// false to hide files and directories whose name starts with a dot
+    serve_dot_files: bool,
+    /// None if files are served directly
+    snapshot_store: Option<Arc<SnapshotStore>>,
 }
 
 /// allow only access to files from the specified served directory
 impl FileSandbox {
 
-    pub fn new(served_dir: PathBuf) -> Self {
-        FileSandbox { served_dir }
+    pub fn new(source: Arc<dyn FileSource>, mount_table: Arc<MountTable>, hidden_files: Vec<PathBuf>, serve_dot_files: bool, snapshot_store: Option<SnapshotStore>) -> Self {
+        FileSandbox { source: RwLock::new(source), mount_table, hidden_files, serve_dot_files, snapshot_store: snapshot_store.map(Arc::new) }
+    }
+
+    /// open the file to serve for a request
+    ///
+    /// if snapshots are enabled, the snapshot of the requested version is served,
+    /// or of the current version if the requested version is not retained
+    ///
+    /// files of read-only mounts are always served directly
+    pub async fn open(&self, file_name: &str, resume_checksum: Option<Checksum>) -> error::Result<SnapshotState> {
+        self.check(file_name)?;
+        let read_only = self.mount_table.resolve(file_name).is_some_and(|(mount, _)| mount.read_only);
+        let snapshot_store = match &self.snapshot_store {
+            Some(snapshot_store) if !read_only => snapshot_store,
+            _ => {
+                let file = match self.get_file(file_name).await {
+                    Ok(file) => file,
+                    Err(e) => return Self::pending_or(e),
+                };
+                return Ok(SnapshotState::Ready(ServedFile {
+                    version: file.identity().await?,
+                    file,
+                    source: self.source(),
+                    name: file_name.to_string(),
+                    checksum: None,
+                }));
+            }
+        };
+        // a retained snapshot can be resumed, even if the file has been removed in the meantime
+        if let Some(checksum) = resume_checksum {
+            if let Some(state) = snapshot_store.find(file_name, checksum).await {
+                return state;
+            }
+        }
+        let file = match self.get_file(file_name).await {
+            Ok(file) => file,
+            Err(e) => return Self::pending_or(e),
+        };
+        snapshot_store.clone().snapshot(file_name, file).await
+    }
+
+    /// a file that is not ready yet is retried like a pending snapshot, see file_source::NotReady
+    fn pending_or(e: ErrorType) -> error::Result<SnapshotState> {
+        if let ErrorType::IOError(io_error) = &e {
+            if let Some(estimated_remaining) = estimated_remaining(io_error) {
+                return Ok(SnapshotState::Pending(estimated_remaining));
+            }
+        }
+        Err(e)
+    }
+
+    /// remember the checksum of the snapshot, so its transfer can be resumed
+    pub fn set_checksum(&self, file_name: &str, version: FileIdentity, checksum: Checksum) {
+        if let Some(snapshot_store) = &self.snapshot_store {
+            snapshot_store.set_checksum(file_name, version, checksum);
+        }
     }
 
     /// only server files from the public directory
-    pub async fn get_file(&self, file_name: String) -> error::Result<File> {
-        if file_name.starts_with("/") {
+    pub async fn get_file(&self, file_name: &str) -> error::Result<Arc<dyn SourceFile>> {
+        self.check(file_name)?;
+        let source = self.source();
+        return Ok(Arc::from(source.open(file_name).await?));
+    }
+
+    fn source(&self) -> Arc<dyn FileSource> {
+        self.source.read().unwrap().clone()
+    }
+
+    /// new requests are served from the source, running transfers keep their source
+    pub fn replace_source(&self, source: Arc<dyn FileSource>) {
+        *self.source.write().unwrap() = source;
+    }
+
+    /// FileNotFound if the file name leaves the served directory or is hidden
+    ///
+    /// only the name is checked, symbolic links and special files are handled by the source
+    fn check(&self, file_name: &str) -> error::Result<()> {
+        let relative_path = Path::new(file_name);
+        // absolute paths, `..` and `.` are rejected
+        let normal = relative_path.components().all(|component| matches!(component, Component::Normal(_)));
+        if file_name.is_empty() || file_name.contains('\0') || !normal || file_name.split('/').any(|name| name == ".") {
             return Err(ErrorType::FileNotFound);
         }
-        if file_name.contains("..") {
+        if self.is_hidden(file_name) {
             return Err(ErrorType::FileNotFound);
         }
-        let path = self.served_dir.join(file_name);
-        if !path.starts_with(&self.served_dir){
-            return Err(ErrorType::FileNotFound);
+        Ok(())
+    }
+
+    pub fn mount_table(&self) -> &MountTable {
+        &self.mount_table
+    }
+
+    /// true if the file or directory is or is below a hidden file or a dot file
+    pub fn is_hidden(&self, file_name: &str) -> bool {
+        if let Some(path) = self.mount_table.local_path(file_name) {
+            if self.hidden_files.iter().any(|hidden_file| path.starts_with(hidden_file)) {
+                return true;
+            }
         }
-        if path.is_dir() {
-            return Err(ErrorType::FileNotFound);
+        !self.serve_dot_files && file_name.split('/').any(|name| name.starts_with('.'))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use soft_shared_lib::error::ErrorType;
+    use crate::checksum_store::ChecksumStore;
+    use crate::file_sandbox::FileSandbox;
+    use crate::file_source::MemoryFileSource;
+    use crate::mount_table::{Mount, MountTable};
+
+    fn new_sandbox(serve_dot_files: bool) -> FileSandbox {
+        let source = Arc::new(MemoryFileSource::new());
+        for file_name in ["hello.txt", "dir/hello.txt", "file..txt", ".hidden", "dir/.hidden/hello.txt", ".soft_checksums", ".soft_checksums.tmp"] {
+            source.insert(file_name, b"hello".to_vec());
         }
-        return Ok(File::open(path).await?);
+        let served_dir = PathBuf::from("/srv/soft");
+        let hidden_files = ChecksumStore::files(&served_dir.join(".soft_checksums")).to_vec();
+        let mount_table = Arc::new(MountTable::new(vec![Mount::new("", served_dir)]));
+        FileSandbox::new(source, mount_table, hidden_files, serve_dot_files, None)
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn file_names() {
+        let sandbox = new_sandbox(false);
+        for file_name in ["hello.txt", "dir/hello.txt", "file..txt"] {
+            assert!(sandbox.get_file(file_name).await.is_ok(), "{}", file_name);
+        }
+        let escapes = ["", "/etc/passwd", "../hello.txt", "dir/../hello.txt", "dir/../../etc/passwd", "./hello.txt", "dir/./hello.txt", "dir/..", "hello.txt\0"];
+        for file_name in escapes {
+            assert!(matches!(sandbox.get_file(file_name).await, Err(ErrorType::FileNotFound)), "{:?}", file_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn dot_files() {
+        let sandbox = new_sandbox(false);
+        for file_name in [".hidden", "dir/.hidden/hello.txt", ".soft_checksums"] {
+            assert!(matches!(sandbox.get_file(file_name).await, Err(ErrorType::FileNotFound)), "{}", file_name);
+        }
+        let sandbox = new_sandbox(true);
+        assert!(sandbox.get_file(".hidden").await.is_ok());
+        assert!(sandbox.get_file("dir/.hidden/hello.txt").await.is_ok());
+        // files of the server itself are always hidden
+        for file_name in [".soft_checksums", ".soft_checksums.tmp"] {
+            assert!(matches!(sandbox.get_file(file_name).await, Err(ErrorType::FileNotFound)), "{}", file_name);
+        }
+    }
+}
@@ -0,0 +1,584 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::Metadata;
+use std::io;
+use std::os::linux::fs::MetadataExt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::future::{self, BoxFuture, FutureExt};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader, SeekFrom};
+use tokio::sync::Mutex;
+use crate::server::FILE_READER_BUFFER_SIZE;
+
+/// identifies a version of a file
+///
+/// a checksum is only valid as long as the identity of the file does not change
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FileIdentity {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    inode: u64,
+}
+
+impl FileIdentity {
+
+    /// sources without modification times can use a version number, that changes with the content, as inode
+    pub fn new(size: u64, mtime: i64, mtime_nsec: i64, inode: u64) -> Self {
+        Self { size, mtime, mtime_nsec, inode }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn mtime(&self) -> i64 {
+        self.mtime
+    }
+
+    pub fn mtime_nsec(&self) -> i64 {
+        self.mtime_nsec
+    }
+
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+}
+
+impl From<&Metadata> for FileIdentity {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.st_size(),
+            mtime: metadata.st_mtime(),
+            mtime_nsec: metadata.st_mtime_nsec(),
+            inode: metadata.st_ino(),
+        }
+    }
+}
+
+/// the error of a source that prepares the file in the background, e.g. reads the index of an archive,
+/// with the estimated time until the file can be opened
+///
+/// the request is answered like a pending snapshot, with ChecksumNotReady and a retry time
+#[derive(Debug)]
+pub struct NotReady(pub Duration);
+
+impl std::fmt::Display for NotReady {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file not ready, retry after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for NotReady {}
+
+pub fn not_ready(estimated_remaining: Duration) -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, NotReady(estimated_remaining))
+}
+
+/// Some if the error is NotReady
+pub fn estimated_remaining(e: &io::Error) -> Option<Duration> {
+    e.get_ref()?.downcast_ref::<NotReady>().map(|not_ready| not_ready.0)
+}
+
+/// # File Source
+///
+/// provides the served files, e.g. the files of a local directory or in-memory blobs
+///
+/// file names are relative and have already been checked by the FileSandbox
+pub trait FileSource: Send + Sync {
+
+    /// NotFound if the file does not exist or is not a regular file,
+    /// NotReady if the file is prepared in the background
+    fn open<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<Box<dyn SourceFile>>>;
+
+    /// the identity of the file that is currently served under that name
+    ///
+    /// differs from the identity of an opened file, if the file has been replaced in the meantime
+    fn stat<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<FileIdentity>>;
+}
+
+/// an opened file of a FileSource
+pub trait SourceFile: Send + Sync {
+
+    /// the identity of the opened file, it changes if the file is modified
+    fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>>;
+
+    /// read from offset into buf, 0 at the end of the file
+    fn read_at<'a>(&'a self, buf: &'a mut [u8], offset: u64) -> BoxFuture<'a, io::Result<usize>>;
+
+    /// the file descriptor of a local file, so snapshots can be reflinked instead of copied
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// how symbolic links in the served directory are resolved
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum SymlinkPolicy {
+    /// files behind symbolic links are not served
+    Deny,
+    /// symbolic links are served if their target is in the served directory
+    #[default]
+    WithinRoot,
+    /// symbolic links are served wherever they point to
+    Follow,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny" => Ok(Self::Deny),
+            "within-root" => Ok(Self::WithinRoot),
+            "follow" => Ok(Self::Follow),
+            _ => Err(format!("unknown symlink policy {:?}", s)),
+        }
+    }
+}
+
+/// the maximum number of symbolic links that are followed to resolve a file name, like the limit of Linux
+const MAX_SYMLINKS: usize = 40;
+
+/// serves the regular files of a local directory
+///
+/// directories and special files, e.g. FIFOs, sockets and devices, are not found,
+/// symbolic links are resolved according to the symlink policy
+pub struct LocalFileSource {
+    dir: PathBuf,
+    /// the canonical path of the directory
+    root: PathBuf,
+    symlink_policy: SymlinkPolicy,
+    /// see ServerConfig::file_reader_buffer_size
+    reader_buffer_size: usize,
+}
+
+impl LocalFileSource {
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self::with_symlink_policy(dir, SymlinkPolicy::default())
+    }
+
+    pub fn with_symlink_policy(dir: PathBuf, symlink_policy: SymlinkPolicy) -> Self {
+        let root = std::fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+        Self { dir, root, symlink_policy, reader_buffer_size: FILE_READER_BUFFER_SIZE }
+    }
+
+    pub fn with_reader_buffer_size(self, reader_buffer_size: usize) -> Self {
+        Self { reader_buffer_size, ..self }
+    }
+
+    /// the parent directory, the name and the metadata of the regular file
+    ///
+    /// NotFound if the file is not served according to the symlink policy
+    fn resolve(&self, file_name: &str) -> io::Result<ResolvedFile> {
+        match self.symlink_policy {
+            SymlinkPolicy::Deny => self.resolve_beneath(file_name, false),
+            SymlinkPolicy::WithinRoot => self.resolve_beneath(file_name, true),
+            SymlinkPolicy::Follow => {
+                let path = self.dir.join(file_name);
+                let metadata = std::fs::metadata(&path)?;
+                match (path.parent(), path.file_name()) {
+                    (Some(parent), Some(name)) if metadata.is_file() => Ok(ResolvedFile {
+                        parent: open_at(None, parent.as_os_str(), libc::O_PATH | libc::O_DIRECTORY)?,
+                        name: name.to_os_string(),
+                        metadata,
+                    }),
+                    _ => Err(not_found("not a regular file")),
+                }
+            }
+        }
+    }
+
+    /// walks the file name component by component from a descriptor of the directory,
+    /// so neither symbolic links nor renamed directories can lead out of it between the checks
+    fn resolve_beneath(&self, file_name: &str, follow_symlinks: bool) -> io::Result<ResolvedFile> {
+        let mut dirs = vec![open_at(None, self.dir.as_os_str(), libc::O_PATH | libc::O_DIRECTORY)?];
+        let mut components = VecDeque::new();
+        push_components(&mut components, Path::new(file_name))?;
+        let mut symlinks = 0;
+        while let Some(name) = components.pop_front() {
+            if name == ".." {
+                if dirs.len() == 1 {
+                    return Err(not_found("leaves the served directory"));
+                }
+                dirs.pop();
+                continue;
+            }
+            let entry = open_at(dirs.last(), &name, libc::O_PATH | libc::O_NOFOLLOW)?;
+            let metadata = entry.metadata()?;
+            if metadata.file_type().is_symlink() {
+                symlinks += 1;
+                if !follow_symlinks {
+                    return Err(not_found("is a symbolic link"));
+                }
+                if symlinks > MAX_SYMLINKS {
+                    return Err(not_found("too many symbolic links"));
+                }
+                let target = read_link(&entry)?;
+                if target.is_absolute() {
+                    // absolute links into the served directory are resolved from the directory
+                    let relative = target.strip_prefix(&self.root).map_err(|_| not_found("leaves the served directory"))?;
+                    dirs.truncate(1);
+                    push_components(&mut components, relative)?;
+                } else {
+                    push_components(&mut components, &target)?;
+                }
+            } else if components.is_empty() {
+                if !metadata.is_file() {
+                    return Err(not_found("not a regular file"));
+                }
+                return Ok(ResolvedFile { parent: dirs.pop().unwrap(), name, metadata });
+            } else if metadata.is_dir() {
+                dirs.push(entry);
+            } else {
+                return Err(not_found("not a directory"));
+            }
+        }
+        Err(not_found("not a regular file"))
+    }
+
+    /// the state that the blocking resolution needs
+    fn resolver(&self) -> Self {
+        Self { dir: self.dir.clone(), root: self.root.clone(), ..*self }
+    }
+}
+
+/// the file names of the directory descriptor cannot be replaced by symbolic links or other directories
+struct ResolvedFile {
+    parent: std::fs::File,
+    name: OsString,
+    metadata: Metadata,
+}
+
+/// prepends the components of the path to the components that still have to be resolved
+fn push_components(components: &mut VecDeque<OsString>, path: &Path) -> io::Result<()> {
+    for component in path.components().rev() {
+        match component {
+            Component::Normal(name) => components.push_front(name.to_os_string()),
+            Component::ParentDir => components.push_front(OsString::from("..")),
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return Err(not_found("absolute path")),
+        }
+    }
+    Ok(())
+}
+
+/// relative to the directory, or the working directory if None
+fn open_at(dir: Option<&std::fs::File>, name: &OsStr, flags: libc::c_int) -> io::Result<std::fs::File> {
+    let name = CString::new(name.as_bytes())?;
+    let dir = dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+/// the target of a symbolic link that has been opened with O_PATH
+fn read_link(link: &std::fs::File) -> io::Result<PathBuf> {
+    let mut target = vec![0u8; libc::PATH_MAX as usize];
+    let size = unsafe { libc::readlinkat(link.as_raw_fd(), b"\0".as_ptr() as *const libc::c_char, target.as_mut_ptr() as *mut libc::c_char, target.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    target.truncate(size as usize);
+    Ok(PathBuf::from(OsString::from_vec(target)))
+}
+
+fn not_found(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message.to_string())
+}
+
+impl FileSource for LocalFileSource {
+
+    fn open<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<Box<dyn SourceFile>>> {
+        async move {
+            let resolver = self.resolver();
+            let file_name = file_name.to_string();
+            let file = tokio::task::spawn_blocking(move || -> io::Result<std::fs::File> {
+                let resolved = resolver.resolve(&file_name)?;
+                // the name must not be replaced by a symbolic link or special file until it is opened,
+                // non-blocking so a FIFO does not block the open
+                let mut flags = libc::O_RDONLY | libc::O_NONBLOCK;
+                if resolver.symlink_policy != SymlinkPolicy::Follow {
+                    flags |= libc::O_NOFOLLOW;
+                }
+                let file = open_at(Some(&resolved.parent), &resolved.name, flags)?;
+                let opened = file.metadata()?;
+                if !opened.is_file() || opened.st_dev() != resolved.metadata.st_dev() || opened.st_ino() != resolved.metadata.st_ino() {
+                    return Err(not_found("replaced while opening"));
+                }
+                Ok(file)
+            }).await.map_err(io::Error::from)??;
+            let file: Box<dyn SourceFile> = Box::new(LocalFile::new(File::from_std(file), self.reader_buffer_size).await?);
+            Ok(file)
+        }.boxed()
+    }
+
+    fn stat<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<FileIdentity>> {
+        async move {
+            let resolver = self.resolver();
+            let file_name = file_name.to_string();
+            let resolved = tokio::task::spawn_blocking(move || resolver.resolve(&file_name)).await.map_err(io::Error::from)??;
+            Ok(FileIdentity::from(&resolved.metadata))
+        }.boxed()
+    }
+}
+
+/// sequential reads are buffered, they only seek if the offset differs from the last read
+struct LocalFile {
+    file: File,
+    /// the reader and its position
+    reader: Mutex<(BufReader<File>, u64)>,
+}
+
+impl LocalFile {
+
+    async fn new(file: File, buffer_size: usize) -> io::Result<Self> {
+        let reader = BufReader::with_capacity(buffer_size, file.try_clone().await?);
+        Ok(Self {
+            file,
+            reader: Mutex::new((reader, 0)),
+        })
+    }
+}
+
+impl SourceFile for LocalFile {
+
+    fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>> {
+        async move {
+            Ok(FileIdentity::from(&self.file.metadata().await?))
+        }.boxed()
+    }
+
+    fn read_at<'a>(&'a self, buf: &'a mut [u8], offset: u64) -> BoxFuture<'a, io::Result<usize>> {
+        async move {
+            let mut reader = self.reader.lock().await;
+            let (reader, position) = &mut *reader;
+            if *position != offset {
+                reader.seek(SeekFrom::Start(offset)).await?;
+                *position = offset;
+            }
+            let size = reader.read(buf).await?;
+            *position += size as u64;
+            Ok(size)
+        }.boxed()
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.file.as_raw_fd())
+    }
+}
+
+/// serves in-memory blobs, e.g. generated content or test data
+///
+/// every inserted blob is a new version of the file
+///
+/// the creation time of the source is part of the identities,
+/// so checksums persisted by a previous run are never served for the blobs of this run
+pub struct MemoryFileSource {
+    files: std::sync::Mutex<HashMap<String, MemoryFile>>,
+    epoch: Duration,
+    next_version: AtomicU64,
+}
+
+impl MemoryFileSource {
+
+    pub fn new() -> Self {
+        Self {
+            files: std::sync::Mutex::new(HashMap::new()),
+            epoch: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),
+            next_version: AtomicU64::new(1),
+        }
+    }
+
+    /// add or replace the file
+    pub fn insert(&self, file_name: &str, content: Vec<u8>) {
+        let identity = FileIdentity::new(
+            content.len() as u64,
+            self.epoch.as_secs() as i64,
+            self.epoch.subsec_nanos() as i64,
+            self.next_version.fetch_add(1, Relaxed),
+        );
+        let file = MemoryFile { content: Arc::new(content), identity };
+        self.files.lock().unwrap().insert(file_name.to_string(), file);
+    }
+
+    pub fn remove(&self, file_name: &str) {
+        self.files.lock().unwrap().remove(file_name);
+    }
+
+    fn get(&self, file_name: &str) -> io::Result<MemoryFile> {
+        match self.files.lock().unwrap().get(file_name) {
+            Some(file) => Ok(file.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "file not found")),
+        }
+    }
+}
+
+impl Default for MemoryFileSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSource for MemoryFileSource {
+
+    fn open<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<Box<dyn SourceFile>>> {
+        let file = self.get(file_name).map(|file| Box::new(file) as Box<dyn SourceFile>);
+        future::ready(file).boxed()
+    }
+
+    fn stat<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<FileIdentity>> {
+        future::ready(self.get(file_name).map(|file| file.identity)).boxed()
+    }
+}
+
+/// an opened version of a blob, replacing the blob does not modify it
+#[derive(Clone)]
+struct MemoryFile {
+    content: Arc<Vec<u8>>,
+    identity: FileIdentity,
+}
+
+impl SourceFile for MemoryFile {
+
+    fn identity(&self) -> BoxFuture<'_, io::Result<FileIdentity>> {
+        future::ready(Ok(self.identity)).boxed()
+    }
+
+    fn read_at<'a>(&'a self, buf: &'a mut [u8], offset: u64) -> BoxFuture<'a, io::Result<usize>> {
+        let start = (offset as usize).min(self.content.len());
+        let size = buf.len().min(self.content.len() - start);
+        buf[..size].copy_from_slice(&self.content[start..start + size]);
+        future::ready(Ok(size)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+    use crate::file_source::{FileSource, LocalFileSource, MemoryFileSource, SymlinkPolicy};
+    use tempdir::TempDir;
+
+    /// a served directory with symbolic links into and out of it
+    ///
+    /// returns the parent directory, the served directory is `served`
+    fn served_dir_with_links() -> TempDir {
+        let dir = TempDir::new("soft_test").unwrap();
+        let served = dir.path().join("served");
+        std::fs::create_dir_all(served.join("dir")).unwrap();
+        std::fs::create_dir_all(dir.path().join("secret")).unwrap();
+        std::fs::write(served.join("dir/hello.txt"), "hello").unwrap();
+        std::fs::write(dir.path().join("secret/passwd"), "secret").unwrap();
+        symlink(served.join("dir/hello.txt"), served.join("inside")).unwrap();
+        symlink("dir", served.join("inside_dir")).unwrap();
+        symlink(dir.path().join("secret/passwd"), served.join("outside")).unwrap();
+        symlink("../secret/passwd", served.join("relative_outside")).unwrap();
+        symlink(dir.path().join("secret"), served.join("outside_dir")).unwrap();
+        symlink("missing", served.join("dangling")).unwrap();
+        symlink("/dev/null", served.join("device")).unwrap();
+        symlink("loop", served.join("loop")).unwrap();
+        let fifo = CString::new(served.join("fifo").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        dir
+    }
+
+    async fn served(source: &LocalFileSource, file_name: &str) -> bool {
+        let opened = source.open(file_name).await.is_ok();
+        assert_eq!(opened, source.stat(file_name).await.is_ok(), "{}", file_name);
+        opened
+    }
+
+    #[tokio::test]
+    async fn symlink_policies() {
+        let dir = served_dir_with_links();
+        let served_dir = dir.path().join("served");
+        let policies = [SymlinkPolicy::Deny, SymlinkPolicy::WithinRoot, SymlinkPolicy::Follow];
+        // served with the deny, within root and follow policy
+        let expected = [
+            ("dir/hello.txt", [true, true, true]),
+            ("inside", [false, true, true]),
+            ("inside_dir/hello.txt", [false, true, true]),
+            ("outside", [false, false, true]),
+            ("relative_outside", [false, false, true]),
+            ("outside_dir/passwd", [false, false, true]),
+            ("inside_dir/../inside", [false, true, true]),
+            ("dir/../../secret/passwd", [false, false, true]),
+            ("loop", [false, false, false]),
+            ("dangling", [false, false, false]),
+            // special files are never served
+            ("device", [false, false, false]),
+            ("fifo", [false, false, false]),
+            ("dir", [false, false, false]),
+        ];
+        for (i, policy) in policies.iter().enumerate() {
+            let source = LocalFileSource::with_symlink_policy(served_dir.clone(), *policy);
+            for (file_name, served_with) in &expected {
+                assert_eq!(served(&source, file_name).await, served_with[i], "{} with {:?}", file_name, policy);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn symlinked_served_dir() {
+        let dir = served_dir_with_links();
+        symlink(dir.path().join("served"), dir.path().join("link")).unwrap();
+        let source = LocalFileSource::with_symlink_policy(dir.path().join("link"), SymlinkPolicy::WithinRoot);
+        assert!(served(&source, "dir/hello.txt").await);
+        assert!(served(&source, "inside").await);
+        assert!(!served(&source, "outside").await);
+    }
+
+    #[tokio::test]
+    async fn local_read_at() {
+        let dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello world").unwrap();
+        let source = LocalFileSource::new(dir.path().to_path_buf());
+        let file = source.open("hello.txt").await.unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(file.read_at(&mut buf, 6).await.unwrap(), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(file.read_at(&mut buf, 0).await.unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(file.read_at(&mut buf, 11).await.unwrap(), 0);
+        assert_eq!(file.identity().await.unwrap(), source.stat("hello.txt").await.unwrap());
+        assert!(source.open(".").await.is_err());
+        assert!(source.open("world.txt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_versions() {
+        let source = MemoryFileSource::new();
+        source.insert("hello.txt", b"hello".to_vec());
+        let file = source.open("hello.txt").await.unwrap();
+        source.insert("hello.txt", b"HELLO".to_vec());
+        // the opened version is not modified
+        let mut buf = [0u8; 8];
+        assert_eq!(file.read_at(&mut buf, 1).await.unwrap(), 4);
+        assert_eq!(&buf[..4], b"ello");
+        assert_ne!(file.identity().await.unwrap(), source.stat("hello.txt").await.unwrap());
+        source.remove("hello.txt");
+        assert!(source.open("hello.txt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_restart() {
+        let source = MemoryFileSource::new();
+        source.insert("hello.txt", b"hello".to_vec());
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        // the same version of a later run has another identity
+        let restarted = MemoryFileSource::new();
+        restarted.insert("hello.txt", b"HELLO".to_vec());
+        assert_ne!(source.stat("hello.txt").await.unwrap(), restarted.stat("hello.txt").await.unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use log::trace;
+use crate::path_cache::CongestionWindow;
+
+/// maximum queuing delay LEDBAT tries to keep (RFC 6817 recommends at most 100ms)
+const TARGET: Duration = Duration::from_millis(100);
+/// number of MPS to increase the congestion window per RTT if there is no queuing delay
+const GAIN: f64 = 1.0;
+/// number of one minute intervals for which the minimum delay is remembered
+const BASE_HISTORY: usize = 10;
+const BASE_HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+/// number of delay samples to filter out noise
+const CURRENT_FILTER: usize = 4;
+const INITIAL_CONGESTION_WINDOW: f64 = 1.0;
+const MIN_CONGESTION_WINDOW: f64 = 1.0;
+/// factor for decreasing the congestion window on loss
+const LOSS_BETA: f64 = 0.5;
+
+/// # LEDBAT Congestion Controller
+///
+/// delay based congestion control for low priority background transfers (RFC 6817)
+///
+/// SOFT packets carry no timestamps, so the queuing delay is estimated from the RTT samples:
+/// the current delay minus the lowest delay that has been observed on the path
+pub struct Ledbat {
+    congestion_window: f64,
+    /// minimum delay of each BASE_HISTORY_INTERVAL, newest at the back
+    base_delays: VecDeque<(Instant, Duration)>,
+    /// the latest delay samples, newest at the back
+    current_delays: VecDeque<Duration>,
+}
+
+impl Ledbat {
+
+    pub fn new() -> Self {
+        Self {
+            congestion_window: INITIAL_CONGESTION_WINDOW,
+            base_delays: VecDeque::with_capacity(BASE_HISTORY),
+            current_delays: VecDeque::with_capacity(CURRENT_FILTER),
+        }
+    }
+
+    pub fn congestion_window(&self) -> CongestionWindow {
+        self.congestion_window as CongestionWindow
+    }
+
+    pub fn apply_rtt_sample(&mut self, rtt_sample: Duration) {
+        let now = Instant::now();
+        match self.base_delays.back_mut() {
+            Some((start, base_delay)) if now.duration_since(*start) < BASE_HISTORY_INTERVAL => {
+                *base_delay = Duration::min(*base_delay, rtt_sample);
+            }
+            _ => {
+                if self.base_delays.len() == BASE_HISTORY {
+                    self.base_delays.pop_front();
+                }
+                self.base_delays.push_back((now, rtt_sample));
+            }
+        }
+        if self.current_delays.len() == CURRENT_FILTER {
+            self.current_delays.pop_front();
+        }
+        self.current_delays.push_back(rtt_sample);
+    }
+
+    /// estimated queuing delay
+    ///
+    /// None if no RTT sample has been applied yet
+    pub fn queuing_delay(&self) -> Option<Duration> {
+        let base_delay = self.base_delays.iter().map(|(_, d)| *d).min()?;
+        let current_delay = self.current_delays.iter().min()?;
+        Some(current_delay.saturating_sub(base_delay))
+    }
+
+    /// should be called on received ACKs
+    ///
+    /// the congestion window grows while the queuing delay is below the target and shrinks above it
+    ///
+    /// the window does not grow beyond the limit, e.g. the receive window of the client,
+    /// but it still shrinks if the queuing delay is above the target
+    pub fn on_ack(&mut self, limit: CongestionWindow) {
+        let off_target = match self.queuing_delay() {
+            Some(queuing_delay) => (TARGET.as_secs_f64() - queuing_delay.as_secs_f64()) / TARGET.as_secs_f64(),
+            None => 1.0,
+        };
+        let mut congestion_window = self.congestion_window + GAIN * off_target / self.congestion_window;
+        if congestion_window > self.congestion_window {
+            congestion_window = f64::min(congestion_window, f64::max(self.congestion_window, limit as f64));
+        }
+        self.congestion_window = f64::max(congestion_window, MIN_CONGESTION_WINDOW);
+        trace!("changed ledbat congestion window to {}", self.congestion_window);
+    }
+
+    /// should be called on congestion loss
+    pub fn on_loss(&mut self) {
+        self.congestion_window = f64::max(self.congestion_window * LOSS_BETA, MIN_CONGESTION_WINDOW);
+        trace!("decreased ledbat congestion window to {}", self.congestion_window);
+    }
+
+    /// should be called on timeouts
+    pub fn on_timeout(&mut self) {
+        self.congestion_window = MIN_CONGESTION_WINDOW;
+        trace!("reset ledbat congestion window to {}", self.congestion_window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ledbat::Ledbat;
+    use crate::path_cache::CongestionWindow;
+    use std::time::Duration;
+
+    #[test]
+    fn grow_without_queuing_delay() {
+        let mut ledbat = Ledbat::new();
+        for _ in 0..10 {
+            ledbat.apply_rtt_sample(Duration::from_millis(20));
+            ledbat.on_ack(CongestionWindow::MAX);
+        }
+        assert_eq!(ledbat.queuing_delay(), Some(Duration::ZERO));
+        assert!(ledbat.congestion_window() > 1);
+    }
+
+    #[test]
+    fn back_off_above_target() {
+        let mut ledbat = Ledbat::new();
+        for _ in 0..50 {
+            ledbat.apply_rtt_sample(Duration::from_millis(20));
+            ledbat.on_ack(CongestionWindow::MAX);
+        }
+        let congestion_window = ledbat.congestion_window();
+        for _ in 0..10 {
+            ledbat.apply_rtt_sample(Duration::from_millis(400));
+            ledbat.on_ack(CongestionWindow::MAX);
+        }
+        assert_eq!(ledbat.queuing_delay(), Some(Duration::from_millis(380)));
+        assert!(ledbat.congestion_window() < congestion_window);
+    }
+
+    #[test]
+    fn back_off_while_window_limited() {
+        let mut ledbat = Ledbat::new();
+        for _ in 0..50 {
+            ledbat.apply_rtt_sample(Duration::from_millis(20));
+            ledbat.on_ack(CongestionWindow::MAX);
+        }
+        let congestion_window = ledbat.congestion_window();
+        // the limit stops growth
+        ledbat.on_ack(congestion_window);
+        assert_eq!(ledbat.congestion_window(), congestion_window);
+        for _ in 0..10 {
+            ledbat.apply_rtt_sample(Duration::from_millis(400));
+            ledbat.on_ack(1);
+        }
+        assert!(ledbat.congestion_window() < congestion_window);
+    }
+}
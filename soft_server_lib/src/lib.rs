@@ -1,6 +1,23 @@
 pub mod server;
 pub mod connection;
+pub mod server_config;
+pub mod file_source;
+pub mod archive_source;
+pub mod access_control;
+pub mod mount_table;
+pub mod stats;
+pub mod observer;
+pub mod access_log;
+mod config_file;
 mod checksum_cache;
+mod checksum_store;
+mod checksum_precomputer;
+mod snapshot_store;
 mod path_cache;
+mod connection_table;
 mod file_sandbox;
 mod send_buffer;
+mod buffer_pool;
+mod ledbat;
+mod pacer;
+mod new_reno;
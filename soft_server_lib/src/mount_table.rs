@@ -0,0 +1,231 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use futures_util::future::{self, BoxFuture, FutureExt};
+use crate::file_source::{FileIdentity, FileSource, SourceFile};
+
+/// how the transfers of a mount share the bandwidth with other traffic
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum BandwidthClass {
+    #[default]
+    Normal,
+    /// transfers yield to other traffic, like scavenger paths
+    Scavenger,
+}
+
+/// serves a directory under a prefix of the file name, e.g. `lab/data.bin` is `data.bin` in the directory mounted at `lab`
+///
+/// written as `PREFIX=DIR[,ro][,precompute][,scavenger]`, e.g. `lab=/data/lab,ro,scavenger`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    /// relative path, empty for the root
+    pub prefix: String,
+    pub dir: PathBuf,
+    /// the directory is not modified while it is served, e.g. read-only media,
+    /// so files are served directly instead of snapshots
+    pub read_only: bool,
+    /// compute the checksums of all files of the mount at start and whenever they are modified
+    pub precompute_checksums: bool,
+    pub bandwidth_class: BandwidthClass,
+}
+
+impl Mount {
+
+    /// a writable mount with normal bandwidth, without precomputed checksums
+    pub fn new(prefix: &str, dir: PathBuf) -> Self {
+        Self {
+            prefix: prefix.trim_matches('/').to_string(),
+            dir,
+            read_only: false,
+            precompute_checksums: false,
+            bandwidth_class: BandwidthClass::Normal,
+        }
+    }
+
+    /// the file name relative to the mounted directory, None if the file is not below the prefix
+    pub fn relative_name<'a>(&self, file_name: &'a str) -> Option<&'a str> {
+        if self.prefix.is_empty() {
+            return Some(file_name);
+        }
+        file_name.strip_prefix(self.prefix.as_str())?.strip_prefix('/')
+    }
+
+    /// the file name of a path relative to the mounted directory
+    pub fn file_name(&self, relative_path: &Path) -> PathBuf {
+        Path::new(&self.prefix).join(relative_path)
+    }
+}
+
+impl FromStr for Mount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(',');
+        let (prefix, dir) = fields.next().and_then(|mount| mount.split_once('='))
+            .ok_or_else(|| format!("mount {:?} is not PREFIX=DIR", s))?;
+        let prefix = prefix.trim_matches('/');
+        let normal = Path::new(prefix).components().all(|component| matches!(component, Component::Normal(_)));
+        // the served directory is mounted at the root
+        if prefix.is_empty() || !normal {
+            return Err(format!("mount prefix {:?} is not a relative path", prefix));
+        }
+        let mut mount = Mount::new(prefix, PathBuf::from(dir));
+        for option in fields {
+            match option {
+                "ro" => mount.read_only = true,
+                "precompute" => mount.precompute_checksums = true,
+                "scavenger" => mount.bandwidth_class = BandwidthClass::Scavenger,
+                _ => return Err(format!("unknown mount option {:?}", option)),
+            }
+        }
+        Ok(mount)
+    }
+}
+
+/// Err if two mounts have the same prefix, only one of them could be served
+pub fn check_mounts(mounts: &[Mount]) -> Result<(), String> {
+    for (i, mount) in mounts.iter().enumerate() {
+        if mounts[..i].iter().any(|other| Path::new(&other.prefix) == Path::new(&mount.prefix)) {
+            return Err(format!("mount prefix {:?} is used more than once", mount.prefix));
+        }
+    }
+    Ok(())
+}
+
+/// # Mount Table
+///
+/// maps file names to the mounts they are served from, the mount with the longest matching prefix applies
+pub struct MountTable {
+    /// sorted by descending prefix length
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+
+    pub fn new(mut mounts: Vec<Mount>) -> Self {
+        mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+        Self { mounts }
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// the mount of the file and the file name relative to the mounted directory
+    pub fn resolve<'a>(&self, file_name: &'a str) -> Option<(&Mount, &'a str)> {
+        self.mounts.iter().find_map(|mount| mount.relative_name(file_name).map(|relative_name| (mount, relative_name)))
+    }
+
+    /// the path of the file in the local file system
+    pub fn local_path(&self, file_name: &str) -> Option<PathBuf> {
+        self.resolve(file_name).map(|(mount, relative_name)| mount.dir.join(relative_name))
+    }
+}
+
+/// serves the files of a source per mount prefix, see MountTable
+pub struct MountFileSource {
+    /// sorted by descending prefix length
+    sources: Vec<(Mount, Arc<dyn FileSource>)>,
+}
+
+impl MountFileSource {
+
+    pub fn new(mut sources: Vec<(Mount, Arc<dyn FileSource>)>) -> Self {
+        sources.sort_by_key(|(mount, _)| std::cmp::Reverse(mount.prefix.len()));
+        Self { sources }
+    }
+
+    fn resolve<'a>(&self, file_name: &'a str) -> io::Result<(&dyn FileSource, &'a str)> {
+        self.sources.iter()
+            .find_map(|(mount, source)| mount.relative_name(file_name).map(|relative_name| (source.as_ref(), relative_name)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no mount"))
+    }
+}
+
+impl FileSource for MountFileSource {
+
+    fn open<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<Box<dyn SourceFile>>> {
+        match self.resolve(file_name) {
+            Ok((source, relative_name)) => source.open(relative_name),
+            Err(e) => future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn stat<'a>(&'a self, file_name: &'a str) -> BoxFuture<'a, io::Result<FileIdentity>> {
+        match self.resolve(file_name) {
+            Ok((source, relative_name)) => source.stat(relative_name),
+            Err(e) => future::ready(Err(e)).boxed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::file_source::{FileSource, MemoryFileSource};
+    use crate::mount_table::{check_mounts, BandwidthClass, Mount, MountFileSource, MountTable};
+
+    #[test]
+    fn parse_mounts() {
+        let mount: Mount = "lab=/data/lab,ro,precompute,scavenger".parse().unwrap();
+        assert_eq!(mount.prefix, "lab");
+        assert_eq!(mount.dir, PathBuf::from("/data/lab"));
+        assert!(mount.read_only);
+        assert!(mount.precompute_checksums);
+        assert_eq!(mount.bandwidth_class, BandwidthClass::Scavenger);
+        assert_eq!("/lab/=/data/lab".parse::<Mount>().unwrap(), Mount::new("lab", PathBuf::from("/data/lab")));
+        assert!("/data/lab".parse::<Mount>().is_err());
+        assert!("=/data/lab".parse::<Mount>().is_err());
+        assert!("../lab=/data/lab".parse::<Mount>().is_err());
+        assert!("lab=/data/lab,rw".parse::<Mount>().is_err());
+    }
+
+    #[test]
+    fn duplicate_prefixes() {
+        let lab = Mount::new("lab", PathBuf::from("/data/lab"));
+        let archive = Mount::new("lab/archive", PathBuf::from("/archive"));
+        assert!(check_mounts(&[lab.clone(), archive.clone()]).is_ok());
+        assert!(check_mounts(&[lab, archive, "lab/=/data/other".parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let table = MountTable::new(vec![
+            Mount::new("", PathBuf::from("/srv")),
+            Mount::new("lab", PathBuf::from("/data/lab")),
+            Mount::new("lab/archive", PathBuf::from("/archive")),
+        ]);
+        assert_eq!(table.local_path("hello.txt"), Some(PathBuf::from("/srv/hello.txt")));
+        assert_eq!(table.local_path("lab/hello.txt"), Some(PathBuf::from("/data/lab/hello.txt")));
+        assert_eq!(table.local_path("lab/archive/2020/hello.txt"), Some(PathBuf::from("/archive/2020/hello.txt")));
+        // prefixes only match whole directories
+        assert_eq!(table.local_path("laboratory/hello.txt"), Some(PathBuf::from("/srv/laboratory/hello.txt")));
+        assert_eq!(table.resolve("lab").map(|(mount, _)| mount.prefix.as_str()), Some(""));
+
+        let table = MountTable::new(vec![Mount::new("lab", PathBuf::from("/data/lab"))]);
+        assert_eq!(table.local_path("hello.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn mounted_sources() {
+        let root = Arc::new(MemoryFileSource::new());
+        root.insert("hello.txt", b"root".to_vec());
+        let lab = Arc::new(MemoryFileSource::new());
+        lab.insert("hello.txt", b"lab".to_vec());
+        let source = MountFileSource::new(vec![
+            (Mount::new("", PathBuf::from("/srv")), root),
+            (Mount::new("lab", PathBuf::from("/data/lab")), lab),
+        ]);
+        let mut buf = [0u8; 8];
+        let file = source.open("lab/hello.txt").await.unwrap();
+        let size = file.read_at(&mut buf, 0).await.unwrap();
+        assert_eq!(&buf[..size], b"lab");
+        let file = source.open("hello.txt").await.unwrap();
+        let size = file.read_at(&mut buf, 0).await.unwrap();
+        assert_eq!(&buf[..size], b"root");
+        assert!(source.stat("lab/hello.txt").await.is_ok());
+        assert!(source.open("lab/world.txt").await.is_err());
+    }
+}
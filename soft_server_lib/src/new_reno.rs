@@ -0,0 +1,178 @@
+use soft_shared_lib::field_types::SequenceNumber;
+use log::trace;
+use crate::path_cache::CongestionWindow;
+
+/// number of duplicate ACKs that indicate a packet loss
+const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+
+/// reaction to a new cumulative ACK
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NewAck {
+    /// not in fast recovery, the congestion window can grow
+    Normal,
+    /// some but not all packets up to the recovery point are acknowledged,
+    /// the first unacknowledged packet is lost as well and must be retransmitted
+    Partial,
+    /// all packets up to the recovery point are acknowledged, fast recovery has ended
+    Full,
+}
+
+/// # NewReno Fast Retransmit and Fast Recovery
+///
+/// loss detection with duplicate ACKs and partial ACK handling like RFC 6582
+///
+/// only the lost packets are retransmitted, instead of all packets after the loss
+pub struct NewReno {
+    duplicate_acks: u32,
+    /// the last packet that has been sent when fast recovery started
+    ///
+    /// None if no loss has been detected yet
+    recovery_point: Option<SequenceNumber>,
+    in_recovery: bool,
+    /// number of packets the congestion window is inflated by during fast recovery,
+    /// every duplicate ACK indicates that a packet has left the network
+    inflation: CongestionWindow,
+}
+
+impl NewReno {
+
+    pub fn new() -> Self {
+        Self {
+            duplicate_acks: 0,
+            recovery_point: None,
+            in_recovery: false,
+            inflation: 0,
+        }
+    }
+
+    pub fn in_recovery(&self) -> bool {
+        self.in_recovery
+    }
+
+    /// has to be added to the congestion window
+    pub fn inflation(&self) -> CongestionWindow {
+        self.inflation
+    }
+
+    /// should be called on duplicate ACKs while packets are in flight
+    ///
+    /// true if fast recovery starts, the congestion window must be decreased
+    /// and the first unacknowledged packet must be retransmitted
+    pub fn on_duplicate_ack(&mut self, next_sequence_number: SequenceNumber, last_packet_sent: SequenceNumber) -> bool {
+        if self.in_recovery {
+            self.inflation = self.inflation.saturating_add(1);
+            return false;
+        }
+        self.duplicate_acks += 1;
+        if self.duplicate_acks < DUPLICATE_ACK_THRESHOLD {
+            return false;
+        }
+        if let Some(recovery_point) = self.recovery_point {
+            // the duplicate ACKs might be caused by retransmissions of a previous recovery
+            if next_sequence_number <= recovery_point {
+                return false;
+            }
+        }
+        self.in_recovery = true;
+        self.recovery_point = Some(last_packet_sent);
+        self.inflation = DUPLICATE_ACK_THRESHOLD as CongestionWindow;
+        trace!("enter fast recovery until {}", last_packet_sent);
+        true
+    }
+
+    /// should be called on ACKs that acknowledge new packets
+    pub fn on_new_ack(&mut self, next_sequence_number: SequenceNumber, newly_acknowledged: CongestionWindow) -> NewAck {
+        self.duplicate_acks = 0;
+        if !self.in_recovery {
+            return NewAck::Normal;
+        }
+        if next_sequence_number > self.recovery_point.unwrap() {
+            self.in_recovery = false;
+            self.inflation = 0;
+            trace!("exit fast recovery");
+            NewAck::Full
+        } else {
+            // deflate by the acknowledged packets and add back the retransmitted packet
+            self.inflation = self.inflation.saturating_sub(newly_acknowledged).saturating_add(1);
+            NewAck::Partial
+        }
+    }
+
+    /// should be called if the client has received new DATA packets with the ECN codepoint CE
+    ///
+    /// true if the congestion window must be decreased,
+    /// which happens at most once per window of packets, like for losses
+    pub fn on_congestion_experienced(&mut self, next_sequence_number: SequenceNumber, last_packet_sent: SequenceNumber) -> bool {
+        if self.in_recovery {
+            return false;
+        }
+        if let Some(recovery_point) = self.recovery_point {
+            if next_sequence_number <= recovery_point {
+                return false;
+            }
+        }
+        self.recovery_point = Some(last_packet_sent);
+        true
+    }
+
+    /// should be called on retransmission timeouts
+    ///
+    /// duplicate ACKs of packets sent before the timeout do not start fast recovery
+    pub fn on_timeout(&mut self, last_packet_sent: Option<SequenceNumber>) {
+        self.duplicate_acks = 0;
+        self.in_recovery = false;
+        self.inflation = 0;
+        if last_packet_sent.is_some() {
+            self.recovery_point = last_packet_sent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::new_reno::{NewReno, NewAck};
+
+    #[test]
+    fn fast_recovery() {
+        let mut new_reno = NewReno::new();
+        assert!(!new_reno.on_duplicate_ack(5, 10));
+        assert!(!new_reno.on_duplicate_ack(5, 10));
+        assert!(new_reno.on_duplicate_ack(5, 10));
+        assert!(new_reno.in_recovery());
+        assert_eq!(new_reno.inflation(), 3);
+        assert!(!new_reno.on_duplicate_ack(5, 12));
+        assert_eq!(new_reno.inflation(), 4);
+        // packets 5 and 7 have been lost
+        assert_eq!(new_reno.on_new_ack(7, 2), NewAck::Partial);
+        assert_eq!(new_reno.inflation(), 3);
+        assert!(new_reno.in_recovery());
+        assert_eq!(new_reno.on_new_ack(11, 4), NewAck::Full);
+        assert_eq!(new_reno.inflation(), 0);
+        assert!(!new_reno.in_recovery());
+        assert_eq!(new_reno.on_new_ack(12, 1), NewAck::Normal);
+    }
+
+    #[test]
+    fn congestion_experienced_once_per_window() {
+        let mut new_reno = NewReno::new();
+        assert!(new_reno.on_congestion_experienced(3, 10));
+        assert!(!new_reno.on_congestion_experienced(8, 12));
+        assert!(new_reno.on_congestion_experienced(11, 20));
+        assert!(!new_reno.in_recovery());
+    }
+
+    #[test]
+    fn no_recovery_for_old_duplicates() {
+        let mut new_reno = NewReno::new();
+        new_reno.on_timeout(Some(10));
+        for _ in 0..5 {
+            assert!(!new_reno.on_duplicate_ack(8, 10));
+        }
+        assert!(!new_reno.in_recovery());
+        new_reno.on_new_ack(11, 3);
+        for _ in 0..2 {
+            assert!(!new_reno.on_duplicate_ack(11, 15));
+        }
+        assert!(new_reno.on_duplicate_ack(11, 15));
+    }
+}
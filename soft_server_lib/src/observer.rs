@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use soft_shared_lib::field_types::ConnectionId;
+use soft_shared_lib::soft_error_code::SoftErrorCode;
+
+/// the state of a transfer when an event occurs, see ServerObserver
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferInfo {
+    pub connection_id: ConnectionId,
+    /// the current address of the client
+    pub peer: SocketAddr,
+    pub file_name: String,
+    pub file_size: u64,
+    /// the offset the client requested the file from
+    pub offset: u64,
+    /// bytes of all sent DATA packets, including retransmissions
+    pub bytes_sent: u64,
+    pub retransmissions: u64,
+    /// bytes of the file after the offset that the client has acknowledged
+    pub bytes_acknowledged: u64,
+}
+
+/// # Server Observer
+///
+/// lets embedding applications react to requests and transfers, see ServerConfig::observer
+///
+/// callbacks are called from the tasks of the server and the connections, so they should return quickly,
+/// all callbacks do nothing by default
+pub trait ServerObserver: Send + Sync {
+
+    /// a client requested a file, before the request is checked
+    fn on_request(&self, _peer: SocketAddr, _file_name: &str, _offset: u64) {}
+
+    /// a request has been answered with an error instead of an ACC packet
+    ///
+    /// requests that are repeated until the checksum is ready are not rejected
+    fn on_rejected(&self, _peer: SocketAddr, _file_name: &str, _offset: u64, _error_code: SoftErrorCode) {}
+
+    /// the ACC packet has been sent, the transfer starts with the first ACK of the client
+    fn on_accepted(&self, _transfer: &TransferInfo) {}
+
+    /// the client continues the transfer from another address, the peer of the transfer is the new address
+    fn on_migrated(&self, _transfer: &TransferInfo, _previous_peer: SocketAddr) {}
+
+    /// the client has acknowledged all bytes of the file
+    fn on_finished(&self, _transfer: &TransferInfo) {}
+
+    /// the client has not answered for the connection timeout
+    fn on_timed_out(&self, _transfer: &TransferInfo) {}
+
+    /// the transfer has been closed by an ERR packet of the client or the server
+    fn on_failed(&self, _transfer: &TransferInfo, _error_code: SoftErrorCode) {}
+}
+
+/// notifies all observers of a server, e.g. the configured observer and the access log
+pub(crate) struct Observers(pub Vec<Arc<dyn ServerObserver>>);
+
+impl ServerObserver for Observers {
+
+    fn on_request(&self, peer: SocketAddr, file_name: &str, offset: u64) {
+        self.0.iter().for_each(|observer| observer.on_request(peer, file_name, offset));
+    }
+
+    fn on_rejected(&self, peer: SocketAddr, file_name: &str, offset: u64, error_code: SoftErrorCode) {
+        self.0.iter().for_each(|observer| observer.on_rejected(peer, file_name, offset, error_code));
+    }
+
+    fn on_accepted(&self, transfer: &TransferInfo) {
+        self.0.iter().for_each(|observer| observer.on_accepted(transfer));
+    }
+
+    fn on_migrated(&self, transfer: &TransferInfo, previous_peer: SocketAddr) {
+        self.0.iter().for_each(|observer| observer.on_migrated(transfer, previous_peer));
+    }
+
+    fn on_finished(&self, transfer: &TransferInfo) {
+        self.0.iter().for_each(|observer| observer.on_finished(transfer));
+    }
+
+    fn on_timed_out(&self, transfer: &TransferInfo) {
+        self.0.iter().for_each(|observer| observer.on_timed_out(transfer));
+    }
+
+    fn on_failed(&self, transfer: &TransferInfo, error_code: SoftErrorCode) {
+        self.0.iter().for_each(|observer| observer.on_failed(transfer, error_code));
+    }
+}
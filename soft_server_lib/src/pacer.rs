@@ -0,0 +1,88 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::path_cache::CongestionWindow;
+
+/// pacing rate factor during slow start, allows the window to double each RTT
+const SLOW_START_PACING_FACTOR: f64 = 2.0;
+/// pacing rate factor during congestion avoidance
+const CONGESTION_AVOIDANCE_PACING_FACTOR: f64 = 1.2;
+/// number of packets that may be sent back to back during slow start
+const SLOW_START_BURST: f64 = 10.0;
+/// number of packets that may be sent back to back during congestion avoidance
+const CONGESTION_AVOIDANCE_BURST: f64 = 2.0;
+/// the timer can not wake up more precisely, so the burst must cover at least this time
+const TIMER_GRANULARITY: Duration = Duration::from_millis(2);
+/// lower bound for the RTT, to prevent an unlimited rate
+const MIN_PACING_RTT: Duration = Duration::from_micros(100);
+
+/// # Packet Pacer
+///
+/// spreads DATA packets over the RTT instead of sending the whole window back to back
+///
+/// implemented as token bucket that is refilled with cwnd/RTT packets per second
+pub struct Pacer {
+    /// number of packets that can be sent immediately
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Pacer {
+
+    pub fn new() -> Self {
+        Self {
+            tokens: SLOW_START_BURST,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// None if a packet can be sent now
+    ///
+    /// otherwise the instant when the next packet can be sent
+    pub fn next_send_instant(&mut self, congestion_window: CongestionWindow, rtt: Duration, slow_start: bool) -> Option<Instant> {
+        let now = Instant::now();
+        let (factor, burst) = if slow_start {
+            (SLOW_START_PACING_FACTOR, SLOW_START_BURST)
+        } else {
+            (CONGESTION_AVOIDANCE_PACING_FACTOR, CONGESTION_AVOIDANCE_BURST)
+        };
+        // packets per second
+        let rate = factor * f64::max(congestion_window as f64, 1.0) / Duration::max(rtt, MIN_PACING_RTT).as_secs_f64();
+        let max_tokens = f64::max(burst, rate * TIMER_GRANULARITY.as_secs_f64());
+        self.tokens = f64::min(self.tokens + (now - self.last_refill).as_secs_f64() * rate, max_tokens);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(now + Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    /// should be called for every sent DATA packet
+    pub fn on_packet_sent(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pacer::{Pacer, CONGESTION_AVOIDANCE_BURST, SLOW_START_BURST};
+    use std::time::Duration;
+
+    #[test]
+    fn bounded_burst() {
+        // congestion avoidance only allows small bursts
+        assert_eq!(burst(false), CONGESTION_AVOIDANCE_BURST as usize);
+        assert_eq!(burst(true), SLOW_START_BURST as usize);
+    }
+
+    /// the number of packets that can be sent back to back at a low rate
+    fn burst(slow_start: bool) -> usize {
+        let mut pacer = Pacer::new();
+        let mut burst = 0;
+        while pacer.next_send_instant(100, Duration::from_secs(1), slow_start).is_none() {
+            pacer.on_packet_sent();
+            burst += 1;
+        }
+        burst
+    }
+}
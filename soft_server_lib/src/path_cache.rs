@@ -1,10 +1,12 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
 use std::net::SocketAddr;
 use std::time::{Duration};
-use soft_shared_lib::times::{path_cache_timeout, INITIAL_RTT};
-use std::sync::{Mutex};
+use soft_shared_lib::rtt_estimator::RttEstimator;
+use std::sync::{Arc, Mutex, MutexGuard};
 use log::{debug, trace};
 use ttl_cache::TtlCache;
-use crate::server::MAX_SIMULTANEOUS_CONNECTIONS;
+use crate::server_config::{CachedConfig, SharedConfig};
 
 pub type CongestionWindow = u16; // same size as receive window
 
@@ -14,13 +16,17 @@ const INITIAL_AVOIDANCE_THRESHOLD: f64 = f64::INFINITY;
 const CONGESTION_ALPHA: f64 = 1.0;
 /// factor for decreasing the congestion window
 const CONGESTION_BETA: f64 = 0.5;
-const RTT_MOVING_AVERAGE_GAMMA: f64 = 0.5;
+/// the paths are spread over this many locks, so the connections of different clients rarely wait for each other
+const PATH_CACHE_SHARDS: usize = 16;
 
+/// the congestion and rtt information of a path
+///
+/// a connection keeps its own copy and stores it in the path cache, see PathCache
 #[derive(PartialEq, Clone)]
 pub struct CongestionState {
     pub congestion_window: f64,
     congestion_avoidance_threshold: f64,
-    current_rtt: Duration,
+    rtt_estimator: RttEstimator,
 }
 
 impl CongestionState {
@@ -28,62 +34,45 @@ impl CongestionState {
         return Self {
             congestion_window: INITIAL_CONGESTION_WINDOW,
             congestion_avoidance_threshold: INITIAL_AVOIDANCE_THRESHOLD,
-            current_rtt: INITIAL_RTT,
+            rtt_estimator: RttEstimator::new(),
         };
     }
+
     /// true if slow_start
     ///
     /// false if congestion avoidance
-    fn is_slow_start(&self) -> bool {
+    pub fn is_slow_start(&self) -> bool {
         self.congestion_window < self.congestion_avoidance_threshold
     }
-}
-
-/// stores congestion and rtt information, independent from the connection
-///
-/// entries expire after some time
-pub struct PathCache {
-    cache: Mutex<TtlCache<SocketAddr, CongestionState>>,
-}
 
-impl PathCache {
-    pub fn new() -> PathCache {
-        return PathCache {
-            cache: Mutex::new(TtlCache::new(MAX_SIMULTANEOUS_CONNECTIONS)),
-        }
+    pub fn congestion_window(&self) -> CongestionWindow {
+        self.congestion_window as CongestionWindow
     }
 
-    pub fn current_rtt(&self, addr: SocketAddr) -> Duration{
-        let cache = self.cache.lock().unwrap();
-        cache.get(&addr).map(|s| s.current_rtt).unwrap_or(INITIAL_RTT)
+    /// the smoothed rtt
+    pub fn current_rtt(&self) -> Duration {
+        self.rtt_estimator.smoothed_rtt()
     }
 
-    /// update the rtt with a moving average
-    ///
-    /// GAMMA = 0.9
-    pub fn apply_rtt_sample(&self, addr: SocketAddr, rtt_sample: Duration) {
-        self.update(addr, |congestion_state| {
-            if *congestion_state == CongestionState::initial() {
-                congestion_state.current_rtt = rtt_sample;
-            } else {
-                congestion_state.current_rtt =
-                    congestion_state.current_rtt.mul_f64(RTT_MOVING_AVERAGE_GAMMA) + rtt_sample.mul_f64(1.0 - RTT_MOVING_AVERAGE_GAMMA);
-            }
-            trace!("updated rtt of {} to {:?}", addr, congestion_state.current_rtt);
-        });
+    /// the DATA packet retransmission timeout
+    pub fn retransmission_timeout(&self) -> Duration {
+        self.rtt_estimator.rto()
     }
 
-    pub fn congestion_window(&self, addr: SocketAddr) -> CongestionWindow{
-        let cache = self.cache.lock().unwrap();
-        cache.get(&addr).map(|s| s.congestion_window).unwrap_or(INITIAL_CONGESTION_WINDOW) as CongestionWindow
+    /// update the smoothed rtt and rtt variance
+    ///
+    /// samples of retransmitted packets must not be applied
+    pub fn apply_rtt_sample(&mut self, rtt_sample: Duration) {
+        self.rtt_estimator.apply_sample(rtt_sample);
+        trace!("updated rtt to {:?}", self.rtt_estimator.smoothed_rtt());
     }
 
-    fn update<F: Fn(&mut CongestionState)>(&self, addr: SocketAddr, f: F) {
-        let mut cache = self.cache.lock().unwrap();
-        let mut congestion_state = cache.remove(&addr).unwrap_or(CongestionState::initial());
-        f(&mut congestion_state);
-        let ttl = path_cache_timeout(congestion_state.current_rtt);
-        cache.insert(addr, congestion_state, ttl);
+    /// double the retransmission timeout until the next rtt sample
+    ///
+    /// should be called on timeouts
+    pub fn back_off_retransmission_timeout(&mut self) {
+        self.rtt_estimator.on_timeout();
+        trace!("increased retransmission timeout to {:?}", self.rtt_estimator.rto());
     }
 
     /// increase congestion window
@@ -93,45 +82,134 @@ impl PathCache {
     /// during slow start: +1
     ///
     /// during avoidance phase: + ( 1/cwnd )
-    pub fn increase_congestion_window(&self, addr: SocketAddr){
-        self.update(addr, |value| {
-            if value.is_slow_start() {
-                value.congestion_window += CONGESTION_ALPHA;
-                // check if it has changed
-                if !value.is_slow_start() {
-                    debug!("{} enter congestion avoidance phase", addr);
-                }
-            } else {
-                value.congestion_window += 1.0 / value.congestion_window;
+    pub fn increase_congestion_window(&mut self) {
+        if self.is_slow_start() {
+            self.congestion_window += CONGESTION_ALPHA;
+            // check if it has changed
+            if !self.is_slow_start() {
+                debug!("enter congestion avoidance phase");
             }
-            trace!("increased congestion window of {} to {}", addr, value.congestion_window);
-        });
+        } else {
+            self.congestion_window += 1.0 / self.congestion_window;
+        }
+        trace!("increased congestion window to {}", self.congestion_window);
     }
 
     /// halve the congestion window
     ///
     /// should be called on congestion loss
-    pub fn decrease_congestion_window(&self, addr: SocketAddr){
-        self.update(addr, |value| {
-            value.congestion_window = f64::max(value.congestion_window * CONGESTION_BETA, 1.0);
-            value.congestion_avoidance_threshold = value.congestion_window;
-            trace!("decreased congestion window of {} to {}", addr, value.congestion_window);
-        });
+    pub fn decrease_congestion_window(&mut self) {
+        self.congestion_window = f64::max(self.congestion_window * CONGESTION_BETA, 1.0);
+        self.congestion_avoidance_threshold = self.congestion_window;
+        trace!("decreased congestion window to {}", self.congestion_window);
     }
 
     /// reset congestion window to 1
     ///
     /// should be called on timeouts
-    pub fn reset_congestion_window(&self, addr: SocketAddr){
-        self.update(addr, |value| {
-            if !value.is_slow_start() {
-                value.congestion_avoidance_threshold = value.congestion_window * CONGESTION_BETA;
-                value.congestion_window = INITIAL_CONGESTION_WINDOW;
-                debug!("{} enter slow start phase", addr);
-                trace!("reset congestion window of {} to {}", addr, value.congestion_window);
-            }
-        });
+    pub fn reset_congestion_window(&mut self) {
+        if !self.is_slow_start() {
+            self.congestion_avoidance_threshold = self.congestion_window * CONGESTION_BETA;
+            self.congestion_window = INITIAL_CONGESTION_WINDOW;
+            debug!("enter slow start phase");
+            trace!("reset congestion window to {}", self.congestion_window);
+        }
     }
+}
 
+/// the paths of some of the clients
+struct PathShard {
+    cache: TtlCache<SocketAddr, CongestionState>,
+    /// the path cache timeout of the current config
+    config: CachedConfig,
+}
 
-}
\ No newline at end of file
+/// stores congestion and rtt information, independent from the connection
+///
+/// entries expire after some time
+///
+/// the paths are sharded by the client address, so the connections of different shards of the server do not share a lock
+pub struct PathCache {
+    shards: Vec<Mutex<PathShard>>,
+    /// randomly seeded, so clients can not choose addresses that share a shard
+    hasher: RandomState,
+}
+
+impl PathCache {
+    pub(crate) fn new(config: Arc<SharedConfig>) -> PathCache {
+        let capacity = config.current().max_connections;
+        return PathCache {
+            shards: (0..PATH_CACHE_SHARDS)
+                .map(|_| Mutex::new(PathShard { cache: TtlCache::new(capacity), config: config.cached() }))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, addr: &SocketAddr) -> MutexGuard<'_, PathShard> {
+        let index = self.hasher.hash_one(addr) as usize % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
+    /// the congestion state of a new connection to the client
+    ///
+    /// the initial state if there is no recent connection
+    pub fn get(&self, addr: SocketAddr) -> CongestionState {
+        self.shard(&addr).cache.get(&addr).cloned().unwrap_or_else(CongestionState::initial)
+    }
+
+    /// replace the congestion state of the path with the state of a connection
+    ///
+    /// connections store their state when it has changed significantly and when they end,
+    /// concurrent connections of the same client do not see the increases of each other in the meantime
+    pub fn store(&self, addr: SocketAddr, congestion_state: &CongestionState) {
+        let mut shard = self.shard(&addr);
+        let ttl = shard.config.get().timeouts.path_cache_timeout(congestion_state.current_rtt());
+        shard.cache.insert(addr, congestion_state.clone(), ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::path_cache::{CongestionState, PathCache};
+    use crate::server_config::{ServerConfig, SharedConfig};
+
+    #[test]
+    fn paths_of_all_shards() {
+        let path_cache = PathCache::new(Arc::new(SharedConfig::new(ServerConfig::new(PathBuf::from("/srv/soft")))));
+        let addrs: Vec<SocketAddr> = (0..64).map(|port| SocketAddr::from(([127, 0, 0, 1], 1000 + port))).collect();
+        for (i, addr) in addrs.iter().enumerate() {
+            let mut congestion_state = path_cache.get(*addr);
+            for _ in 0..i {
+                congestion_state.increase_congestion_window();
+            }
+            path_cache.store(*addr, &congestion_state);
+        }
+        for (i, addr) in addrs.iter().enumerate() {
+            assert_eq!(path_cache.get(*addr).congestion_window(), 1 + i as u16);
+        }
+    }
+
+    #[test]
+    fn congestion_phases() {
+        let mut congestion_state = CongestionState::initial();
+        for _ in 0..7 {
+            congestion_state.increase_congestion_window();
+        }
+        assert_eq!(congestion_state.congestion_window(), 8);
+        congestion_state.decrease_congestion_window();
+        assert_eq!(congestion_state.congestion_window(), 4);
+        assert!(!congestion_state.is_slow_start());
+        // one packet per round trip
+        for _ in 0..4 {
+            congestion_state.increase_congestion_window();
+        }
+        assert_eq!(congestion_state.congestion_window(), 4);
+        congestion_state.reset_congestion_window();
+        assert_eq!(congestion_state.congestion_window(), 1);
+        assert!(congestion_state.is_slow_start());
+    }
+}
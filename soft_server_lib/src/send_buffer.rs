@@ -1,3 +1,4 @@
+use std::cmp::max;
 use std::collections::VecDeque;
 use soft_shared_lib::field_types::SequenceNumber;
 
@@ -10,7 +11,9 @@ pub struct SendBuffer {
     /// packets that are already transferred but not acknowledged
     packet_queue: VecDeque<Vec<u8>>,
     /// the sequence number of the element at the front of the queue
-    front_sequence_number: SequenceNumber
+    front_sequence_number: SequenceNumber,
+    /// the sequence number of the first packet that has been added, but not sent yet, see take_unsent
+    unsent_sequence_number: SequenceNumber,
 }
 
 impl SendBuffer {
@@ -20,6 +23,7 @@ impl SendBuffer {
             memory_cache: VecDeque::new(),
             packet_queue: VecDeque::new(),
             front_sequence_number: 0,
+            unsent_sequence_number: 0,
         }
     }
 
@@ -33,6 +37,14 @@ impl SendBuffer {
         self.packet_queue.get_mut(self.packet_queue.len() - 1).unwrap()
     }
 
+    /// give back the buffer of the last added packet, e.g. if it could not be filled
+    pub fn remove_last(&mut self) {
+        if let Some(mut vec) = self.packet_queue.pop_back() {
+            vec.clear();
+            self.memory_cache.push_front(vec);
+        }
+    }
+
     pub fn get(&mut self, sequence_number: SequenceNumber) -> Option<&mut [u8]> {
         if sequence_number < self.front_sequence_number {
             return None;
@@ -50,7 +62,74 @@ impl SendBuffer {
         }
     }
 
+    /// the number of packets that have been added since the last take_unsent
+    pub fn unsent(&self) -> u64 {
+        self.end() - max(self.unsent_sequence_number, self.front_sequence_number)
+    }
+
+    /// the sequence number of the first packet that has been added since the last call and these packets,
+    /// so they can be sent as one batch
+    pub fn take_unsent(&mut self) -> (SequenceNumber, Vec<&[u8]>) {
+        let start = max(self.unsent_sequence_number, self.front_sequence_number);
+        self.unsent_sequence_number = self.end();
+        let packets = self.packet_queue.iter()
+            .skip((start - self.front_sequence_number) as usize)
+            .map(Vec::as_slice)
+            .collect();
+        (start, packets)
+    }
+
+    /// the sequence number after the last buffered packet
+    pub fn end(&self) -> SequenceNumber {
+        self.front_sequence_number + self.len()
+    }
+
     pub fn len(&self) -> u64 {
         self.packet_queue.len() as u64
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use crate::send_buffer::SendBuffer;
+
+    fn add(send_buffer: &mut SendBuffer, packet: &[u8]) {
+        send_buffer.add().write_all(packet).unwrap();
+    }
+
+    #[test]
+    fn take_unsent() {
+        let mut send_buffer = SendBuffer::new();
+        add(&mut send_buffer, b"0");
+        add(&mut send_buffer, b"1");
+        assert_eq!(send_buffer.unsent(), 2);
+        assert_eq!(send_buffer.take_unsent(), (0, vec![&b"0"[..], b"1"]));
+        assert_eq!(send_buffer.unsent(), 0);
+        assert_eq!(send_buffer.take_unsent(), (2, vec![]));
+
+        // the sent packets stay buffered for retransmissions
+        add(&mut send_buffer, b"2");
+        assert_eq!(send_buffer.unsent(), 1);
+        assert_eq!(send_buffer.get(0).unwrap(), b"0");
+
+        // acknowledged packets are not sent, even if they have not been sent yet
+        add(&mut send_buffer, b"3");
+        send_buffer.drop_before(3);
+        assert_eq!(send_buffer.unsent(), 1);
+        assert_eq!(send_buffer.take_unsent(), (3, vec![&b"3"[..]]));
+        assert_eq!(send_buffer.end(), 4);
+    }
+
+    #[test]
+    fn remove_last() {
+        let mut send_buffer = SendBuffer::new();
+        add(&mut send_buffer, b"0");
+        send_buffer.add().extend_from_slice(b"incomplete");
+        send_buffer.remove_last();
+        assert_eq!(send_buffer.end(), 1);
+        // the buffer is reused
+        assert!(send_buffer.add().is_empty());
+        assert_eq!(send_buffer.take_unsent(), (0, vec![&b"0"[..], b""]));
+    }
+}
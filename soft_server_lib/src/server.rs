@@ -3,109 +3,335 @@ use tokio::runtime::Runtime;
 use std::time::Duration;
 use soft_shared_lib::constants::SOFT_MAX_PACKET_SIZE;
 use soft_shared_lib::packet::packet_buf::PacketBuf;
-use ttl_cache::TtlCache;
-use soft_shared_lib::field_types::{ConnectionId};
-use crate::connection::Connection;
-use tokio::sync::Mutex;
+use soft_shared_lib::packet::err_packet::ErrPacket;
+use soft_shared_lib::general::byte_view::ByteView;
+use soft_shared_lib::soft_error_code::SoftErrorCode;
+use crate::connection::{Connection, ConnectionContext};
 use std::sync::{Arc};
-use soft_shared_lib::times::{connection_timeout, INITIAL_RTT};
-use log::{info, trace};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::Ordering::SeqCst;
+use log::{info, trace, warn};
 use std::net::SocketAddr;
-use rand::Rng;
 use crate::file_sandbox::FileSandbox;
 use crate::checksum_cache::ChecksumCache;
+use crate::checksum_store::ChecksumStore;
+use crate::snapshot_store::SnapshotStore;
+use crate::file_source::{FileSource, LocalFileSource, SymlinkPolicy};
+use crate::archive_source::ArchiveFileSource;
+use crate::mount_table::{BandwidthClass, Mount, MountFileSource, MountTable};
+use crate::checksum_precomputer::ChecksumPrecomputer;
 use crate::path_cache::PathCache;
+use crate::connection_table::ConnectionTable;
 use core::mem;
 use tokio::task::JoinHandle;
 use std::ops::Deref;
 use soft_shared_async_lib::general::loss_simulation_udp_socket::LossSimulationUdpSocket;
+use crate::server_config::{ServerConfig, SharedConfig};
+use crate::stats::{ServerStats, TransferCounters};
+use crate::observer::{Observers, ServerObserver};
+use crate::access_log::AccessLog;
+use tokio::net::{TcpListener, TcpStream};
+use soft_shared_lib::helper::ecn_helper::Ecn;
+use soft_shared_lib::helper::socket_helper::{bind_reuse_port, RecvBatch};
+use crate::buffer_pool::BufferPool;
 
 pub const MAX_SIMULTANEOUS_CONNECTIONS: usize = 100;
 pub const FILE_READER_BUFFER_SIZE: usize = 2usize.pow(16);
+/// how often a shutdown checks whether the active transfers have finished
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// how long a shutdown waits for stopped connections to send their STOP errors
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+/// the maximum number of packets received with one system call
+const RECEIVE_BATCH_SIZE: usize = 32;
+/// the maximum number of handled packet buffers a shard keeps for reuse, in addition to the buffers of a batch
+const POOLED_RECEIVE_BUFFERS: usize = 2 * RECEIVE_BATCH_SIZE;
+/// the request of a metrics scrape is not parsed, only read up to this size
+const METRICS_REQUEST_SIZE: usize = 4096;
+/// a scrape that is not answered within this time is aborted, so slow clients do not keep connections open
+const METRICS_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Server {
     local_addr: SocketAddr,
+    /// None if the metrics endpoint is disabled
+    metrics_addr: Option<SocketAddr>,
     runtime: Runtime,
-    connections: Arc<Mutex<TtlCache<ConnectionId, Arc<Connection>>>>,
+    connections: Arc<ConnectionTable>,
     file_sandbox: Arc<FileSandbox>,
     checksum_cache: Arc<ChecksumCache>,
     congestion_cache: Arc<PathCache>,
+    mount_table: Arc<MountTable>,
+    /// the configured observer and the access log
+    observer: Arc<dyn ServerObserver>,
+    /// None if the access log is disabled or can not be opened
+    access_log: Option<Arc<AccessLog>>,
+    /// replaced by reload
+    config: Arc<SharedConfig>,
+    /// new requests are answered with SERVER_UNAVAILABLE, see shutdown
+    shutting_down: Arc<AtomicBool>,
+    /// DATA packets and ACKs of all connections
+    transfer_counters: Arc<TransferCounters>,
+    accepted_connections: Arc<AtomicU64>,
  }
 
 impl Server {
 
     pub fn start<A: std::net::ToSocketAddrs>(addr: A, served_dir: PathBuf, first_loss_probability: f64, repeated_loss_probability: f64) -> Server {
+        let mut config = ServerConfig::new(served_dir);
+        config.first_loss_probability = first_loss_probability;
+        config.repeated_loss_probability = repeated_loss_probability;
+        Self::start_with_config(addr, config)
+    }
+
+    pub fn start_with_config<A: std::net::ToSocketAddrs>(addr: A, config: ServerConfig) -> Server {
         let runtime = Runtime::new().unwrap();
 
         let addr: Vec<SocketAddr> = addr.to_socket_addrs().unwrap().collect();
-        let socket = runtime.block_on(async { LossSimulationUdpSocket::bind(addr.as_slice(), first_loss_probability, repeated_loss_probability).await }).unwrap();
+        let sockets = runtime.block_on(async { Self::bind_sockets(&addr, &config).await }).unwrap();
+        for socket in &sockets {
+            // routers can signal congestion by marking ECN capable packets instead of dropping them
+            if let Err(e) = socket.set_ecn_marking(Ecn::Ect0) {
+                warn!("failed to enable ECN, caused by: {}", e);
+            }
+        }
+
+        let metrics_listener = config.metrics_addr.and_then(|metrics_addr| {
+            match runtime.block_on(TcpListener::bind(metrics_addr)) {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    warn!("failed to serve metrics on {}, caused by: {}", metrics_addr, e);
+                    None
+                }
+            }
+        });
+
+        let access_log = Self::open_access_log(&config);
+        let observers = config.observer.iter().cloned()
+            .chain(access_log.iter().map(|access_log| access_log.clone() as Arc<dyn ServerObserver>))
+            .collect();
 
+        let mount_table = Arc::new(config.mount_table());
+        let connections = Arc::new(ConnectionTable::new(sockets.len(), config.max_connections));
+        let checksum_cache = ChecksumCache::new(Self::open_checksum_store(&config, &mount_table), config.max_concurrent_checksums, config.checksum_cache_ttl);
+        let file_sandbox = Arc::new(FileSandbox::new(
+            Self::file_source(&config, &mount_table),
+            mount_table.clone(),
+            Self::hidden_files(&config),
+            config.serve_dot_files,
+            Self::open_snapshot_store(&config),
+        ));
+        let config = Arc::new(SharedConfig::new(config));
         let server = Server {
-            local_addr: socket.local_addr().unwrap(),
+            local_addr: sockets[0].local_addr().unwrap(),
+            metrics_addr: metrics_listener.as_ref().map(|listener| listener.local_addr().unwrap()),
             runtime,
-            connections: Arc::new(Mutex::new(TtlCache::new(MAX_SIMULTANEOUS_CONNECTIONS))),
-            file_sandbox: Arc::new(FileSandbox::new(served_dir.clone())),
-            checksum_cache: ChecksumCache::new(),
-            congestion_cache: Arc::new(PathCache::new()),
+            connections,
+            file_sandbox,
+            checksum_cache,
+            congestion_cache: Arc::new(PathCache::new(config.clone())),
+            mount_table,
+            observer: Arc::new(Observers(observers)),
+            access_log,
+            config,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            transfer_counters: Arc::new(TransferCounters::default()),
+            accepted_connections: Arc::new(AtomicU64::new(0)),
         };
 
         info!(
             "server start listening on port {}, serving {}",
             server.local_addr().port(),
-            served_dir.to_str().unwrap()
+            server.config.current().served_dir.to_str().unwrap()
         );
 
-        server.spawn(socket);
+        for (shard, socket) in sockets.into_iter().enumerate() {
+            server.spawn(socket, shard);
+        }
+        for mount in server.mount_table.mounts() {
+            if mount.precompute_checksums {
+                server.spawn_checksum_precomputer(mount.clone());
+            }
+        }
+        if let Some(listener) = metrics_listener {
+            info!("serving metrics on {}", listener.local_addr().unwrap());
+            server.spawn_metrics_endpoint(listener);
+        }
 
         server
     }
 
-    fn spawn(&self, socket: LossSimulationUdpSocket) -> JoinHandle<()> {
+    /// one socket per shard, several shards share the port with SO_REUSEPORT
+    ///
+    /// SO_REUSEPORT would also let the sockets share the port with any other process of the same user,
+    /// so the port is first bound without it to fail if it is already in use
+    async fn bind_sockets(addr: &[SocketAddr], config: &ServerConfig) -> std::io::Result<Vec<LossSimulationUdpSocket>> {
+        let (p, q) = (config.first_loss_probability, config.repeated_loss_probability);
+        if config.shards <= 1 {
+            return Ok(vec![LossSimulationUdpSocket::bind(addr, p, q).await?]);
+        }
+        // an unspecified port is resolved by the probe
+        let local_addr = std::net::UdpSocket::bind(addr)?.local_addr()?;
+        (0..config.shards)
+            .map(|_| LossSimulationUdpSocket::from_std(bind_reuse_port(local_addr)?, p, q))
+            .collect()
+    }
+
+    /// checksums are only kept in memory if the store can not be opened
+    fn open_checksum_store(config: &ServerConfig, mount_table: &MountTable) -> Option<ChecksumStore> {
+        let path = config.checksum_store_path.as_ref()?;
+        match ChecksumStore::open(path, mount_table) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!("failed to open checksum store {:?}, caused by: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// the files of the mounted directories, the root is replaced by another source if it is configured
+    fn file_source(config: &ServerConfig, mount_table: &MountTable) -> Arc<dyn FileSource> {
+        let sources = mount_table.mounts().iter().map(|mount| {
+            let source: Arc<dyn FileSource> = match &config.file_source {
+                Some(file_source) if mount.prefix.is_empty() => file_source.clone(),
+                _ => Arc::new(LocalFileSource::with_symlink_policy(mount.dir.clone(), config.effective_symlink_policy())
+                    .with_reader_buffer_size(config.file_reader_buffer_size)),
+            };
+            (mount.clone(), source)
+        });
+        let file_source = Arc::new(MountFileSource::new(sources.collect()));
+        match config.serve_archive_members {
+            true => Arc::new(ArchiveFileSource::new(file_source)),
+            false => file_source,
+        }
+    }
+
+    /// files of the server in the served directory
+    fn hidden_files(config: &ServerConfig) -> Vec<PathBuf> {
+        let checksum_store_files = config.checksum_store_path.iter().flat_map(|path| ChecksumStore::files(path));
+        checksum_store_files.chain(config.snapshot_dir.iter().cloned()).collect()
+    }
+
+    /// transfers are not logged if the access log can not be opened
+    fn open_access_log(config: &ServerConfig) -> Option<Arc<AccessLog>> {
+        let path = config.access_log_path.as_ref()?;
+        match AccessLog::open(path, config.access_log_format) {
+            Ok(access_log) => Some(Arc::new(access_log)),
+            Err(e) => {
+                warn!("failed to open access log {:?}, caused by: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// files are served directly if the store can not be opened
+    fn open_snapshot_store(config: &ServerConfig) -> Option<SnapshotStore> {
+        let dir = config.snapshot_dir.as_ref()?;
+        match SnapshotStore::open(dir, config.snapshot_retention) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!("failed to open snapshot store {:?}, caused by: {}", dir, e);
+                None
+            }
+        }
+    }
+
+    /// the receive loop of a shard, the shard owns the connections of the requests it receives
+    fn spawn(&self, socket: LossSimulationUdpSocket, shard: usize) -> JoinHandle<()> {
         let connections = self.connections.clone();
         let congestion_cache = self.congestion_cache.clone();
         let checksum_cache = self.checksum_cache.clone();
         let file_sandbox = self.file_sandbox.clone();
+        let mount_table = self.mount_table.clone();
+        let config = self.config.clone();
+        let shutting_down = self.shutting_down.clone();
+        let transfer_counters = self.transfer_counters.clone();
+        let accepted_connections = self.accepted_connections.clone();
+        let observer = self.observer.clone();
         self.runtime.spawn(async move {
             let socket = Arc::new(socket);
+            let context = ConnectionContext {
+                socket: socket.clone(),
+                path_cache: congestion_cache,
+                checksum_cache,
+                server_counters: transfer_counters,
+                observer: observer.clone(),
+                config: config.clone(),
+                receive_buffers: Arc::new(BufferPool::new(SOFT_MAX_PACKET_SIZE, POOLED_RECEIVE_BUFFERS)),
+            };
+            let mut cached_config = config.cached();
+            // a received packet keeps its buffer, which is replaced by a buffer of the pool
+            let mut receive_buffers: Vec<Vec<u8>> = (0..RECEIVE_BATCH_SIZE).map(|_| context.receive_buffers.take()).collect();
+            let mut recv_batch = RecvBatch::default();
+            let mut received = Vec::with_capacity(RECEIVE_BATCH_SIZE);
             loop {
-                let mut receive_buffer = vec![0u8; SOFT_MAX_PACKET_SIZE];
-                let (size, src_addr) = socket.recv_from(&mut receive_buffer).await.unwrap();
-                receive_buffer.truncate(size);
-                let packet = match PacketBuf::new(receive_buffer) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        log::info!("received invalid packet, caused by: {}", e);
-                        continue
-                    }
-                };
-                trace!("received {} from {}", packet, src_addr);
-                match &packet {
-                    PacketBuf::Req(req) => {
-                        let mut connections = connections.lock().await;
-                        let connection_id = Self::generate_connection_id(&connections);
-                        {
-                            let connection = Connection::new(
-                                connection_id,
-                                req.deref(),
-                                src_addr,
-                                socket.clone(),
-                                congestion_cache.clone(),
-                                checksum_cache.clone(),
-                                &file_sandbox,
-                            ).await;
-                            if let Ok(connection) = connection {
-                                connections.insert(connection_id, connection, connection_timeout(INITIAL_RTT));
+                socket.recv_batch(&mut recv_batch, &mut receive_buffers, &mut received).await.unwrap();
+                for (i, &(_, src_addr)) in received.iter().enumerate() {
+                    let buffer = mem::replace(&mut receive_buffers[i], context.receive_buffers.take());
+                    let packet = match PacketBuf::new(buffer) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            log::info!("received invalid packet, caused by: {}", e);
+                            continue
+                        }
+                    };
+                    trace!("received {} from {}", packet, src_addr);
+                    match &packet {
+                        PacketBuf::Req(req) => {
+                            observer.on_request(src_addr, &req.file_name(), req.offset());
+                            let current_config = cached_config.get();
+                            if shutting_down.load(SeqCst) {
+                                info!("rejected request for {} from {} during shutdown", req.file_name(), src_addr);
+                                observer.on_rejected(src_addr, &req.file_name(), req.offset(), SoftErrorCode::ServerUnavailable);
+                                let err = ErrPacket::new_buf(SoftErrorCode::ServerUnavailable, 0);
+                                if let Err(e) = socket.send_to(err.buf(), src_addr).await {
+                                    warn!("failed to send {} to {}, caused by: {}", err, src_addr, e);
+                                }
+                                continue;
+                            }
+                            if !current_config.is_allowed(&req.file_name(), src_addr.ip()) {
+                                info!("denied access to {} for {}", req.file_name(), src_addr);
+                                observer.on_rejected(src_addr, &req.file_name(), req.offset(), SoftErrorCode::AccessDenied);
+                                let err = ErrPacket::new_buf(SoftErrorCode::AccessDenied, 0);
+                                if let Err(e) = socket.send_to(err.buf(), src_addr).await {
+                                    warn!("failed to send {} to {}, caused by: {}", err, src_addr, e);
+                                }
+                                continue;
+                            }
+                            let permit = match connections.try_reserve() {
+                                Some(permit) => permit,
+                                None => {
+                                    info!("rejected request for {} from {}, the server has the maximum number of connections", req.file_name(), src_addr);
+                                    observer.on_rejected(src_addr, &req.file_name(), req.offset(), SoftErrorCode::ServerUnavailable);
+                                    let err = ErrPacket::new_buf(SoftErrorCode::ServerUnavailable, 0);
+                                    if let Err(e) = socket.send_to(err.buf(), src_addr).await {
+                                        warn!("failed to send {} to {}, caused by: {}", err, src_addr, e);
+                                    }
+                                    continue;
+                                }
+                            };
+                            let mut owned_connections = connections.shard(shard).lock().await;
+                            let connection_id = connections.generate_connection_id(&owned_connections, shard);
+                            let bandwidth_class = mount_table.resolve(&req.file_name()).map(|(mount, _)| mount.bandwidth_class);
+                            let scavenger = req.scavenger() || current_config.is_scavenger_path(&req.file_name()) || bandwidth_class == Some(BandwidthClass::Scavenger);
+                            {
+                                let connection = Connection::new(connection_id, req.deref(), src_addr, &context, &file_sandbox, scavenger, permit).await;
+                                if let Ok(connection) = connection {
+                                    accepted_connections.fetch_add(1, SeqCst);
+                                    ConnectionTable::remove_stopped(&mut owned_connections);
+                                    let timeouts = current_config.timeouts;
+                                    owned_connections.insert(connection_id, connection, timeouts.connection_timeout(timeouts.initial_rtt));
+                                }
                             }
                         }
-                    }
-                    _ => {
-                        let connection_id = packet.connection_id_or_none().unwrap();
-                        let mut connections = connections.lock().await;
-                        if let Some(connection) = connections.remove(&connection_id) {
-                            let _ = connection.packet_sender.send((packet, src_addr)).await;
-                            // update ttl
-                            let rtt = connection.rtt().await;
-                            connections.insert(connection_id, connection, connection_timeout(rtt));
+                        _ => {
+                            let connection_id = packet.connection_id_or_none().unwrap();
+                            // the packets of a migrated client can be received by another shard
+                            let mut owning_connections = connections.shard_of(connection_id).lock().await;
+                            if let Some(connection) = owning_connections.remove(&connection_id) {
+                                connection.send_packet(packet, src_addr).await;
+                                // update ttl
+                                let rtt = connection.rtt();
+                                owning_connections.insert(connection_id, connection, cached_config.get().timeouts.connection_timeout(rtt));
+                            }
                         }
                     }
                 }
@@ -113,20 +339,176 @@ impl Server {
         })
     }
 
-    fn generate_connection_id<T>(map: &TtlCache<ConnectionId, T>) -> ConnectionId{
-        let mut rng = rand::thread_rng();
-        loop {
-            let connection_id: u32 = rng.gen();
-            if !map.contains_key(&connection_id) {
-                return connection_id;
+    fn spawn_checksum_precomputer(&self, mount: Mount) -> JoinHandle<()> {
+        let precomputer = ChecksumPrecomputer::new(
+            mount,
+            self.file_sandbox.clone(),
+            self.checksum_cache.clone(),
+        );
+        self.runtime.spawn(async move {
+            if let Err(e) = precomputer.run().await {
+                warn!("checksum precomputation stopped, caused by: {}", e);
+            }
+        })
+    }
+
+    /// answers every TCP connection with the statistics in the Prometheus text format
+    ///
+    /// every connection is answered by its own task, so a slow client does not delay the others
+    fn spawn_metrics_endpoint(&self, listener: TcpListener) -> JoinHandle<()> {
+        let connections = self.connections.clone();
+        let transfer_counters = self.transfer_counters.clone();
+        let accepted_connections = self.accepted_connections.clone();
+        let checksum_cache = self.checksum_cache.clone();
+        let per_connection = self.config.current().metrics_per_connection;
+        self.runtime.spawn(async move {
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("failed to accept metrics connection, caused by: {}", e);
+                        continue;
+                    }
+                };
+                let connections = connections.clone();
+                let transfer_counters = transfer_counters.clone();
+                let accepted_connections = accepted_connections.clone();
+                let checksum_cache = checksum_cache.clone();
+                tokio::spawn(async move {
+                    let response = async {
+                        let stats = Self::collect_stats(&connections, &transfer_counters, &accepted_connections, &checksum_cache).await;
+                        Self::respond_metrics(stream, stats, per_connection).await
+                    };
+                    match tokio::time::timeout(METRICS_TIMEOUT, response).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => log::debug!("failed to send metrics to {}, caused by: {}", peer_addr, e),
+                        Err(_) => log::debug!("metrics request of {} timed out", peer_addr),
+                    }
+                });
+            }
+        })
+    }
+
+    async fn respond_metrics(mut stream: TcpStream, stats: ServerStats, per_connection: bool) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut request = vec![0u8; METRICS_REQUEST_SIZE];
+        let _ = stream.read(&mut request).await?;
+        let body = stats.to_prometheus(per_connection);
+        let header = format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    async fn collect_stats(connections: &ConnectionTable, transfer_counters: &TransferCounters, accepted_connections: &AtomicU64, checksum_cache: &ChecksumCache) -> ServerStats {
+        let mut connection_stats = Vec::new();
+        for connection in connections.active_connections().await {
+            if let Some(snapshot) = connection.snapshot().await {
+                connection_stats.push(snapshot.stats);
             }
         }
+        let (checksum_cache_hits, checksum_cache_misses) = checksum_cache.hits_and_misses();
+        ServerStats {
+            total_connections: accepted_connections.load(SeqCst),
+            bytes_sent: transfer_counters.bytes_sent(),
+            retransmissions: transfer_counters.retransmissions(),
+            duplicate_acks: transfer_counters.duplicate_acks(),
+            checksum_cache_hits,
+            checksum_cache_misses,
+            connections: connection_stats,
+        }
     }
 
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
 
+    /// open the access log file again, e.g. after it has been rotated
+    pub fn reopen_access_log(&self) -> std::io::Result<()> {
+        match &self.access_log {
+            Some(access_log) => access_log.reopen(),
+            None => Ok(()),
+        }
+    }
+
+    /// apply the settings of the config that are safe to change while transfers are running,
+    /// see ServerConfig::with_live_settings
+    ///
+    /// the other settings are only applied by a restart, a warning is logged for each changed setting
+    pub fn reload(&self, config: ServerConfig) {
+        let current = self.config.current();
+        for setting in current.restart_required_changes(&config) {
+            warn!("{} changed, restart the server to apply it", setting);
+        }
+        self.checksum_cache.set_entry_ttl(config.checksum_cache_ttl);
+        let reloaded = current.with_live_settings(&config);
+        let symlink_policy = reloaded.effective_symlink_policy();
+        if symlink_policy == current.effective_symlink_policy() {
+            self.config.replace(reloaded);
+        } else {
+            // the access rules deny symbolic links, see ServerConfig::effective_symlink_policy,
+            // so links are denied before the first rule applies and only allowed after the last rule has been removed
+            let file_source = Self::file_source(&reloaded, &self.mount_table);
+            if symlink_policy == SymlinkPolicy::Deny {
+                self.file_sandbox.replace_source(file_source);
+                self.config.replace(reloaded);
+            } else {
+                self.config.replace(reloaded);
+                self.file_sandbox.replace_source(file_source);
+            }
+        }
+        info!("reloaded configuration");
+    }
+
+    /// the current configuration, including the settings changed by reload
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.current()
+    }
+
+    /// the address of the metrics endpoint, see ServerConfig::metrics_addr
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// snapshot of the active connections and the counters since the server has been started
+    pub fn stats(&self) -> ServerStats {
+        self.runtime.block_on(Self::collect_stats(&self.connections, &self.transfer_counters, &self.accepted_connections, &self.checksum_cache))
+    }
+
+    /// stop the server without interrupting transfers that finish within the timeout
+    ///
+    /// new requests are answered with SERVER_UNAVAILABLE,
+    /// transfers that are still active after the timeout are stopped with STOP, so the clients can resume them later
+    pub fn shutdown(self, timeout: Duration) {
+        self.shutting_down.store(true, SeqCst);
+        let connections = self.connections.clone();
+        self.runtime.block_on(async move {
+            let deadline = tokio::time::Instant::now() + timeout;
+            let mut active = connections.active_connections().await;
+            if !active.is_empty() {
+                info!("shutting down, waiting for {} active transfers", active.len());
+            }
+            while !active.is_empty() && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+                active = connections.active_connections().await;
+            }
+            if active.is_empty() {
+                return;
+            }
+            info!("stopping {} active transfers", active.len());
+            for connection in &active {
+                connection.stop().await;
+            }
+            let stop_deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+            while active.iter().any(|connection| !connection.stopped()) && tokio::time::Instant::now() < stop_deadline {
+                tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+            }
+        });
+    }
+
 }
 
 impl Drop for Server {
@@ -142,7 +524,8 @@ mod tests {
     use crate::server::Server;
     use tempdir::TempDir;
     use std::net::{UdpSocket, SocketAddr};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+    use std::collections::BTreeMap;
     use std::fs::File;
     use std::io::{Write, ErrorKind};
     use std::thread::sleep;
@@ -157,23 +540,48 @@ mod tests {
     use soft_shared_lib::packet::packet_buf::{AccPacketBuf, DataPacketBuf, PacketBuf};
     use soft_shared_lib::packet::ack_packet::AckPacket;
     use soft_shared_lib::soft_error_code::SoftErrorCode;
+    use soft_shared_lib::constants::REQ_FLAG_SCAVENGER;
+    use crate::checksum_store::CHECKSUM_STORE_FILE_NAME;
+    use crate::snapshot_store::SNAPSHOT_DIR_NAME;
+    use crate::file_source::MemoryFileSource;
+    use crate::archive_source::tests::tar;
+    use crate::mount_table::Mount;
+    use std::sync::Arc;
+    use crate::server_config::ServerConfig;
+    use crate::observer::{ServerObserver, TransferInfo};
+    #[cfg(target_os = "linux")]
+    use soft_shared_lib::helper::ecn_helper::{Ecn, enable_ecn_reception, recv_with_ecn};
 
     /// add some methods to Sever for testing
     impl Server {
         fn count_connections(&self) -> usize {
-            let connections = self.connections.clone();
-            self.runtime.block_on(async move {
-                let mut connections = connections.lock().await;
-                connections.iter().filter(|c| !c.1.stopped()).count()
-            })
+            self.runtime.block_on(self.connections.active_connections()).len()
+        }
+
+        /// panics if the number of connections does not reach the count within a second
+        fn wait_for_connections(&self, count: usize) {
+            let deadline = std::time::Instant::now() + Duration::from_secs(1);
+            while self.count_connections() != count {
+                assert!(std::time::Instant::now() < deadline, "unexpected number of connections {}", self.count_connections());
+                sleep(Duration::from_millis(1));
+            }
+        }
+
+        /// panics if the max window of the connection does not match the predicate within a second
+        fn wait_for_max_window<P: Fn(u16) -> bool>(&self, connection_id: ConnectionId, predicate: P) {
+            let deadline = std::time::Instant::now() + Duration::from_secs(1);
+            while !predicate(self.max_window_of(connection_id).unwrap()) {
+                assert!(std::time::Instant::now() < deadline, "unexpected max window {:?}", self.max_window_of(connection_id));
+                sleep(Duration::from_millis(1));
+            }
         }
 
         fn max_window_of(&self, connection_id: ConnectionId) -> Option<u16> {
             self.runtime.block_on(async move {
-                let connections = self.connections.lock().await;
+                let connections = self.connections.shard_of(connection_id).lock().await;
                 match connections.get(&connection_id) {
                     None => None,
-                    Some(connection) => Some(connection.max_window().await)
+                    Some(connection) => connection.snapshot().await.map(|snapshot| snapshot.max_window)
                 }
             })
         }
@@ -182,7 +590,7 @@ mod tests {
     fn retry_req_until_checksum_ready(client_socket: &UdpSocket, req: &ReqPacket, server_addr: SocketAddr) -> AccPacketBuf {
         loop {
             client_socket.send_to(req.buf(), server_addr).unwrap();
-            match receive(&client_socket).unwrap().0 {
+            match receive(client_socket).unwrap().0 {
                 PacketBuf::Acc(acc) => {
                     break acc
                 }
@@ -230,7 +638,7 @@ mod tests {
         // server should send nothing here
         assert_eq!(client_socket.recv(&mut []).err().map(|e| e.kind()), Some(ErrorKind::WouldBlock));
         // send Ack 0
-        client_socket.send_to(&AckPacket::new_buf(
+        client_socket.send_to(AckPacket::new_buf(
             10,
             connection_id,
             0
@@ -256,7 +664,7 @@ mod tests {
             expected_sequence_number += 1;
             // send Ack
             client_socket.send_to(
-                &AckPacket::new_buf(
+                AckPacket::new_buf(
                     10,
                     connection_id,
                     expected_sequence_number
@@ -305,7 +713,7 @@ mod tests {
 
         // send Ack 0
         client_socket.send_to(
-            &AckPacket::new_buf(
+            AckPacket::new_buf(
                 10,
                 connection_id,
                 0
@@ -325,7 +733,7 @@ mod tests {
 
         // send Ack 1
         client_socket.send_to(
-            &AckPacket::new_buf(
+            AckPacket::new_buf(
                 10,
                 connection_id,
                 1
@@ -345,12 +753,103 @@ mod tests {
         drop(server);
     }
 
+    #[test]
+    fn sharded_migration(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 22;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        let file_content = "hello world ".repeat(8);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), &file_content).unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.shards = 4;
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // every ACK is sent from another port, so the ACKs are received by different shards
+        let mut received_file_content = Vec::new();
+        let mut sequence_number = 0;
+        while received_file_content.len() < file_content.len() {
+            let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+            client_socket.send_to(AckPacket::new_buf(1, connection_id, sequence_number).buf(), server.local_addr).unwrap();
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            assert_eq!(data_packet.sequence_number(), sequence_number);
+            received_file_content.write(data_packet.data()).unwrap();
+            sequence_number += 1;
+        }
+        assert_eq!(std::str::from_utf8(&received_file_content).unwrap(), file_content);
+        assert_eq!(server.count_connections(), 1);
+
+        drop(server);
+    }
+
+    #[test]
+    fn sharded_port_in_use(){
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = ServerConfig::new(TempDir::new("soft_test").unwrap().path().to_path_buf());
+        config.shards = 2;
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(Server::bind_sockets(&[other.local_addr().unwrap()], &config)).is_err());
+    }
+
+    #[test]
+    fn max_connections(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 100;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), "hello world").unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.shards = 2;
+        config.max_connections = 1;
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // the running transfer is not evicted by another request
+        let other_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        other_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        other_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&other_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::ServerUnavailable),
+            _ => panic!("unexpected packet"),
+        }
+        client_socket.send_to(AckPacket::new_buf(10, connection_id, 0).buf(), server.local_addr).unwrap();
+        let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(data_packet.data(), b"hello world");
+
+        // the finished transfer releases its connection
+        client_socket.send_to(AckPacket::new_buf(10, connection_id, 1).buf(), server.local_addr).unwrap();
+        loop {
+            other_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+            match receive(&other_socket).unwrap().0 {
+                PacketBuf::Acc(_) => break,
+                PacketBuf::Err(e) if e.error_code() == SoftErrorCode::ServerUnavailable => sleep(Duration::from_millis(10)),
+                _ => panic!("unexpected packet"),
+            }
+        }
+
+        drop(server);
+    }
+
     #[test]
     fn retransmission(){
         const FILE_NAME: &str = "hello.txt";
         const FILE_CONTENT: &str = "hello world";
         const MAX_PACKET_SIZE: MaxPacketSize = 100; // content fit in one packet
-        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000); // longer than the minimum retransmission timeout
 
         //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
 
@@ -378,7 +877,7 @@ mod tests {
 
         // send Ack 0
         client_socket.send_to(
-            &AckPacket::new_buf(
+            AckPacket::new_buf(
                 10,
                 connection_id,
                 0
@@ -396,7 +895,7 @@ mod tests {
 
         // send Ack 1
         client_socket.send_to(
-            &AckPacket::new_buf(
+            AckPacket::new_buf(
                 10,
                 connection_id,
                 1
@@ -412,11 +911,78 @@ mod tests {
     }
 
     #[test]
-    fn resumption(){
+    fn scavenger_transfer(){
+        const FILE_NAME: &str = "backup.bin";
+        const MAX_PACKET_SIZE: MaxPacketSize = 100;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        let file_content = "backup".repeat(100);
+
+        //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
+
+        // start server
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let mut file = File::create(served_dir.path().join(FILE_NAME)).unwrap();
+        file.write(file_content.as_bytes()).unwrap();
+        let server = Server::start("127.0.0.1:0", served_dir.into_path(), 0.0, 0.0);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let mut received_file_content = Vec::<u8>::with_capacity(file_content.len());
+
+        // create Req
+        let req_packet = ReqPacket::new_buf_with_flags(
+            MAX_PACKET_SIZE,
+            FILE_NAME,
+            0,
+            REQ_FLAG_SCAVENGER,
+        );
+
+        // receive Acc
+        let acc_packet: AccPacketBuf = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // send Ack 0
+        client_socket.send_to(
+            AckPacket::new_buf(
+                10,
+                connection_id,
+                0
+            ).buf(),
+            server.local_addr()
+        ).unwrap();
+
+        let mut expected_sequence_number = 0;
+        while received_file_content.len() != file_content.len() {
+            // receive Data
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            assert_eq!(data_packet.sequence_number(), expected_sequence_number);
+            received_file_content.write(data_packet.data()).unwrap();
+            expected_sequence_number += 1;
+            // send Ack
+            client_socket.send_to(
+                AckPacket::new_buf(
+                    10,
+                    connection_id,
+                    expected_sequence_number
+                ).buf(),
+                server.local_addr()
+            ).unwrap();
+        }
+
+        // validate content
+        assert_eq!(std::str::from_utf8(&received_file_content).unwrap(), file_content);
+
+        // stop server
+        drop(server);
+    }
+
+    #[test]
+    fn zero_window(){
         const FILE_NAME: &str = "hello.txt";
         const FILE_CONTENT: &str = "hello world";
-        const MAX_PACKET_SIZE: MaxPacketSize = 22; // content fit in two packet
-        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(2000); // longer than the persist timeout
 
         //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
 
@@ -426,6 +992,833 @@ mod tests {
         file.write(FILE_CONTENT.as_bytes()).unwrap();
         let server = Server::start("127.0.0.1:0", served_dir.into_path(), 0.0, 0.0);
 
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let mut received_file_content = Vec::<u8>::with_capacity(FILE_CONTENT.len());
+
+        // create Req
+        let req_packet = ReqPacket::new_buf(
+            MAX_PACKET_SIZE,
+            FILE_NAME,
+            0
+        );
+
+        // receive Acc
+        let acc_packet: AccPacketBuf = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // send Ack 0 with closed receive window
+        client_socket.send_to(
+            AckPacket::new_buf(
+                0,
+                connection_id,
+                0
+            ).buf(),
+            server.local_addr()
+        ).unwrap();
+
+        // receive window probe Data 0 after the persist timeout
+        let send_instant = Instant::now();
+        let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert!(send_instant.elapsed() >= Duration::from_millis(100));
+        assert_eq!(data_packet.sequence_number(), 0);
+        received_file_content.write(data_packet.data()).unwrap();
+        drop(data_packet);
+
+        // receive window is still closed
+        client_socket.send_to(
+            AckPacket::new_buf(
+                0,
+                connection_id,
+                1
+            ).buf(),
+            server.local_addr()
+        ).unwrap();
+
+        // receive window probe Data 1
+        let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(data_packet.sequence_number(), 1);
+        received_file_content.write(data_packet.data()).unwrap();
+        drop(data_packet);
+
+        // acknowledge Data 1 and reopen the receive window with a separate window update
+        for receive_window in [0, 10] {
+            client_socket.send_to(
+                AckPacket::new_buf(
+                    receive_window,
+                    connection_id,
+                    2
+                ).buf(),
+                server.local_addr()
+            ).unwrap();
+        }
+
+        let mut expected_sequence_number = 2;
+        while received_file_content.len() != FILE_CONTENT.len() {
+            // receive Data
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            assert_eq!(data_packet.sequence_number(), expected_sequence_number);
+            received_file_content.write(data_packet.data()).unwrap();
+            expected_sequence_number += 1;
+            // send Ack
+            client_socket.send_to(
+                AckPacket::new_buf(
+                    10,
+                    connection_id,
+                    expected_sequence_number
+                ).buf(),
+                server.local_addr()
+            ).unwrap();
+        }
+
+        // validate content
+        assert_eq!(std::str::from_utf8(&received_file_content).unwrap(), FILE_CONTENT);
+
+        // stop server
+        drop(server);
+    }
+
+    #[test]
+    fn fast_retransmit(){
+        const FILE_NAME: &str = "hello.txt";
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        const LOST_SEQUENCE_NUMBER: u64 = 6;
+        let file_content = "hello world".repeat(3);
+
+        //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
+
+        // start server
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let mut file = File::create(served_dir.path().join(FILE_NAME)).unwrap();
+        file.write(file_content.as_bytes()).unwrap();
+        let server = Server::start("127.0.0.1:0", served_dir.into_path(), 0.0, 0.0);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+
+        // create Req
+        let req_packet = ReqPacket::new_buf(
+            MAX_PACKET_SIZE,
+            FILE_NAME,
+            0
+        );
+
+        // receive Acc
+        let acc_packet: AccPacketBuf = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // send Ack 0
+        client_socket.send_to(
+            AckPacket::new_buf(
+                10,
+                connection_id,
+                0
+            ).buf(),
+            server.local_addr()
+        ).unwrap();
+
+        let mut received_packets = BTreeMap::<u64, Vec<u8>>::new();
+        let mut next_sequence_number = 0;
+        let mut lost = false;
+        while next_sequence_number != file_content.len() as u64 {
+            // receive Data
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            if data_packet.sequence_number() == LOST_SEQUENCE_NUMBER && !lost {
+                // simulate loss
+                lost = true;
+                continue;
+            }
+            // only the lost packet is retransmitted
+            assert!(received_packets.insert(data_packet.sequence_number(), data_packet.data().to_vec()).is_none());
+            while received_packets.contains_key(&next_sequence_number) {
+                next_sequence_number += 1;
+            }
+            // send Ack, duplicate until the lost packet is retransmitted
+            client_socket.send_to(
+                AckPacket::new_buf(
+                    10,
+                    connection_id,
+                    next_sequence_number
+                ).buf(),
+                server.local_addr()
+            ).unwrap();
+        }
+
+        // validate content
+        let received_file_content: Vec<u8> = received_packets.into_values().flatten().collect();
+        assert_eq!(std::str::from_utf8(&received_file_content).unwrap(), file_content);
+
+        // stop server
+        drop(server);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test_case("127.0.0.1:0"; "ipv4")]
+    #[test_case("[::1]:0"; "ipv6")]
+    fn ecn(addr: &str){
+        const FILE_NAME: &str = "hello.txt";
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        const CE_SEQUENCE_NUMBER: u64 = 5;
+        let file_content = "hello world".repeat(3);
+
+        //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
+
+        // start server
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let mut file = File::create(served_dir.path().join(FILE_NAME)).unwrap();
+        file.write(file_content.as_bytes()).unwrap();
+        let server = Server::start(addr, served_dir.into_path(), 0.0, 0.0);
+
+        let client_socket = UdpSocket::bind(addr).unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        enable_ecn_reception(&client_socket).unwrap();
+
+        // create Req
+        let req_packet = ReqPacket::new_buf(
+            MAX_PACKET_SIZE,
+            FILE_NAME,
+            0
+        );
+
+        // receive Acc
+        let acc_packet: AccPacketBuf = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // send Ack 0
+        client_socket.send_to(
+            AckPacket::new_buf(
+                10,
+                connection_id,
+                0
+            ).buf(),
+            server.local_addr()
+        ).unwrap();
+
+        let mut buf = [0u8; MAX_PACKET_SIZE as usize];
+        let mut next_sequence_number = 0;
+        while next_sequence_number <= CE_SEQUENCE_NUMBER {
+            // receive Data, the client socket is not connected
+            let (size, ecn) = recv_with_ecn(&client_socket, &mut buf).unwrap();
+            let data_packet: DataPacketBuf = PacketBuf::new(buf[..size].to_vec()).unwrap().try_into().unwrap();
+            assert_eq!(ecn, Ecn::Ect0);
+            assert_eq!(data_packet.sequence_number(), next_sequence_number);
+            next_sequence_number += 1;
+            // simulate a router that marks the packet with congestion experienced
+            let ce_count = if data_packet.sequence_number() == CE_SEQUENCE_NUMBER { 1 } else { 0 };
+            let max_window = server.max_window_of(connection_id).unwrap();
+            client_socket.send_to(
+                AckPacket::new_buf_with_extension(
+                    10,
+                    connection_id,
+                    next_sequence_number,
+                    0,
+                    ce_count
+                ).buf(),
+                server.local_addr()
+            ).unwrap();
+            if ce_count == 0 {
+                server.wait_for_max_window(connection_id, |window| window > max_window);
+            } else {
+                server.wait_for_max_window(connection_id, |window| window < max_window);
+            }
+        }
+
+        // stop server
+        drop(server);
+    }
+
+    #[test]
+    fn file_changed(){
+        const FILE_NAME: &str = "hello.txt";
+        const FILE_CONTENT: &str = "hello world";
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        // start server
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join(FILE_NAME), FILE_CONTENT).unwrap();
+        let served_file = served_dir.path().join(FILE_NAME);
+        let server = Server::start("127.0.0.1:0", served_dir.into_path(), 0.0, 0.0);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, FILE_NAME, 0);
+        let acc_packet: AccPacketBuf = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // receive Data 0 with a window of one packet
+        client_socket.send_to(AckPacket::new_buf(1, connection_id, 0).buf(), server.local_addr()).unwrap();
+        let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(data_packet.sequence_number(), 0);
+        drop(data_packet);
+
+        // rewrite the file, so the next read notices the change
+        std::fs::write(&served_file, "HELLO WORLD").unwrap();
+        sleep(Duration::from_millis(150));
+        client_socket.send_to(AckPacket::new_buf(1, connection_id, 1).buf(), server.local_addr()).unwrap();
+
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => {
+                assert_eq!(e.error_code(), SoftErrorCode::FileChanged);
+                assert_eq!(e.connection_id(), connection_id);
+            }
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test]
+    fn memory_file_source(){
+        const FILE_NAME: &str = "hello.txt";
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let file_source = Arc::new(MemoryFileSource::new());
+        file_source.insert(FILE_NAME, b"hello world".to_vec());
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.file_source = Some(file_source.clone());
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, FILE_NAME, 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.file_size(), 11);
+        assert_eq!(acc_packet.checksum(), sha256_from_bytes(b"hello world"));
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        client_socket.send_to(AckPacket::new_buf(1, connection_id, 0).buf(), server.local_addr()).unwrap();
+        let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(data_packet.data(), b"h");
+        drop(data_packet);
+
+        // a new version of the blob replaces the file
+        file_source.insert(FILE_NAME, b"HELLO WORLD".to_vec());
+        sleep(Duration::from_millis(150));
+        client_socket.send_to(AckPacket::new_buf(1, connection_id, 1).buf(), server.local_addr()).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileChanged),
+            _ => panic!("unexpected packet"),
+        }
+
+        // blobs that do not exist are not found
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "world.txt", 0);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test]
+    fn access_denied(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 1200;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::create_dir(served_dir.path().join("lab")).unwrap();
+        std::fs::write(served_dir.path().join("lab/data.bin"), "lab data").unwrap();
+        std::fs::write(served_dir.path().join("public.bin"), "public data").unwrap();
+        std::os::unix::fs::symlink("lab", served_dir.path().join("public")).unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.access_rules = vec![
+            "allow lab/** 10.1.0.0/16".parse().unwrap(),
+            "deny lab/**".parse().unwrap(),
+        ];
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "public.bin", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.file_size(), 11);
+        drop(acc_packet);
+
+        // denied independent of the existence of the file
+        for file_name in ["lab/data.bin", "lab//data.bin", "lab/missing.bin"] {
+            let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, file_name, 0);
+            client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+            match receive(&client_socket).unwrap().0 {
+                PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::AccessDenied),
+                _ => panic!("unexpected packet"),
+            }
+        }
+        // symbolic links do not lead around the rules
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "public/data.bin", 0);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+        assert_eq!(server.count_connections(), 1);
+
+        drop(server);
+    }
+
+    #[test]
+    fn reload(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 1200;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::create_dir(served_dir.path().join("lab")).unwrap();
+        std::fs::write(served_dir.path().join("lab/data.bin"), "lab data").unwrap();
+        std::fs::write(served_dir.path().join("public.bin"), "public data").unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.access_rules = vec!["deny lab/**".parse().unwrap()];
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "public.bin", 0);
+        retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+
+        let mut reloaded = ServerConfig::new(served_dir.path().to_path_buf());
+        reloaded.timeouts.min_connection_timeout = Duration::from_secs(30);
+        reloaded.max_connections = 10;
+        server.reload(reloaded);
+        assert!(server.config().access_rules.is_empty());
+        assert_eq!(server.config().timeouts.min_connection_timeout, Duration::from_secs(30));
+        // requires a restart
+        assert_eq!(server.config().max_connections, super::MAX_SIMULTANEOUS_CONNECTIONS);
+
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "lab/data.bin", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.file_size(), 8);
+        // the running transfer is kept
+        assert_eq!(server.count_connections(), 2);
+
+        // the first access rule also denies symbolic links, which could lead into the denied directory
+        std::os::unix::fs::symlink("lab/data.bin", served_dir.path().join("link.bin")).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "link.bin", 0);
+        retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let mut reloaded = ServerConfig::new(served_dir.path().to_path_buf());
+        reloaded.access_rules = vec!["deny lab/**".parse().unwrap()];
+        server.reload(reloaded);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test]
+    fn stats(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 100;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        let file_content = "test".repeat(100);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), &file_content).unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.metrics_addr = Some("127.0.0.1:0".parse().unwrap());
+        config.metrics_per_connection = true;
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+        let stats = server.stats();
+        assert_eq!(stats.active_connections(), 1);
+        assert_eq!(stats.connections[0].connection_id, connection_id);
+        assert_eq!(stats.connections[0].file_name, "hello.txt");
+        assert_eq!(stats.connections[0].file_size, file_content.len() as u64);
+        assert_eq!(stats.connections[0].bytes_sent, 0);
+        assert!(stats.checksum_cache_misses >= 1);
+
+        // transfer the file packet by packet
+        let mut received_bytes = 0;
+        let mut next_sequence_number = 0;
+        while received_bytes < file_content.len() {
+            client_socket.send_to(AckPacket::new_buf(1, connection_id, next_sequence_number).buf(), server.local_addr).unwrap();
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            received_bytes += data_packet.data().len();
+            next_sequence_number += 1;
+        }
+        client_socket.send_to(AckPacket::new_buf(1, connection_id, next_sequence_number).buf(), server.local_addr).unwrap();
+        server.wait_for_connections(0);
+
+        let stats = server.stats();
+        assert_eq!(stats.active_connections(), 0);
+        assert_eq!(stats.total_connections, 1);
+        assert!(stats.bytes_sent > file_content.len() as u64);
+        assert_eq!(stats.retransmissions, 0);
+
+        // a second request finds the checksum in the cache
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        drop(acc_packet);
+        assert!(server.stats().checksum_cache_hits >= 1);
+
+        // a client that does not send its request does not delay the others
+        let _silent_stream = std::net::TcpStream::connect(server.metrics_addr().unwrap()).unwrap();
+        let mut metrics = String::new();
+        let mut stream = std::net::TcpStream::connect(server.metrics_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+        std::io::Read::read_to_string(&mut stream, &mut metrics).unwrap();
+        assert!(metrics.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(metrics.contains("\nsoft_connections_total 2\n"));
+        assert!(metrics.contains("\nsoft_active_connections 1\n"));
+        assert!(metrics.contains("soft_connection_sent_bytes_total{connection_id="));
+
+        drop(server);
+    }
+
+    /// records the events of the server in the order they occur
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl ServerObserver for RecordingObserver {
+        fn on_request(&self, _peer: SocketAddr, file_name: &str, offset: u64) {
+            self.events.lock().unwrap().push(format!("request {} {}", file_name, offset));
+        }
+
+        fn on_rejected(&self, _peer: SocketAddr, file_name: &str, _offset: u64, error_code: SoftErrorCode) {
+            self.events.lock().unwrap().push(format!("rejected {} {}", file_name, error_code));
+        }
+
+        fn on_accepted(&self, transfer: &TransferInfo) {
+            self.events.lock().unwrap().push(format!("accepted {} {}", transfer.file_name, transfer.file_size));
+        }
+
+        fn on_migrated(&self, transfer: &TransferInfo, previous_peer: SocketAddr) {
+            assert_ne!(transfer.peer, previous_peer);
+            self.events.lock().unwrap().push(format!("migrated {}", transfer.bytes_acknowledged));
+        }
+
+        fn on_finished(&self, transfer: &TransferInfo) {
+            self.events.lock().unwrap().push(format!("finished {} {}", transfer.bytes_acknowledged, transfer.bytes_sent > 0));
+        }
+    }
+
+    #[test]
+    fn observer(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 100;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), "hello world").unwrap();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.observer = Some(observer.clone());
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        client_socket.send_to(ReqPacket::new_buf(MAX_PACKET_SIZE, "missing.txt", 0).buf(), server.local_addr).unwrap();
+        receive(&client_socket).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // the transfer continues from another address
+        let migrated_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        migrated_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        migrated_socket.send_to(AckPacket::new_buf(10, connection_id, 0).buf(), server.local_addr).unwrap();
+        let data_packet: DataPacketBuf = receive(&migrated_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(data_packet.data(), b"hello world");
+        migrated_socket.send_to(AckPacket::new_buf(10, connection_id, 1).buf(), server.local_addr).unwrap();
+        sleep(Duration::from_millis(200));
+
+        let mut events = observer.events.lock().unwrap().clone();
+        // requests are repeated until the checksum is ready
+        events.dedup();
+        assert_eq!(events, vec![
+            "request missing.txt 0",
+            "rejected missing.txt FileNotFound",
+            "request hello.txt 0",
+            "accepted hello.txt 11",
+            "migrated 0",
+            "finished 11 true",
+        ]);
+
+        drop(server);
+    }
+
+    #[test]
+    fn graceful_shutdown(){
+        const MAX_PACKET_SIZE: MaxPacketSize = 1200;
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(2000);
+        const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), "hello world").unwrap();
+        let server = Server::start("127.0.0.1:0", served_dir.path().to_path_buf(), 0.0, 0.0);
+        let server_addr = server.local_addr();
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server_addr);
+        let connection_id = acc_packet.connection_id();
+        drop(acc_packet);
+
+        // the transfer is not acknowledged, so it is still active after the timeout
+        let start = Instant::now();
+        let shutdown = std::thread::spawn(move || server.shutdown(SHUTDOWN_TIMEOUT));
+        sleep(Duration::from_millis(100));
+        client_socket.send_to(req_packet.buf(), server_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::ServerUnavailable),
+            _ => panic!("unexpected packet"),
+        }
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => {
+                assert_eq!(e.error_code(), SoftErrorCode::Stop);
+                assert_eq!(e.connection_id(), connection_id);
+            }
+            _ => panic!("unexpected packet"),
+        }
+        assert!(start.elapsed() >= SHUTDOWN_TIMEOUT);
+        shutdown.join().unwrap();
+    }
+
+    #[test]
+    fn snapshots(){
+        const FILE_NAME: &str = "hello.txt";
+        const MAX_PACKET_SIZE: MaxPacketSize = 17; // one byte per packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let served_file = served_dir.path().join(FILE_NAME);
+        std::fs::write(&served_file, "hello world").unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.enable_snapshots();
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let receive_data = |connection_id: ConnectionId, sequence_number: u64| {
+            client_socket.send_to(AckPacket::new_buf(1, connection_id, sequence_number).buf(), server.local_addr()).unwrap();
+            let data_packet: DataPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+            assert_eq!(data_packet.sequence_number(), sequence_number);
+            data_packet.data().to_vec()
+        };
+
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, FILE_NAME, 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        let connection_id = acc_packet.connection_id();
+        let checksum = acc_packet.checksum();
+        assert_eq!(checksum, sha256_from_bytes(b"hello world"));
+        drop(acc_packet);
+
+        // modified in place, the transfer continues with the snapshot
+        assert_eq!(receive_data(connection_id, 0), b"h");
+        std::fs::write(&served_file, "HELLO WORLD").unwrap();
+        sleep(Duration::from_millis(150));
+        assert_eq!(receive_data(connection_id, 1), b"e");
+
+        // new requests get the new version
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.checksum(), sha256_from_bytes(b"HELLO WORLD"));
+        drop(acc_packet);
+
+        // the old version is resumed by its checksum
+        let req_packet = ReqPacket::new_buf_with_resume_checksum(MAX_PACKET_SIZE, FILE_NAME, 2, 0, Some(checksum));
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        let acc_packet: AccPacketBuf = receive(&client_socket).unwrap().0.try_into().unwrap();
+        assert_eq!(acc_packet.checksum(), checksum);
+        assert_eq!(receive_data(acc_packet.connection_id(), 0), b"l");
+
+        // the snapshots are not served
+        let req_packet = ReqPacket::new_buf(MAX_PACKET_SIZE, &format!("{}/0", SNAPSHOT_DIR_NAME), 0);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test]
+    fn checksum_store(){
+        const FILE_NAME: &str = "hello.txt";
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let mut file = File::create(served_dir.path().join(FILE_NAME)).unwrap();
+        file.write(b"hello world").unwrap();
+        let served_dir = served_dir.into_path();
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(100, FILE_NAME, 0);
+
+        // generate the checksum
+        let server = Server::start("127.0.0.1:0", served_dir.clone(), 0.0, 0.0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.checksum(), sha256_from_bytes(b"hello world"));
+        drop(server);
+
+        // the restarted server knows the checksum
+        let server = Server::start("127.0.0.1:0", served_dir.clone(), 0.0, 0.0);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Acc(acc) => assert_eq!(acc.checksum(), sha256_from_bytes(b"hello world")),
+            _ => panic!("unexpected packet"),
+        }
+
+        // the store itself is not served
+        let req_packet = ReqPacket::new_buf(100, CHECKSUM_STORE_FILE_NAME, 0);
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+        drop(server);
+    }
+
+    #[test]
+    fn checksum_retry_after(){
+        const FILE_NAME: &str = "hello.txt";
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join(FILE_NAME), "hello").unwrap();
+        let server = Server::start("127.0.0.1:0", served_dir.into_path(), 0.0, 0.0);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let req_packet = ReqPacket::new_buf(100, FILE_NAME, 0);
+
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        let retry_after = match receive(&client_socket).unwrap().0 {
+            PacketBuf::Err(e) if e.error_code() == SoftErrorCode::ChecksumNotReady => e.retry_after(),
+            _ => panic!("unexpected packet"),
+        };
+        // the estimate for a small file is short, but never unknown
+        assert!(retry_after > 0 && retry_after < 100);
+
+        sleep(Duration::from_millis(retry_after as u64) + Duration::from_millis(50));
+        client_socket.send_to(req_packet.buf(), server.local_addr).unwrap();
+        match receive(&client_socket).unwrap().0 {
+            PacketBuf::Acc(acc) => assert_eq!(acc.checksum(), sha256_from_bytes(b"hello")),
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test]
+    fn precompute_checksums(){
+        const FILE_NAME: &str = "hello.txt";
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+        const PRECOMPUTE_TIMEOUT: Duration = Duration::from_millis(200);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join(FILE_NAME), "hello").unwrap();
+        std::fs::create_dir(served_dir.path().join("dir")).unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        config.precompute_checksums = true;
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let request = |file_name: &str| {
+            client_socket.send_to(ReqPacket::new_buf(100, file_name, 0).buf(), server.local_addr).unwrap();
+            match receive(&client_socket).unwrap().0 {
+                PacketBuf::Acc(acc) => acc.checksum(),
+                _ => panic!("unexpected packet"),
+            }
+        };
+
+        // scanned at start
+        sleep(PRECOMPUTE_TIMEOUT);
+        assert_eq!(request(FILE_NAME), sha256_from_bytes(b"hello"));
+
+        // modified
+        std::fs::write(served_dir.path().join(FILE_NAME), "hello world").unwrap();
+        sleep(PRECOMPUTE_TIMEOUT);
+        assert_eq!(request(FILE_NAME), sha256_from_bytes(b"hello world"));
+
+        // created in a subdirectory
+        std::fs::write(served_dir.path().join("dir").join(FILE_NAME), "world").unwrap();
+        sleep(PRECOMPUTE_TIMEOUT);
+        assert_eq!(request("dir/hello.txt"), sha256_from_bytes(b"world"));
+
+        drop(server);
+    }
+
+    #[test]
+    fn mounts(){
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+        let served_dir = TempDir::new("soft_test").unwrap();
+        let lab_dir = TempDir::new("soft_test").unwrap();
+        std::fs::write(served_dir.path().join("hello.txt"), "root").unwrap();
+        std::fs::write(lab_dir.path().join("hello.txt"), "lab").unwrap();
+        let mut config = ServerConfig::new(served_dir.path().to_path_buf());
+        let mut lab = Mount::new("lab", lab_dir.path().to_path_buf());
+        lab.read_only = true;
+        lab.precompute_checksums = true;
+        config.mounts.push(lab);
+        // files of read-only mounts are served directly instead of snapshots
+        config.enable_snapshots();
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
+        let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client_socket.set_read_timeout(Some(RECEIVE_TIMEOUT)).unwrap();
+        let request = |file_name: &str| {
+            client_socket.send_to(ReqPacket::new_buf(100, file_name, 0).buf(), server.local_addr).unwrap();
+            receive(&client_socket).unwrap().0
+        };
+
+        // precomputed at start
+        let req_packet = ReqPacket::new_buf(100, "lab/hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.checksum(), sha256_from_bytes(b"lab"));
+        drop(acc_packet);
+        let req_packet = ReqPacket::new_buf(100, "hello.txt", 0);
+        let acc_packet = retry_req_until_checksum_ready(&client_socket, &req_packet, server.local_addr);
+        assert_eq!(acc_packet.checksum(), sha256_from_bytes(b"root"));
+        drop(acc_packet);
+        match request("lab/world.txt") {
+            PacketBuf::Err(e) => assert_eq!(e.error_code(), SoftErrorCode::FileNotFound),
+            _ => panic!("unexpected packet"),
+        }
+
+        drop(server);
+    }
+
+    #[test_case("hello.txt", false; "file")]
+    #[test_case("bundle.tar/inner/hello.txt", true; "archive member")]
+    fn resumption(file_name: &str, archive: bool){
+        const FILE_CONTENT: &str = "hello world";
+        const MAX_PACKET_SIZE: MaxPacketSize = 22; // content fit in two packet
+        const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+        //let _ = env_logger::builder().filter_level(log::LevelFilter::Debug).try_init();
+
+        // start server
+        let served_dir = TempDir::new("soft_test").unwrap();
+        match archive {
+            true => std::fs::write(served_dir.path().join("bundle.tar"), tar(&[("inner/hello.txt", FILE_CONTENT.as_bytes())])).unwrap(),
+            false => std::fs::write(served_dir.path().join(file_name), FILE_CONTENT).unwrap(),
+        }
+        let mut config = ServerConfig::new(served_dir.into_path());
+        config.serve_archive_members = archive;
+        let server = Server::start_with_config("127.0.0.1:0", config);
+
         let mut received_file_content = Vec::<u8>::with_capacity(FILE_CONTENT.len());
         let mut connection_count = 0;
 
@@ -437,7 +1830,7 @@ mod tests {
             // create Req
             let req_packet = ReqPacket::new_buf(
                 MAX_PACKET_SIZE,
-                FILE_NAME,
+                file_name,
                 received_file_content.len() as Offset,
             );
 
@@ -448,7 +1841,7 @@ mod tests {
 
             // send Ack 0
             client_socket.send_to(
-                &AckPacket::new_buf(
+                AckPacket::new_buf(
                     10,
                     connection_id,
                     0,
@@ -462,7 +1855,7 @@ mod tests {
 
             // send Ack 1
             client_socket.send_to(
-                &AckPacket::new_buf(
+                AckPacket::new_buf(
                     10,
                     connection_id,
                     1
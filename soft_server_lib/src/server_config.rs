@@ -0,0 +1,286 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::{PathBuf, Path};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::time::Duration;
+use soft_shared_lib::times::{self, INITIAL_RTT, MAX_PERSIST_TIMEOUT, MIN_CONNECTION_TIMEOUT, MIN_PATH_CACHE_TIMEOUT};
+use crate::file_source::{FileSource, SymlinkPolicy};
+use crate::access_control::{check_access, Access, AccessRule};
+use crate::mount_table::{Mount, MountTable};
+use crate::observer::ServerObserver;
+use crate::access_log::AccessLogFormat;
+use crate::checksum_store::CHECKSUM_STORE_FILE_NAME;
+use crate::snapshot_store::{DEFAULT_SNAPSHOT_RETENTION, SNAPSHOT_DIR_NAME};
+use crate::server::{FILE_READER_BUFFER_SIZE, MAX_SIMULTANEOUS_CONNECTIONS};
+use crate::checksum_cache::DEFAULT_ENTRY_TTL;
+
+pub const DEFAULT_MAX_CONCURRENT_CHECKSUMS: usize = 2;
+
+/// timeouts of connections and paths, the defaults are the ones of soft_shared_lib::times
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timeouts {
+    /// the rtt that new connections are timed out with, until the rtt of the path is measured
+    pub initial_rtt: Duration,
+    pub min_connection_timeout: Duration,
+    /// the congestion state of a client address is forgotten after 20 rtt, but not before this timeout
+    pub min_path_cache_timeout: Duration,
+    /// the longest time between window probes if the client keeps its receive window closed
+    pub max_persist_timeout: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            initial_rtt: INITIAL_RTT,
+            min_connection_timeout: MIN_CONNECTION_TIMEOUT,
+            min_path_cache_timeout: MIN_PATH_CACHE_TIMEOUT,
+            max_persist_timeout: MAX_PERSIST_TIMEOUT,
+        }
+    }
+}
+
+impl Timeouts {
+
+    pub fn connection_timeout(&self, rtt: Duration) -> Duration {
+        times::connection_timeout_with_min(rtt, self.min_connection_timeout)
+    }
+
+    pub fn path_cache_timeout(&self, rtt: Duration) -> Duration {
+        times::path_cache_timeout_with_min(rtt, self.min_path_cache_timeout)
+    }
+
+    /// doubled for every window probe that has not reopened the receive window
+    pub fn persist_timeout(&self, rto: Duration, window_probes: u32) -> Duration {
+        times::persist_timeout_with_max(rto, window_probes, self.max_persist_timeout)
+    }
+}
+
+/// Configuration of a Server
+///
+/// can be read from a TOML file, see ServerConfig::load
+#[derive(Clone)]
+pub struct ServerConfig {
+    /// the directory to serve files from
+    pub served_dir: PathBuf,
+    /// directories served under a prefix of the file name, with their own options
+    ///
+    /// the served directory is mounted at the root, with the options of this config
+    pub mounts: Vec<Mount>,
+    /// serve the files of this source instead of the files in the served directory,
+    /// e.g. in-memory blobs or generated content
+    ///
+    /// checksums are still persisted and precomputed in the served directory
+    pub file_source: Option<Arc<dyn FileSource>>,
+    /// loss simulation; the probability that the next package sent will be lost if the last packet was lost
+    pub first_loss_probability: f64,
+    /// loss simulation; the probability that the next package sent will be lost if the last packet was also lost
+    pub repeated_loss_probability: f64,
+    /// files in these directories, relative to the served directory, are always transferred in scavenger mode
+    pub scavenger_paths: Vec<PathBuf>,
+    /// the file the checksums are persisted in, by default in the served directory
+    ///
+    /// the store and its compaction file are never served to clients or precomputed, see ChecksumStore::files
+    ///
+    /// None to keep checksums only in memory
+    pub checksum_store_path: Option<PathBuf>,
+    /// compute the checksums of all files in the served directory at start and whenever they are modified,
+    /// instead of at the first request
+    ///
+    /// without a checksum store, the precomputed checksums are kept in memory until the server stops
+    pub precompute_checksums: bool,
+    /// the number of files that are hashed at the same time
+    pub max_concurrent_checksums: usize,
+    /// serve snapshots from this directory, so files can be modified in place during transfers
+    ///
+    /// None to serve files directly
+    pub snapshot_dir: Option<PathBuf>,
+    /// snapshots are removed if they have not been requested for this time
+    pub snapshot_retention: Duration,
+    /// serve the members of tar and zip archives, e.g. `bundle.tar/inner/file.bin`
+    pub serve_archive_members: bool,
+    /// how symbolic links in the served directory are resolved
+    ///
+    /// symbolic links are never served while access rules are configured, see effective_symlink_policy
+    pub symlink_policy: SymlinkPolicy,
+    /// serve files and directories whose name starts with a dot, as before this option existed
+    pub serve_dot_files: bool,
+    /// the first rule that matches a request decides if it is allowed, requests that match no rule are allowed
+    pub access_rules: Vec<AccessRule>,
+    /// serve the statistics of the server in the Prometheus text format on this TCP address, see Server::stats
+    ///
+    /// None to disable the endpoint
+    pub metrics_addr: Option<SocketAddr>,
+    /// also serve metrics per active connection, labeled with the connection id and the client address
+    ///
+    /// disabled by default, because every connection adds new series to the monitoring system
+    pub metrics_per_connection: bool,
+    /// notified about requests and the events of transfers, e.g. to log completed downloads
+    pub observer: Option<Arc<dyn ServerObserver>>,
+    /// append one line per closed connection to this file, see Server::reopen_access_log
+    ///
+    /// None to disable the access log
+    pub access_log_path: Option<PathBuf>,
+    pub access_log_format: AccessLogFormat,
+    /// the number of connections and client addresses that are tracked at the same time
+    pub max_connections: usize,
+    /// the buffer for sequential reads of each transferred local file
+    pub file_reader_buffer_size: usize,
+    /// checksums that have not been requested for this time are dropped from memory,
+    /// they are still found in the checksum store
+    pub checksum_cache_ttl: Duration,
+    pub timeouts: Timeouts,
+    /// the number of receive loops, each with its own socket and connections,
+    /// several shards share the port with SO_REUSEPORT, so the server can use several cores
+    pub shards: usize,
+}
+
+impl ServerConfig {
+
+    pub fn new(served_dir: PathBuf) -> Self {
+        Self {
+            checksum_store_path: Some(served_dir.join(CHECKSUM_STORE_FILE_NAME)),
+            served_dir,
+            mounts: Vec::new(),
+            file_source: None,
+            first_loss_probability: 0.0,
+            repeated_loss_probability: 0.0,
+            scavenger_paths: Vec::new(),
+            precompute_checksums: false,
+            max_concurrent_checksums: DEFAULT_MAX_CONCURRENT_CHECKSUMS,
+            snapshot_dir: None,
+            snapshot_retention: DEFAULT_SNAPSHOT_RETENTION,
+            serve_archive_members: false,
+            symlink_policy: SymlinkPolicy::default(),
+            serve_dot_files: true,
+            access_rules: Vec::new(),
+            metrics_addr: None,
+            metrics_per_connection: false,
+            observer: None,
+            access_log_path: None,
+            access_log_format: AccessLogFormat::default(),
+            max_connections: MAX_SIMULTANEOUS_CONNECTIONS,
+            file_reader_buffer_size: FILE_READER_BUFFER_SIZE,
+            checksum_cache_ttl: DEFAULT_ENTRY_TTL,
+            timeouts: Timeouts::default(),
+            shards: 1,
+        }
+    }
+
+    /// serve snapshots from the default directory in the served directory
+    pub fn enable_snapshots(&mut self) {
+        self.snapshot_dir = Some(self.served_dir.join(SNAPSHOT_DIR_NAME));
+    }
+
+    /// the served directory and the mounts
+    pub fn mount_table(&self) -> MountTable {
+        let mut root = Mount::new("", self.served_dir.clone());
+        root.precompute_checksums = self.precompute_checksums;
+        MountTable::new(std::iter::once(root).chain(self.mounts.iter().cloned()).collect())
+    }
+
+    /// true if the client may request the file
+    pub fn is_allowed(&self, file_name: &str, addr: IpAddr) -> bool {
+        check_access(&self.access_rules, file_name, addr) == Access::Allow
+    }
+
+    /// the rules match the requested file name, so a symbolic link could lead into a restricted directory
+    pub fn effective_symlink_policy(&self) -> SymlinkPolicy {
+        match self.access_rules.is_empty() {
+            true => self.symlink_policy,
+            false => SymlinkPolicy::Deny,
+        }
+    }
+
+    /// true if the requested file should yield to other traffic, independent of the REQ flags
+    pub fn is_scavenger_path(&self, file_name: &str) -> bool {
+        let path = Path::new(file_name);
+        self.scavenger_paths.iter().any(|p| path.starts_with(p))
+    }
+
+    /// the settings that differ from the other config and are only applied when the server is started,
+    /// see Server::reload
+    ///
+    /// the file source and the observer can not be reloaded, they are not compared
+    pub fn restart_required_changes(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        let mut compare = |name, changed| if changed { changes.push(name) };
+        compare("served_dir", self.served_dir != other.served_dir);
+        compare("mounts", self.mounts != other.mounts);
+        compare("loss probability", self.first_loss_probability != other.first_loss_probability || self.repeated_loss_probability != other.repeated_loss_probability);
+        compare("checksum_store", self.checksum_store_path != other.checksum_store_path);
+        compare("precompute_checksums", self.precompute_checksums != other.precompute_checksums);
+        compare("max_concurrent_checksums", self.max_concurrent_checksums != other.max_concurrent_checksums);
+        compare("snapshot_dir", self.snapshot_dir != other.snapshot_dir);
+        compare("snapshot_retention", self.snapshot_retention != other.snapshot_retention);
+        compare("archives", self.serve_archive_members != other.serve_archive_members);
+        compare("dot_files", self.serve_dot_files != other.serve_dot_files);
+        compare("metrics_addr", self.metrics_addr != other.metrics_addr || self.metrics_per_connection != other.metrics_per_connection);
+        compare("access_log", self.access_log_path != other.access_log_path || self.access_log_format != other.access_log_format);
+        compare("max_connections", self.max_connections != other.max_connections);
+        compare("file_reader_buffer_size", self.file_reader_buffer_size != other.file_reader_buffer_size);
+        compare("shards", self.shards != other.shards);
+        changes
+    }
+
+    /// this config with the settings of the other config that can be changed while the server is running:
+    /// the access rules, the symbolic link policy, the scavenger paths, the checksum cache ttl and the timeouts
+    pub fn with_live_settings(&self, other: &ServerConfig) -> ServerConfig {
+        let mut config = self.clone();
+        config.access_rules = other.access_rules.clone();
+        config.symlink_policy = other.symlink_policy;
+        config.scavenger_paths = other.scavenger_paths.clone();
+        config.checksum_cache_ttl = other.checksum_cache_ttl;
+        config.timeouts = other.timeouts;
+        config
+    }
+}
+
+/// the config of a running server, replaced by Server::reload
+pub(crate) struct SharedConfig {
+    config: RwLock<Arc<ServerConfig>>,
+    /// incremented by every replacement, so a CachedConfig only takes the lock after a reload
+    version: AtomicU64,
+}
+
+impl SharedConfig {
+
+    pub fn new(config: ServerConfig) -> Self {
+        Self { config: RwLock::new(Arc::new(config)), version: AtomicU64::new(0) }
+    }
+
+    /// a snapshot, a later replacement is only seen by the next call
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// for readers of the config per packet
+    pub fn cached(self: &Arc<Self>) -> CachedConfig {
+        let version = self.version.load(Acquire);
+        CachedConfig { shared: self.clone(), version, config: self.current() }
+    }
+
+    pub fn replace(&self, config: ServerConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+        self.version.fetch_add(1, Release);
+    }
+}
+
+/// a snapshot of the shared config that is only renewed when the config has been replaced
+pub(crate) struct CachedConfig {
+    shared: Arc<SharedConfig>,
+    version: u64,
+    config: Arc<ServerConfig>,
+}
+
+impl CachedConfig {
+
+    pub fn get(&mut self) -> &ServerConfig {
+        let version = self.shared.version.load(Acquire);
+        if version != self.version {
+            self.config = self.shared.current();
+            self.version = version;
+        }
+        &self.config
+    }
+}
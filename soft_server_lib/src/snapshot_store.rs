@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use soft_shared_lib::error;
+use soft_shared_lib::field_types::Checksum;
+use crate::file_sandbox::ServedFile;
+use crate::file_source::{FileIdentity, FileSource, LocalFileSource, SourceFile};
+
+/// default directory of the snapshots, in the served directory
+pub const SNAPSHOT_DIR_NAME: &str = ".soft_snapshots";
+/// marks a directory as created by the store, so its snapshots can be removed when the server starts
+const MARKER_FILE_NAME: &str = ".soft_snapshot_store";
+/// the file names of all snapshots start with this prefix, other files in the directory are never removed
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+/// snapshots are removed if they have not been requested for this time
+pub const DEFAULT_SNAPSHOT_RETENTION: Duration = Duration::from_secs(60 * 60);
+/// bytes per second, to estimate the copying time
+const ASSUMED_COPY_RATE: f64 = 200_000_000.0;
+const COPY_BUFFER_SIZE: usize = 2usize.pow(16);
+/// if the file has been modified while the snapshot was taken
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// shares the extents of the file on copy-on-write file systems, e.g. btrfs or xfs
+const FICLONE: libc::c_ulong = 0x4004_9409;
+
+pub enum SnapshotState {
+    Ready(ServedFile),
+    /// the file is copied, the duration is the estimated time until the snapshot is ready
+    ///
+    /// also used for files that their source prepares in the background, see file_source::NotReady
+    Pending(Duration),
+}
+
+struct Snapshot {
+    /// the served file at the time of the snapshot
+    version: FileIdentity,
+    /// the file name in the snapshot directory
+    name: String,
+    /// None until the checksum is generated
+    checksum: Option<Checksum>,
+    last_used: Instant,
+    /// Some with the copied bytes while the file is copied
+    copied_bytes: Option<Arc<AtomicU64>>,
+}
+
+impl Snapshot {
+
+    fn estimated_remaining(&self, copied_bytes: &AtomicU64) -> Duration {
+        let remaining_bytes = self.version.size().saturating_sub(copied_bytes.load(Relaxed));
+        Duration::from_secs_f64(remaining_bytes as f64 / ASSUMED_COPY_RATE)
+    }
+}
+
+/// # Snapshot Store
+///
+/// serves immutable snapshots of files that are modified in place,
+/// so the checksum in the ACC packet always matches the transferred bytes
+///
+/// a snapshot of a local file is a reflink if the file system supports it, otherwise a copy
+///
+/// all requests of the same version share a snapshot, which is retained until it has not been requested for the retention time,
+/// so clients can resume the transfer of that version by its checksum
+pub struct SnapshotStore {
+    dir: PathBuf,
+    /// the snapshot directory
+    source: Arc<LocalFileSource>,
+    retention: Duration,
+    /// snapshots by file name, relative to the served directory
+    snapshots: Mutex<HashMap<String, Vec<Snapshot>>>,
+    next_id: AtomicU64,
+}
+
+impl SnapshotStore {
+
+    /// snapshots of previous runs are removed, because their versions are unknown
+    ///
+    /// the directory is created if it does not exist,
+    /// an existing directory must be empty or must have been created by the store,
+    /// so a misconfigured directory is never cleaned up
+    pub fn open(dir: &Path, retention: Duration) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let marker = dir.join(MARKER_FILE_NAME);
+        if marker.exists() {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with(SNAPSHOT_PREFIX) && entry.file_type()?.is_file() {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        } else if fs::read_dir(dir)?.next().is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is not empty and is no snapshot directory", dir)));
+        } else {
+            fs::File::create(&marker)?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            source: Arc::new(LocalFileSource::new(dir.to_path_buf())),
+            retention,
+            snapshots: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    /// the snapshot of the current version of the file
+    ///
+    /// files that can not be reflinked are copied in an own task
+    pub async fn snapshot(self: Arc<Self>, file_name: &str, file: Arc<dyn SourceFile>) -> error::Result<SnapshotState> {
+        self.remove_expired().await;
+        let version = file.identity().await?;
+        if let Some(state) = self.get(file_name, |snapshot| snapshot.version == version).await {
+            return state;
+        }
+
+        let name = format!("{}{}", SNAPSHOT_PREFIX, self.next_id.fetch_add(1, Relaxed));
+        let path = self.dir.join(&name);
+        // the file stays open until the blocking task has finished, so its descriptor stays valid
+        let fd = file.as_raw_fd();
+        let (snapshot_file, reflinked) = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || -> io::Result<(fs::File, bool)> {
+                let snapshot_file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+                let reflinked = fd.is_some_and(|fd| reflink(fd, &snapshot_file).is_ok());
+                Ok((snapshot_file, reflinked))
+            }).await.map_err(io::Error::from)??
+        };
+        if reflinked {
+            if file.identity().await? != version {
+                debug!("{} changed while taking a snapshot", file_name);
+                tokio::fs::remove_file(&path).await?;
+                return Ok(SnapshotState::Pending(SNAPSHOT_RETRY_DELAY));
+            }
+            tokio::fs::set_permissions(&path, Permissions::from_mode(0o444)).await?;
+            debug!("reflinked snapshot of {}", file_name);
+            self.insert(file_name, Snapshot { version, name, checksum: None, last_used: Instant::now(), copied_bytes: None });
+            // the snapshot might have expired in the meantime, the client retries
+            return self.get(file_name, |snapshot| snapshot.version == version).await
+                .unwrap_or(Ok(SnapshotState::Pending(SNAPSHOT_RETRY_DELAY)));
+        }
+
+        debug!("copying snapshot of {}", file_name);
+        let copied_bytes = Arc::new(AtomicU64::new(0));
+        let snapshot = Snapshot { version, name, checksum: None, last_used: Instant::now(), copied_bytes: Some(copied_bytes.clone()) };
+        let estimated_remaining = snapshot.estimated_remaining(&copied_bytes);
+        self.insert(file_name, snapshot);
+        let file_name = file_name.to_string();
+        tokio::spawn(async move {
+            let result = Self::copy(file.as_ref(), File::from_std(snapshot_file), version, &copied_bytes).await;
+            self.on_copied(&file_name, version, result).await;
+        });
+        Ok(SnapshotState::Pending(estimated_remaining))
+    }
+
+    /// the snapshot of a previous version, if it is retained
+    pub async fn find(&self, file_name: &str, checksum: Checksum) -> Option<error::Result<SnapshotState>> {
+        self.get(file_name, |snapshot| snapshot.checksum == Some(checksum)).await
+    }
+
+    /// the checksum is generated after the snapshot has been taken
+    pub fn set_checksum(&self, file_name: &str, version: FileIdentity, checksum: Checksum) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let snapshot = snapshots.get_mut(file_name)
+            .and_then(|snapshots| snapshots.iter_mut().find(|snapshot| snapshot.version == version));
+        if let Some(snapshot) = snapshot {
+            snapshot.checksum = Some(checksum);
+        }
+    }
+
+    async fn get<P: Fn(&Snapshot) -> bool>(&self, file_name: &str, predicate: P) -> Option<error::Result<SnapshotState>> {
+        let (name, version, checksum) = {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            let snapshot = snapshots.get_mut(file_name)?.iter_mut().find(|snapshot| predicate(snapshot))?;
+            snapshot.last_used = Instant::now();
+            if let Some(copied_bytes) = &snapshot.copied_bytes {
+                return Some(Ok(SnapshotState::Pending(snapshot.estimated_remaining(copied_bytes))));
+            }
+            (snapshot.name.clone(), snapshot.version, snapshot.checksum)
+        };
+        let served_file = self.source.open(&name).await.map(|file| ServedFile {
+            file: Arc::from(file),
+            source: self.source.clone(),
+            name,
+            version,
+            checksum,
+        });
+        Some(served_file.map(SnapshotState::Ready).map_err(error::ErrorType::from))
+    }
+
+    fn insert(&self, file_name: &str, snapshot: Snapshot) {
+        self.snapshots.lock().unwrap().entry(file_name.to_string()).or_default().push(snapshot);
+    }
+
+    /// false if the file has been modified in the meantime
+    async fn copy(file: &dyn SourceFile, mut snapshot_file: File, version: FileIdentity, copied_bytes: &AtomicU64) -> io::Result<bool> {
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let mut offset = 0;
+        loop {
+            let size = file.read_at(&mut buf, offset).await?;
+            if size == 0 {
+                break;
+            }
+            snapshot_file.write_all(&buf[..size]).await?;
+            offset += size as u64;
+            copied_bytes.store(offset, Relaxed);
+        }
+        snapshot_file.flush().await?;
+        Ok(file.identity().await? == version)
+    }
+
+    /// the snapshot is discarded if copying failed or the file has been modified in the meantime
+    async fn on_copied(&self, file_name: &str, version: FileIdentity, result: io::Result<bool>) {
+        let (path, ready) = {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            let file_snapshots = match snapshots.get_mut(file_name) {
+                Some(file_snapshots) => file_snapshots,
+                None => {
+                    warn!("copied snapshot of {} is unknown", file_name);
+                    return;
+                }
+            };
+            let index = match file_snapshots.iter().position(|snapshot| snapshot.version == version) {
+                Some(index) => index,
+                None => {
+                    warn!("copied snapshot of {} is unknown", file_name);
+                    return;
+                }
+            };
+            let path = self.dir.join(&file_snapshots[index].name);
+            match result {
+                Ok(true) => {
+                    file_snapshots[index].copied_bytes = None;
+                    debug!("snapshot of {} is ready", file_name);
+                    (path, true)
+                }
+                Ok(false) => {
+                    debug!("{} changed while taking a snapshot", file_name);
+                    file_snapshots.remove(index);
+                    (path, false)
+                }
+                Err(e) => {
+                    warn!("failed to take snapshot of {}, caused by: {}", file_name, e);
+                    file_snapshots.remove(index);
+                    (path, false)
+                }
+            }
+        };
+        if ready {
+            if let Err(e) = tokio::fs::set_permissions(&path, Permissions::from_mode(0o444)).await {
+                warn!("failed to make snapshot {:?} read only, caused by: {}", path, e);
+            }
+        } else {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    /// snapshots that are copied are kept, because the copy task expects them
+    async fn remove_expired(&self) {
+        let mut expired = Vec::new();
+        {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            for file_snapshots in snapshots.values_mut() {
+                file_snapshots.retain(|snapshot| {
+                    if snapshot.copied_bytes.is_some() || snapshot.last_used.elapsed() < self.retention {
+                        return true;
+                    }
+                    expired.push(self.dir.join(&snapshot.name));
+                    false
+                });
+            }
+            snapshots.retain(|_, file_snapshots| !file_snapshots.is_empty());
+        }
+        for path in expired {
+            debug!("remove expired snapshot {:?}", path);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                warn!("failed to remove snapshot {:?}, caused by: {}", path, e);
+            }
+        }
+    }
+}
+
+fn reflink(src: RawFd, dst: &fs::File) -> io::Result<()> {
+    let result = unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use tempdir::TempDir;
+    use crate::snapshot_store::{SnapshotStore, DEFAULT_SNAPSHOT_RETENTION};
+
+    #[test]
+    fn open() {
+        let dir = TempDir::new("soft_test").unwrap();
+        let snapshot_dir = dir.path().join("snapshots");
+        SnapshotStore::open(&snapshot_dir, DEFAULT_SNAPSHOT_RETENTION).unwrap();
+        fs::write(snapshot_dir.join("snapshot-0"), b"old").unwrap();
+        fs::write(snapshot_dir.join("notes.txt"), b"keep").unwrap();
+        // only the snapshots of the previous run are removed
+        SnapshotStore::open(&snapshot_dir, DEFAULT_SNAPSHOT_RETENTION).unwrap();
+        assert!(!snapshot_dir.join("snapshot-0").exists());
+        assert!(snapshot_dir.join("notes.txt").exists());
+
+        // directories that have not been created by the store are not touched
+        let foreign_dir = dir.path().join("data");
+        fs::create_dir(&foreign_dir).unwrap();
+        fs::write(foreign_dir.join("snapshot-0"), b"data").unwrap();
+        assert_eq!(SnapshotStore::open(&foreign_dir, DEFAULT_SNAPSHOT_RETENTION).err().map(|e| e.kind()), Some(ErrorKind::AlreadyExists));
+        assert!(foreign_dir.join("snapshot-0").exists());
+    }
+}
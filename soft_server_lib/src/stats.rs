@@ -0,0 +1,174 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+use soft_shared_lib::field_types::ConnectionId;
+
+/// name, help, type and value of a metric per connection
+type ConnectionMetric = (&'static str, &'static str, &'static str, fn(&ConnectionStats) -> f64);
+
+/// counters of DATA packets and ACKs, per connection and for the whole server
+#[derive(Default)]
+pub(crate) struct TransferCounters {
+    /// bytes of all sent DATA packets, including retransmissions
+    bytes_sent: AtomicU64,
+    retransmissions: AtomicU64,
+    duplicate_acks: AtomicU64,
+}
+
+impl TransferCounters {
+
+    pub fn on_packet_sent(&self, size: usize, retransmission: bool) {
+        self.bytes_sent.fetch_add(size as u64, Relaxed);
+        if retransmission {
+            self.retransmissions.fetch_add(1, Relaxed);
+        }
+    }
+
+    pub fn on_duplicate_ack(&self) {
+        self.duplicate_acks.fetch_add(1, Relaxed);
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Relaxed)
+    }
+
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions.load(Relaxed)
+    }
+
+    pub fn duplicate_acks(&self) -> u64 {
+        self.duplicate_acks.load(Relaxed)
+    }
+}
+
+/// snapshot of an active connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStats {
+    pub connection_id: ConnectionId,
+    pub client_addr: SocketAddr,
+    pub file_name: String,
+    pub file_size: u64,
+    /// bytes of all sent DATA packets, including retransmissions
+    pub bytes_sent: u64,
+    pub retransmissions: u64,
+    pub duplicate_acks: u64,
+    /// in packets
+    pub congestion_window: u16,
+    pub rtt: Duration,
+    pub scavenger: bool,
+}
+
+/// snapshot of the server, see Server::stats
+///
+/// the totals include closed connections since the server has been started
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ServerStats {
+    pub total_connections: u64,
+    pub bytes_sent: u64,
+    pub retransmissions: u64,
+    pub duplicate_acks: u64,
+    /// requests for checksums that are known, in memory or in the checksum store
+    pub checksum_cache_hits: u64,
+    /// requests that have to wait until the checksum is generated
+    pub checksum_cache_misses: u64,
+    pub connections: Vec<ConnectionStats>,
+}
+
+impl ServerStats {
+
+    pub fn active_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// the Prometheus text exposition format
+    ///
+    /// the metrics of the connections are only included if `per_connection` is true, see ServerConfig::metrics_per_connection
+    pub fn to_prometheus(&self, per_connection: bool) -> String {
+        let mut text = String::new();
+        let totals = [
+            ("soft_connections_total", "Accepted connections", self.total_connections),
+            ("soft_sent_bytes_total", "Bytes of sent DATA packets, including retransmissions", self.bytes_sent),
+            ("soft_retransmissions_total", "Retransmitted DATA packets", self.retransmissions),
+            ("soft_duplicate_acks_total", "Received duplicate ACK packets", self.duplicate_acks),
+            ("soft_checksum_cache_hits_total", "Requests for known checksums", self.checksum_cache_hits),
+            ("soft_checksum_cache_misses_total", "Requests that wait for the generation of a checksum", self.checksum_cache_misses),
+        ];
+        for (name, help, value) in totals {
+            write_metric(&mut text, name, help, "counter", [("", value as f64)]);
+        }
+        write_metric(&mut text, "soft_active_connections", "Active connections", "gauge", [("", self.active_connections() as f64)]);
+        let scavenger_connections = self.connections.iter().filter(|connection| connection.scavenger).count();
+        write_metric(&mut text, "soft_active_scavenger_connections", "Active connections that yield to other traffic", "gauge", [("", scavenger_connections as f64)]);
+        if !per_connection {
+            return text;
+        }
+
+        let labels: Vec<String> = self.connections.iter()
+            .map(|connection| format!("{{connection_id=\"{}\",client=\"{}\"}}", connection.connection_id, connection.client_addr))
+            .collect();
+        let per_connection: [ConnectionMetric; 5] = [
+            ("soft_connection_sent_bytes_total", "Bytes of sent DATA packets of the connection", "counter", |c| c.bytes_sent as f64),
+            ("soft_connection_retransmissions_total", "Retransmitted DATA packets of the connection", "counter", |c| c.retransmissions as f64),
+            ("soft_connection_duplicate_acks_total", "Received duplicate ACK packets of the connection", "counter", |c| c.duplicate_acks as f64),
+            ("soft_connection_congestion_window", "Congestion window of the connection in packets", "gauge", |c| c.congestion_window as f64),
+            ("soft_connection_rtt_seconds", "Smoothed round trip time of the connection", "gauge", |c| c.rtt.as_secs_f64()),
+        ];
+        for (name, help, metric_type, value) in per_connection {
+            let samples = labels.iter().map(String::as_str).zip(self.connections.iter().map(value));
+            write_metric(&mut text, name, help, metric_type, samples);
+        }
+        text
+    }
+}
+
+fn write_metric<'a>(text: &mut String, name: &str, help: &str, metric_type: &str, samples: impl IntoIterator<Item = (&'a str, f64)>) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, metric_type);
+    for (labels, value) in samples {
+        let _ = writeln!(text, "{}{} {}", name, labels, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::stats::{ConnectionStats, ServerStats};
+
+    #[test]
+    fn prometheus() {
+        let stats = ServerStats {
+            total_connections: 3,
+            bytes_sent: 4096,
+            retransmissions: 2,
+            duplicate_acks: 5,
+            checksum_cache_hits: 2,
+            checksum_cache_misses: 1,
+            connections: vec![ConnectionStats {
+                connection_id: 42,
+                client_addr: "10.1.2.3:5000".parse().unwrap(),
+                file_name: String::from("lab/data.bin"),
+                file_size: 8192,
+                bytes_sent: 1024,
+                retransmissions: 1,
+                duplicate_acks: 3,
+                congestion_window: 10,
+                rtt: Duration::from_millis(250),
+                scavenger: false,
+            }],
+        };
+        let text = stats.to_prometheus(false);
+        assert!(text.contains("# TYPE soft_sent_bytes_total counter\nsoft_sent_bytes_total 4096\n"));
+        assert!(text.contains("# TYPE soft_active_connections gauge\nsoft_active_connections 1\n"));
+        assert!(text.contains("soft_active_scavenger_connections 0\n"));
+        assert!(text.contains("soft_checksum_cache_misses_total 1\n"));
+        assert!(!text.contains("connection_id="));
+
+        let text = stats.to_prometheus(true);
+        assert!(text.contains("soft_connection_congestion_window{connection_id=\"42\",client=\"10.1.2.3:5000\"} 10\n"));
+        assert!(text.contains("soft_connection_rtt_seconds{connection_id=\"42\",client=\"10.1.2.3:5000\"} 0.25\n"));
+        // every line is a comment or a sample
+        assert!(text.lines().all(|line| line.starts_with('#') || line.split(' ').count() == 2));
+    }
+}
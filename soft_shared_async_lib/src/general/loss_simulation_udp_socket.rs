@@ -3,6 +3,10 @@ use std::sync::atomic::Ordering::SeqCst;
 use tokio::net::{UdpSocket, ToSocketAddrs};
 use std::net::SocketAddr;
 use std::io::Result;
+use soft_shared_lib::helper::ecn_helper::{self, Ecn};
+use soft_shared_lib::helper::socket_helper::{RecvBatch, SendBatch};
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
 
 /// Wraps a normal UdpSocket
 ///
@@ -26,14 +30,26 @@ impl LossSimulationUdpSocket {
     /// * `p` - the probability that the next package sent will be lost if the last packet was lost
     /// * `q` - the probability that the next package sent will be lost if the last packet was also lost
     pub async fn bind<A: ToSocketAddrs>(addr: A, p: f64, q: f64) -> Result<Self> {
+        Ok(Self::new(UdpSocket::bind(addr).await?, p, q))
+    }
+
+    /// wraps a bound socket, e.g. one of several sockets that share a port
+    ///
+    /// must be called within a tokio runtime
+    pub fn from_std(socket: std::net::UdpSocket, p: f64, q: f64) -> Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self::new(UdpSocket::from_std(socket)?, p, q))
+    }
+
+    fn new(inner: UdpSocket, p: f64, q: f64) -> Self {
         assert!(0.0 <= p && p <= 1.0);
         assert!(0.0 <= q && q <= 1.0);
-        Ok(Self {
-            inner: UdpSocket::bind(addr).await?,
+        Self {
+            inner,
             p,
             q,
             last_packet_lost: AtomicBool::new(false),
-        })
+        }
     }
 
     /// unmodified receive function
@@ -41,6 +57,70 @@ impl LossSimulationUdpSocket {
         self.inner.recv_from(buf).await
     }
 
+    /// receive the available packets, at least one, with one packet per buffer
+    ///
+    /// a packet replaces the content of its buffer and is limited by the capacity of the buffer,
+    /// the sizes and source addresses of the received packets replace the content of received
+    #[cfg(target_os = "linux")]
+    pub async fn recv_batch(&self, batch: &mut RecvBatch, buffers: &mut [Vec<u8>], received: &mut Vec<(usize, SocketAddr)>) -> Result<()> {
+        loop {
+            self.inner.readable().await?;
+            match self.inner.try_io(Interest::READABLE, || batch.recv(&self.inner, buffers, received)) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// batches are only supported on Linux, receives a single packet
+    #[cfg(not(target_os = "linux"))]
+    pub async fn recv_batch(&self, _batch: &mut RecvBatch, buffers: &mut [Vec<u8>], received: &mut Vec<(usize, SocketAddr)>) -> Result<()> {
+        let buffer = &mut buffers[0];
+        buffer.resize(buffer.capacity(), 0);
+        let packet = self.recv_from(buffer).await?;
+        buffer.truncate(packet.0);
+        received.clear();
+        received.push(packet);
+        Ok(())
+    }
+
+    /// modified send function for several packets to the same address, the batch keeps the message headers between the calls.
+    /// Each packet is lost with the specified probability.
+    #[cfg(target_os = "linux")]
+    pub async fn send_batch(&self, batch: &mut SendBatch, packets: &[&[u8]], addr: SocketAddr) -> Result<()> {
+        let mut start = 0;
+        while start < packets.len() {
+            // the packets up to the next lost packet are sent together
+            let lost = (start..packets.len()).find(|_| self.random_loss()).unwrap_or(packets.len());
+            self.send_all(batch, &packets[start..lost], addr).await?;
+            start = lost + 1;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn send_all(&self, batch: &mut SendBatch, packets: &[&[u8]], addr: SocketAddr) -> Result<()> {
+        let mut sent = 0;
+        while sent < packets.len() {
+            self.inner.writable().await?;
+            match self.inner.try_io(Interest::WRITABLE, || batch.send(&self.inner, &packets[sent..], addr)) {
+                Ok(count) => sent += count,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// batches are only supported on Linux, sends one packet after the other
+    #[cfg(not(target_os = "linux"))]
+    pub async fn send_batch(&self, _batch: &mut SendBatch, packets: &[&[u8]], addr: SocketAddr) -> Result<()> {
+        for packet in packets {
+            self.send_to(packet, addr).await?;
+        }
+        Ok(())
+    }
+
     /// modified send function.
     /// Sent packet are lost with the specified probability.
     pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
@@ -62,6 +142,11 @@ impl LossSimulationUdpSocket {
         panic!()
     }
 
+    /// mark all sent packets with the ECN codepoint
+    pub fn set_ecn_marking(&self, ecn: Ecn) -> Result<()> {
+        ecn_helper::set_ecn_marking(&self.inner, ecn)
+    }
+
     pub fn local_addr(&self) -> Result<SocketAddr> {
         self.inner.local_addr()
     }
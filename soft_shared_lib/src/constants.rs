@@ -1,6 +1,15 @@
+use crate::field_types::RequestFlags;
+
 pub const SOFT_PROTOCOL_VERSION: u8 = 1;
 
 pub const SOFT_PACKET_HEADER_SIZE: u8 = 2;
 
 /// the maximum packet size the SOFT protocol supports
 pub const SOFT_MAX_PACKET_SIZE: usize = 2usize.pow(16) - 8 - 20;
+
+/// REQ flag, the client asks for a low priority transfer that yields to other traffic
+pub const REQ_FLAG_SCAVENGER: RequestFlags = 0b0000_0001;
+
+/// REQ flag, the REQ extension contains the checksum of the partially downloaded file,
+/// so the server can resume the transfer of that version if it is still retained
+pub const REQ_FLAG_RESUME_CHECKSUM: RequestFlags = 0b0000_0010;
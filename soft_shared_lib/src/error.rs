@@ -18,6 +18,7 @@ pub enum ErrorType {
     Eof,
     InvalidRequest,
     ChecksumNotReady,
+    FileChanged,
 }
 
 impl Display for ErrorType {
@@ -57,6 +58,10 @@ impl Display for ErrorType {
             ErrorType::ChecksumNotReady => write!(
                 fmt,
                 "checksum not ready"
+            ),
+            ErrorType::FileChanged => write!(
+                fmt,
+                "file changed"
             )
         }
     }
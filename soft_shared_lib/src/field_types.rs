@@ -7,6 +7,13 @@ pub type Checksum = [u8; 32];
 pub type Offset = u64;
 pub type SequenceNumber = u64;
 pub type NextSequenceNumber = u64;
+pub type RequestFlags = u8;
+/// 0 means no token
+pub type AckToken = u16;
+/// number of received DATA packets with the ECN codepoint CE
+pub type CeCount = u32;
+/// milliseconds until the request should be repeated, 0 means unknown
+pub type RetryAfter = u32;
 
 /// this is the raw field type
 /// it might be better to use the enum PacketType
@@ -3,6 +3,7 @@ use std::sync::atomic::{AtomicBool, AtomicU32};
 use std::sync::atomic::Ordering::SeqCst;
 use std::time::Duration;
 use core::mem;
+use crate::helper::ecn_helper::{self, Ecn};
 
 /// Wraps a normal UdpSocket
 ///
@@ -48,6 +49,17 @@ impl LossSimulationUdpSocket {
         self.inner.recv(buf)
     }
 
+    /// unmodified receive function, that also returns the ECN codepoint of the packet
+    ///
+    /// requires enable_ecn_reception
+    pub fn recv_with_ecn(&self, buf: &mut [u8]) -> std::io::Result<(usize, Ecn)> {
+        ecn_helper::recv_with_ecn(&self.inner, buf)
+    }
+
+    pub fn enable_ecn_reception(&self) -> std::io::Result<()> {
+        ecn_helper::enable_ecn_reception(&self.inner)
+    }
+
     /// unmodified receive function
     pub fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
         self.inner.recv_from(buf)
@@ -1,3 +1,5 @@
+pub mod ecn_helper;
 pub mod range_helper;
 pub mod sha256_helper;
+pub mod socket_helper;
 pub mod transfer_helper;
\ No newline at end of file
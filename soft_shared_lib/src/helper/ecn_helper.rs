@@ -0,0 +1,163 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use libc::{c_int, c_void};
+
+/// ECN codepoint of the IP header (RFC 3168)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ecn {
+    NotEct,
+    Ect1,
+    Ect0,
+    /// congestion experienced
+    Ce,
+}
+
+impl Ecn {
+
+    /// from the IPv4 TOS field, the ECN codepoint are the two lowest bits
+    pub fn from_tos(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            0b11 => Ecn::Ce,
+            _ => Ecn::NotEct,
+        }
+    }
+
+    pub fn to_tos(self) -> u8 {
+        match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+/// mark all packets sent by the socket with the ECN codepoint
+///
+/// IPv6 sockets set the traffic class, IPv4 sockets the TOS field
+pub fn set_ecn_marking<S: AsRawFd>(socket: &S, ecn: Ecn) -> io::Result<()> {
+    match socket_family(socket)? {
+        libc::AF_INET6 => set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, ecn.to_tos() as c_int),
+        _ => set_option(socket, libc::IPPROTO_IP, libc::IP_TOS, ecn.to_tos() as c_int),
+    }
+}
+
+/// required for recv_with_ecn
+#[cfg(target_os = "linux")]
+pub fn enable_ecn_reception<S: AsRawFd>(socket: &S) -> io::Result<()> {
+    match socket_family(socket)? {
+        libc::AF_INET6 => {
+            set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+            // dual stack sockets receive IPv4 packets as well
+            let _ = set_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1);
+            Ok(())
+        }
+        _ => set_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS, 1),
+    }
+}
+
+/// ECN reception is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn enable_ecn_reception<S: AsRawFd>(_socket: &S) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "ECN reception is not supported"))
+}
+
+fn set_option<S: AsRawFd>(socket: &S, level: c_int, option: c_int, value: c_int) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// AF_INET or AF_INET6
+fn socket_family<S: AsRawFd>(socket: &S) -> io::Result<c_int> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe { libc::getsockname(socket.as_raw_fd(), &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr, &mut len) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(addr.ss_family as c_int)
+}
+
+/// receive a packet from the connected socket and the ECN codepoint of its IP header
+///
+/// the codepoint is NotEct if ECN reception is not enabled
+#[cfg(target_os = "linux")]
+pub fn recv_with_ecn<S: AsRawFd>(socket: &S, buf: &mut [u8]) -> io::Result<(usize, Ecn)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    // u64 for the alignment of cmsghdr
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut ecn = Ecn::NotEct;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_TOS {
+                ecn = Ecn::from_tos(*libc::CMSG_DATA(cmsg));
+            } else if (*cmsg).cmsg_level == libc::IPPROTO_IPV6 && (*cmsg).cmsg_type == libc::IPV6_TCLASS {
+                // the traffic class is an int, unlike the TOS byte
+                let traffic_class = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int);
+                ecn = Ecn::from_tos(traffic_class as u8);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((size as usize, ecn))
+}
+
+/// ECN reception is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn recv_with_ecn<S: AsRawFd>(socket: &S, buf: &mut [u8]) -> io::Result<(usize, Ecn)> {
+    let size = unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((size as usize, Ecn::NotEct))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::helper::ecn_helper::{Ecn, set_ecn_marking, enable_ecn_reception, recv_with_ecn};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn loopback() {
+        for addr in ["127.0.0.1:0", "[::1]:0"] {
+            let receiver = UdpSocket::bind(addr).unwrap();
+            enable_ecn_reception(&receiver).unwrap();
+            let sender = UdpSocket::bind(addr).unwrap();
+            sender.connect(receiver.local_addr().unwrap()).unwrap();
+            let mut buf = [0u8; 16];
+            for ecn in [Ecn::NotEct, Ecn::Ect0, Ecn::Ce] {
+                set_ecn_marking(&sender, ecn).unwrap();
+                sender.send(b"hello").unwrap();
+                assert_eq!(recv_with_ecn(&receiver, &mut buf).unwrap(), (5, ecn), "{}", addr);
+            }
+            assert_eq!(&buf[..5], b"hello");
+        }
+    }
+}
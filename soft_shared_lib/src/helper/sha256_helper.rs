@@ -14,6 +14,18 @@ pub fn sha256_to_hex_string(sha: [u8; 32]) -> String{
     return str;
 }
 
+/// None if the string is not a hex encoded sha256
+pub fn sha256_from_hex_string(str: &str) -> Option<[u8; 32]> {
+    if str.len() != 64 || !str.is_ascii() {
+        return None;
+    }
+    let mut sha = [0u8; 32];
+    for (i, byte) in sha.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&str[2 * i..2 * i + 2], 16).ok()?;
+    }
+    return Some(sha);
+}
+
 // generate sha256 from bytes
 pub fn sha256_from_bytes(bytes: &[u8]) -> [u8; 32]{
     let mut sha256 = Sha256::new();
@@ -40,6 +52,7 @@ mod tests {
     use sha2::{Sha256, Digest};
     use std::convert::TryInto;
     use hex_literal::hex;
+    use crate::helper::sha256_helper::{sha256_to_hex_string, sha256_from_hex_string};
 
     #[test]
     fn sha256() {
@@ -48,4 +61,12 @@ mod tests {
         let result: [u8; 32] = hasher.finalize().as_slice().try_into().expect("wrong length");
         assert_eq!(result, hex!("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hex_string() {
+        let sha = hex!("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        assert_eq!(sha256_from_hex_string(&sha256_to_hex_string(sha)), Some(sha));
+        assert_eq!(sha256_from_hex_string("b94d"), None);
+        assert_eq!(sha256_from_hex_string(&"x".repeat(64)), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::io::FromRawFd;
+#[cfg(target_os = "linux")]
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use libc::{c_int, c_void};
+
+/// bind a UDP socket with SO_REUSEPORT, so several sockets can receive on the same address
+///
+/// the kernel distributes the received packets by a hash of their source address,
+/// all sockets of the address must set the option,
+/// any process of the same user can bind the address with the option as well and receive a share of the packets
+pub fn bind_reuse_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // closes the file descriptor if the socket can not be bound
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let enable: c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &enable as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (sockaddr, len) = to_sockaddr(addr);
+    let result = unsafe { libc::bind(fd, &sockaddr as *const _ as *const libc::sockaddr, len) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// the message headers of recvmmsg, kept to receive every batch without allocating them
+///
+/// the headers only point to the buffers during a call
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct RecvBatch {
+    addrs: Vec<libc::sockaddr_storage>,
+    iovecs: Vec<libc::iovec>,
+    messages: Vec<libc::mmsghdr>,
+}
+
+/// batches are only supported on Linux
+#[cfg(not(target_os = "linux"))]
+#[derive(Default)]
+pub struct RecvBatch;
+
+// the raw pointers of the headers are reset before each use and only used by methods that take &mut self
+#[cfg(target_os = "linux")]
+unsafe impl Send for RecvBatch {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for RecvBatch {}
+
+#[cfg(target_os = "linux")]
+impl RecvBatch {
+
+    /// receive up to one packet per buffer with a single system call, waits only for the first packet
+    ///
+    /// a packet is received into the capacity of a buffer and replaces its content,
+    /// the sizes and source addresses of the received packets replace the content of received,
+    /// WouldBlock if no packet is available
+    pub fn recv<S: AsRawFd>(&mut self, socket: &S, buffers: &mut [Vec<u8>], received: &mut Vec<(usize, SocketAddr)>) -> io::Result<()> {
+        self.addrs.resize(buffers.len(), unsafe { mem::zeroed() });
+        self.iovecs.clear();
+        self.iovecs.extend(buffers.iter_mut()
+            .map(|buffer| libc::iovec { iov_base: buffer.as_mut_ptr() as *mut c_void, iov_len: buffer.capacity() }));
+        self.messages.clear();
+        self.messages.extend(self.iovecs.iter_mut().zip(self.addrs.iter_mut())
+            .map(|(iovec, addr)| {
+                let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+                message.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut c_void;
+                message.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                message.msg_hdr.msg_iov = iovec;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            }));
+        let count = unsafe { libc::recvmmsg(socket.as_raw_fd(), self.messages.as_mut_ptr(), self.messages.len() as _, libc::MSG_WAITFORONE, std::ptr::null_mut()) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        received.clear();
+        for ((message, addr), buffer) in self.messages.iter().zip(self.addrs.iter()).zip(buffers.iter_mut()).take(count as usize) {
+            let size = message.msg_len as usize;
+            // the kernel has written the packet to the first size bytes
+            unsafe { buffer.set_len(size) };
+            received.push((size, from_sockaddr(addr)?));
+        }
+        Ok(())
+    }
+}
+
+/// the message headers of sendmmsg, kept to send every batch without allocating them
+///
+/// the headers only point to the packets during a call
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct SendBatch {
+    iovecs: Vec<libc::iovec>,
+    messages: Vec<libc::mmsghdr>,
+}
+
+/// batches are only supported on Linux
+#[cfg(not(target_os = "linux"))]
+#[derive(Default)]
+pub struct SendBatch;
+
+// the raw pointers of the headers are reset before each use and only used by methods that take &mut self
+#[cfg(target_os = "linux")]
+unsafe impl Send for SendBatch {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for SendBatch {}
+
+#[cfg(target_os = "linux")]
+impl SendBatch {
+
+    /// send the packets to the address with a single system call
+    ///
+    /// the number of sent packets, the first packets are sent if the send buffer of the socket is full,
+    /// WouldBlock if no packet has been sent
+    pub fn send<S: AsRawFd>(&mut self, socket: &S, packets: &[&[u8]], addr: SocketAddr) -> io::Result<usize> {
+        let (mut sockaddr, len) = to_sockaddr(addr);
+        self.iovecs.clear();
+        self.iovecs.extend(packets.iter()
+            .map(|packet| libc::iovec { iov_base: packet.as_ptr() as *mut c_void, iov_len: packet.len() }));
+        self.messages.clear();
+        self.messages.extend(self.iovecs.iter_mut()
+            .map(|iovec| {
+                let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+                message.msg_hdr.msg_name = &mut sockaddr as *mut libc::sockaddr_storage as *mut c_void;
+                message.msg_hdr.msg_namelen = len;
+                message.msg_hdr.msg_iov = iovec;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            }));
+        let count = unsafe { libc::sendmmsg(socket.as_raw_fd(), self.messages.as_mut_ptr(), self.messages.len() as _, 0) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+}
+
+fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_addr.s6_addr = addr.ip().octets();
+            sockaddr.sin6_flowinfo = addr.flowinfo();
+            sockaddr.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(target_os = "linux")]
+fn from_sockaddr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match storage.ss_family as c_int {
+        libc::AF_INET => {
+            let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr));
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sockaddr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sockaddr.sin6_addr.s6_addr);
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sockaddr.sin6_port), sockaddr.sin6_flowinfo, sockaddr.sin6_scope_id)))
+        }
+        family => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected address family {}", family))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::helper::socket_helper::bind_reuse_port;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn shared_port() {
+        let first = bind_reuse_port("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_reuse_port(addr).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+        // sockets without the option can not join
+        assert!(UdpSocket::bind(addr).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn batches() {
+        use crate::helper::socket_helper::{RecvBatch, SendBatch};
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut send_batch = SendBatch::default();
+        let mut recv_batch = RecvBatch::default();
+        let packets: [&[u8]; 3] = [b"first", b"second", b"third"];
+        let mut buffers: Vec<Vec<u8>> = (0..4).map(|_| Vec::with_capacity(16)).collect();
+        let mut received = Vec::new();
+        // the headers are reused for the second batch
+        for _ in 0..2 {
+            assert_eq!(send_batch.send(&sender, &packets, receiver.local_addr().unwrap()).unwrap(), 3);
+            recv_batch.recv(&receiver, &mut buffers, &mut received).unwrap();
+            assert_eq!(received.len(), 3);
+            for (i, (size, addr)) in received.iter().enumerate() {
+                assert_eq!(buffers[i], packets[i]);
+                assert_eq!(*size, packets[i].len());
+                assert_eq!(*addr, sender.local_addr().unwrap());
+            }
+        }
+    }
+}
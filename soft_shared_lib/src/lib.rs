@@ -4,6 +4,7 @@ pub mod error;
 pub mod soft_error_code;
 pub mod field_types;
 pub mod times;
+pub mod rtt_estimator;
 pub mod helper;
 pub mod general;
 
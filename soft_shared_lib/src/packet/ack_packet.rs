@@ -1,4 +1,4 @@
-use crate::field_types::{Version, PacketTypeRaw, ReceiveWindow, ConnectionId, NextSequenceNumber};
+use crate::field_types::{Version, PacketTypeRaw, ReceiveWindow, ConnectionId, NextSequenceNumber, AckToken, CeCount};
 use crate::packet::packet_type::PacketType;
 use std::mem::size_of;
 use crate::constants::SOFT_PROTOCOL_VERSION;
@@ -25,14 +25,40 @@ impl AckPacket {
             size_of::<NextSequenceNumber>()
     }
 
+    fn get_required_extension_size() -> usize {
+        size_of::<AckToken>() +
+            size_of::<CeCount>()
+    }
+
     pub fn new_buf(receive_window: ReceiveWindow, connection_id: ConnectionId, next_sequence_number: NextSequenceNumber) -> AckPacketBuf {
-        let mut buf = vec![0u8; Self::get_required_buffer_size()];
+        Self::new_buf_with_token(receive_window, connection_id, next_sequence_number, 0)
+    }
+
+    /// the server echoes the token in the DATA packets, so the client can measure the RTT
+    pub fn new_buf_with_token(receive_window: ReceiveWindow, connection_id: ConnectionId, next_sequence_number: NextSequenceNumber, token: AckToken) -> AckPacketBuf {
+        Self::new_buf_with_extension(receive_window, connection_id, next_sequence_number, token, 0)
+    }
+
+    /// the ACK extension is only appended if the token or the CE count is not 0
+    ///
+    /// the CE count is the number of DATA packets the client has received with the ECN codepoint CE
+    pub fn new_buf_with_extension(receive_window: ReceiveWindow, connection_id: ConnectionId, next_sequence_number: NextSequenceNumber, token: AckToken, ce_count: CeCount) -> AckPacketBuf {
+        let extension = token != 0 || ce_count != 0;
+        let mut size = Self::get_required_buffer_size();
+        if extension {
+            size += Self::get_required_extension_size();
+        }
+        let mut buf = vec![0u8; size];
         let unchecked = UncheckedPacket::from_buf_mut(buf.as_mut_slice());
         unchecked.set_version(SOFT_PROTOCOL_VERSION);
         unchecked.set_packet_type(PacketType::Ack);
         unchecked.set_receive_window(receive_window);
         unchecked.set_connection_id(connection_id);
         unchecked.set_next_sequence_number(next_sequence_number);
+        if extension {
+            unchecked.set_ack_token(token);
+            unchecked.set_ce_count(ce_count);
+        }
         buf.try_into().unwrap()
     }
 
@@ -59,6 +85,16 @@ impl AckPacket {
     pub fn set_next_sequence_number(&mut self, val: NextSequenceNumber) {
         self.inner.set_next_sequence_number(val);
     }
+
+    /// 0 if the packet has no ACK extension
+    pub fn token(&self) -> AckToken {
+        self.inner.ack_token()
+    }
+
+    /// 0 if the packet has no ACK extension
+    pub fn ce_count(&self) -> CeCount {
+        self.inner.ce_count()
+    }
 }
 
 impl GeneralPacket for AckPacket {
@@ -100,11 +136,35 @@ impl Display for AckPacket {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Ack {{ version: {},  connection_id: {}, receive_window: {}, next_sequence_number: {} }}",
+            "Ack {{ version: {},  connection_id: {}, receive_window: {}, next_sequence_number: {}, token: {}, ce_count: {} }}",
             self.version(),
             self.connection_id(),
             self.receive_window(),
-            self.next_sequence_number()
+            self.next_sequence_number(),
+            self.token(),
+            self.ce_count()
         )
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::packet::ack_packet::AckPacket;
+    use crate::general::byte_view::ByteView;
+
+    #[test]
+    fn without_extension(){
+        let ack = AckPacket::new_buf(10, 1, 2);
+        assert_eq!(ack.buf().len(), AckPacket::get_required_buffer_size());
+        assert_eq!(ack.token(), 0);
+        assert_eq!(ack.ce_count(), 0);
+    }
+
+    #[test]
+    fn with_extension(){
+        let ack = AckPacket::new_buf_with_extension(10, 1, 2, 0, 5);
+        assert_eq!(ack.buf().len(), AckPacket::get_required_buffer_size() + AckPacket::get_required_extension_size());
+        assert_eq!(ack.next_sequence_number(), 2);
+        assert_eq!(ack.token(), 0);
+        assert_eq!(ack.ce_count(), 5);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::packet::packet_type::PacketType;
-use crate::field_types::{ConnectionId, Version, PacketTypeRaw, Padding16, SequenceNumber};
+use crate::field_types::{ConnectionId, Version, PacketTypeRaw, AckToken, SequenceNumber};
 use std::mem::size_of;
 use crate::constants::{SOFT_PROTOCOL_VERSION, SOFT_MAX_PACKET_SIZE};
 use std::fmt::{Display, Formatter};
@@ -21,7 +21,7 @@ impl DataPacket {
     pub fn get_required_buffer_size_without_data() -> usize {
         return size_of::<Version>() +
             size_of::<PacketTypeRaw>() +
-            size_of::<Padding16>() +
+            size_of::<AckToken>() +
             size_of::<ConnectionId>() +
             size_of::<SequenceNumber>();
     }
@@ -35,13 +35,22 @@ impl DataPacket {
 
     pub fn new_buf(connection_id: ConnectionId, sequence_number: SequenceNumber, data: &[u8]) -> DataPacketBuf {
         let mut buf = vec![0u8; Self::get_required_buffer_size(data.len())];
-        let unchecked = UncheckedPacket::from_buf_mut(buf.as_mut_slice());
+        Self::write_header(buf.as_mut_slice(), connection_id, sequence_number);
+        UncheckedPacket::from_buf_mut(buf.as_mut_slice()).set_data(data);
+        buf.try_into().unwrap()
+    }
+
+    /// write the fields in front of the data into buf, e.g. before the data is read into the rest of buf
+    ///
+    /// the echo token is 0
+    pub fn write_header(buf: &mut [u8], connection_id: ConnectionId, sequence_number: SequenceNumber) {
+        assert!(buf.len() >= Self::get_required_buffer_size_without_data() && buf.len() <= SOFT_MAX_PACKET_SIZE);
+        let unchecked = UncheckedPacket::from_buf_mut(buf);
         unchecked.set_version(SOFT_PROTOCOL_VERSION);
         unchecked.set_packet_type(PacketType::Data);
+        unchecked.set_echo_token(0);
         unchecked.set_connection_id(connection_id);
         unchecked.set_sequence_number(sequence_number);
-        unchecked.set_data(data);
-        buf.try_into().unwrap()
     }
 
     pub fn connection_id(&self) -> ConnectionId {
@@ -56,6 +65,15 @@ impl DataPacket {
         self.inner.data()
     }
 
+    /// token of the latest ACK the server received, 0 if none
+    pub fn echo_token(&self) -> AckToken {
+        self.inner.echo_token()
+    }
+
+    pub fn set_echo_token(&mut self, val: AckToken) {
+        self.inner.set_echo_token(val);
+    }
+
     pub fn packet_size(&self) -> u16 {
         self.buf().len() as u16
     }
@@ -101,10 +119,11 @@ impl Display for DataPacket {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Data {{ version: {},  connection_id: {}, sequence_number: {}, data: ({} bytes) }}",
+            "Data {{ version: {},  connection_id: {}, sequence_number: {}, echo_token: {}, data: ({} bytes) }}",
             self.version(),
             self.connection_id(),
             self.sequence_number(),
+            self.echo_token(),
             self.data().len()
         )
     }
@@ -1,7 +1,7 @@
 use crate::packet::packet_type::PacketType;
 use crate::soft_error_code::SoftErrorCode;
 use std::mem::size_of;
-use crate::field_types::{Version, PacketTypeRaw, ErrorCodeRaw, Padding8, ConnectionId};
+use crate::field_types::{Version, PacketTypeRaw, ErrorCodeRaw, Padding8, ConnectionId, RetryAfter};
 use crate::constants::SOFT_PROTOCOL_VERSION;
 use std::fmt::{Display, Formatter};
 use crate::packet::unchecked_packet::UncheckedPacket;
@@ -25,13 +25,31 @@ impl ErrPacket {
             + size_of::<ConnectionId>();
     }
 
+    fn get_required_extension_size() -> usize {
+        size_of::<RetryAfter>()
+    }
+
     pub fn new_buf(error_code: SoftErrorCode, connection_id: ConnectionId) -> ErrPacketBuf {
-        let mut buf = vec![0u8; Self::get_required_buffer_size()];
+        Self::new_buf_with_retry_after(error_code, connection_id, 0)
+    }
+
+    /// the ERR extension is only appended if retry_after is not 0
+    ///
+    /// retry_after is the time in milliseconds after which the client should repeat its request, e.g. for ChecksumNotReady
+    pub fn new_buf_with_retry_after(error_code: SoftErrorCode, connection_id: ConnectionId, retry_after: RetryAfter) -> ErrPacketBuf {
+        let mut size = Self::get_required_buffer_size();
+        if retry_after != 0 {
+            size += Self::get_required_extension_size();
+        }
+        let mut buf = vec![0u8; size];
         let unchecked = UncheckedPacket::from_buf_mut(buf.as_mut_slice());
         unchecked.set_version(SOFT_PROTOCOL_VERSION);
         unchecked.set_packet_type(PacketType::Err);
         unchecked.set_error_code(error_code);
         unchecked.set_connection_id(connection_id);
+        if retry_after != 0 {
+            unchecked.set_retry_after(retry_after);
+        }
         buf.try_into().unwrap()
     }
 
@@ -50,6 +68,11 @@ impl ErrPacket {
     pub fn set_connection_id(&mut self, val: ConnectionId) {
         self.inner.set_connection_id(val);
     }
+
+    /// milliseconds, 0 if unknown
+    pub fn retry_after(&self) -> RetryAfter {
+        self.inner.retry_after()
+    }
 }
 
 
@@ -92,10 +115,30 @@ impl Display for ErrPacket {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Err {{ version: {},  connection_id: {}, error_code: {} }}",
+            "Err {{ version: {},  connection_id: {}, error_code: {}, retry_after: {} }}",
             self.version(),
             self.connection_id(),
             self.error_code(),
+            self.retry_after(),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::err_packet::ErrPacket;
+    use crate::soft_error_code::SoftErrorCode;
+    use crate::general::byte_view::ByteView;
+
+    #[test]
+    fn retry_after(){
+        let err = ErrPacket::new_buf(SoftErrorCode::FileNotFound, 1);
+        assert_eq!(err.buf().len(), ErrPacket::get_required_buffer_size());
+        assert_eq!(err.retry_after(), 0);
+        let err = ErrPacket::new_buf_with_retry_after(SoftErrorCode::ChecksumNotReady, 1, 1500);
+        assert_eq!(err.buf().len(), ErrPacket::get_required_buffer_size() + ErrPacket::get_required_extension_size());
+        assert_eq!(err.error_code(), SoftErrorCode::ChecksumNotReady);
+        assert_eq!(err.connection_id(), 1);
+        assert_eq!(err.retry_after(), 1500);
+    }
+}
@@ -1,14 +1,15 @@
 use crate::packet::packet_type::PacketType;
-use crate::field_types::{Version, MaxPacketSize, Offset, PacketTypeRaw, ConnectionId};
+use crate::field_types::{Version, MaxPacketSize, Offset, PacketTypeRaw, ConnectionId, RequestFlags, Checksum};
 use std::mem::size_of;
 use std::fmt::{Display, Formatter};
 use crate::packet::unchecked_packet::UncheckedPacket;
-use crate::constants::{SOFT_MAX_PACKET_SIZE, SOFT_PROTOCOL_VERSION};
+use crate::constants::{SOFT_MAX_PACKET_SIZE, SOFT_PROTOCOL_VERSION, REQ_FLAG_SCAVENGER, REQ_FLAG_RESUME_CHECKSUM};
 use crate::packet::general_packet::GeneralPacket;
 use crate::general::byte_view::ByteView;
 use crate::error::Result;
 use std::convert::{TryInto};
 use crate::packet::packet_buf::ReqPacketBuf;
+use crate::helper::sha256_helper::sha256_to_hex_string;
 
 #[repr(transparent)]
 pub struct ReqPacket {
@@ -18,7 +19,24 @@ pub struct ReqPacket {
 impl ReqPacket {
 
     pub fn new_buf(max_packet_size: MaxPacketSize, file_name: &str, offset: Offset) -> ReqPacketBuf {
-        let size = ReqPacket::get_required_buffer_size(&file_name);
+        Self::new_buf_with_flags(max_packet_size, file_name, offset, 0)
+    }
+
+    /// the REQ extension is only appended if any flag is set
+    pub fn new_buf_with_flags(max_packet_size: MaxPacketSize, file_name: &str, offset: Offset, flags: RequestFlags) -> ReqPacketBuf {
+        Self::new_buf_with_resume_checksum(max_packet_size, file_name, offset, flags, None)
+    }
+
+    /// sets the RESUME_CHECKSUM flag if a checksum is given
+    pub fn new_buf_with_resume_checksum(max_packet_size: MaxPacketSize, file_name: &str, offset: Offset, mut flags: RequestFlags, resume_checksum: Option<Checksum>) -> ReqPacketBuf {
+        let mut size = ReqPacket::get_required_buffer_size(file_name);
+        if resume_checksum.is_some() {
+            flags |= REQ_FLAG_RESUME_CHECKSUM;
+            size += size_of::<Checksum>();
+        }
+        if flags != 0 {
+            size += ReqPacket::get_required_extension_size();
+        }
         assert!(size <= SOFT_MAX_PACKET_SIZE);
         let mut buf = vec![0u8; size];
         let packet = UncheckedPacket::from_buf_mut(&mut buf);
@@ -27,6 +45,12 @@ impl ReqPacket {
         packet.set_max_packet_size(max_packet_size);
         packet.set_file_name(file_name);
         packet.set_offset(offset);
+        if flags != 0 {
+            packet.set_request_flags(flags);
+        }
+        if let Some(checksum) = resume_checksum {
+            packet.set_resume_checksum(checksum);
+        }
         buf.try_into().unwrap()
     }
 
@@ -38,6 +62,12 @@ impl ReqPacket {
             file_name.as_bytes().len()
     }
 
+    /// NUL separator + flags
+    pub fn get_required_extension_size() -> usize {
+        size_of::<u8>() +
+            size_of::<RequestFlags>()
+    }
+
     pub fn max_packet_size(&self) -> MaxPacketSize {
         self.inner.max_packet_size()
     }
@@ -61,6 +91,26 @@ impl ReqPacket {
     pub fn set_file_name(&mut self, val: &str) {
         self.inner.set_file_name(val);
     }
+
+    /// 0 if the packet has no REQ extension
+    pub fn flags(&self) -> RequestFlags {
+        self.inner.request_flags()
+    }
+
+    /// true if the client requests a low priority transfer
+    pub fn scavenger(&self) -> bool {
+        self.flags() & REQ_FLAG_SCAVENGER != 0
+    }
+
+    /// the checksum of the version the client has partially downloaded
+    ///
+    /// None if the RESUME_CHECKSUM flag is not set
+    pub fn resume_checksum(&self) -> Option<Checksum> {
+        if self.flags() & REQ_FLAG_RESUME_CHECKSUM == 0 {
+            return None;
+        }
+        self.inner.resume_checksum()
+    }
 }
 
 impl GeneralPacket for ReqPacket {
@@ -102,11 +152,52 @@ impl<'a> Display for ReqPacket {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Req {{ version: {},  max_packet_size: {}, offset: {}, file_name: {} }}",
+            "Req {{ version: {},  max_packet_size: {}, offset: {}, file_name: {}, flags: {:#04x}",
             self.version(),
             self.max_packet_size(),
             self.offset(),
             self.file_name(),
-        )
+            self.flags(),
+        )?;
+        if let Some(checksum) = self.resume_checksum() {
+            write!(f, ", resume_checksum: {}", sha256_to_hex_string(checksum))?;
+        }
+        write!(f, " }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::req_packet::ReqPacket;
+    use crate::constants::{REQ_FLAG_SCAVENGER, REQ_FLAG_RESUME_CHECKSUM};
+    use crate::general::byte_view::ByteView;
+
+    #[test]
+    fn without_extension(){
+        let req = ReqPacket::new_buf(1200, "hello.txt", 3);
+        assert_eq!(req.buf().len(), ReqPacket::get_required_buffer_size("hello.txt"));
+        assert_eq!(req.file_name(), "hello.txt");
+        assert_eq!(req.flags(), 0);
+        assert!(!req.scavenger());
+    }
+
+    #[test]
+    fn with_extension(){
+        let req = ReqPacket::new_buf_with_flags(1200, "hello.txt", 3, REQ_FLAG_SCAVENGER);
+        assert_eq!(req.file_name(), "hello.txt");
+        assert_eq!(req.offset(), 3);
+        assert!(req.scavenger());
+        assert_eq!(req.resume_checksum(), None);
+    }
+
+    #[test]
+    fn with_resume_checksum(){
+        let req = ReqPacket::new_buf_with_resume_checksum(1200, "hello.txt", 3, REQ_FLAG_SCAVENGER, Some([7; 32]));
+        assert_eq!(req.buf().len(), ReqPacket::get_required_buffer_size("hello.txt") + ReqPacket::get_required_extension_size() + 32);
+        assert_eq!(req.file_name(), "hello.txt");
+        assert_eq!(req.offset(), 3);
+        assert!(req.scavenger());
+        assert_eq!(req.flags(), REQ_FLAG_SCAVENGER | REQ_FLAG_RESUME_CHECKSUM);
+        assert_eq!(req.resume_checksum(), Some([7; 32]));
     }
 }
@@ -2,8 +2,9 @@ use crate::packet::packet_type::PacketType;
 use std::io::{Cursor, Write, Read};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
 use crate::soft_error_code::SoftErrorCode;
-use crate::field_types::{MaxPacketSize, Version, ConnectionId, FileSize, Checksum, Offset, ReceiveWindow, NextSequenceNumber, ErrorCodeRaw, SequenceNumber};
+use crate::field_types::{MaxPacketSize, Version, ConnectionId, FileSize, Checksum, Offset, ReceiveWindow, NextSequenceNumber, ErrorCodeRaw, SequenceNumber, RequestFlags, AckToken, CeCount, RetryAfter};
 use std::borrow::{BorrowMut};
+use std::mem::size_of;
 use crate::general::byte_view::ByteView;
 use crate::error::Result;
 
@@ -67,15 +68,54 @@ impl UncheckedPacket {
         c.write_u64::<BigEndian>(val).expect("failed to write field");
     }
 
-    /// reads buffer until the end
+    /// reads buffer until the end or until the NUL byte that separates the REQ extension
     pub fn file_name(&self) -> String {
-        return std::str::from_utf8(&self.inner[12..]).expect("failed to read field").to_string();
+        std::str::from_utf8(&self.inner[12..self.file_name_end()]).expect("failed to read field").to_string()
     }
 
     pub fn set_file_name(&mut self, val: &str) {
         let mut c = Cursor::new( self.inner.borrow_mut());
         c.set_position(12);
-        c.write(val.as_bytes()).expect("failed to write field");
+        c.write_all(val.as_bytes()).expect("failed to write field");
+    }
+
+    /// index of the first byte after the file name
+    fn file_name_end(&self) -> usize {
+        self.inner[12..].iter().position(|b| *b == 0).map(|i| 12 + i).unwrap_or(self.inner.len())
+    }
+
+    /// for REQ packets
+    ///
+    /// 0 if the packet has no REQ extension
+    pub fn request_flags(&self) -> RequestFlags {
+        self.inner.get(self.file_name_end() + 1).copied().unwrap_or(0)
+    }
+
+    /// for REQ packets
+    ///
+    /// the file name has to be set first and the buffer must have space for the REQ extension
+    pub fn set_request_flags(&mut self, val: RequestFlags) {
+        let separator = self.file_name_end();
+        self.inner[separator] = 0;
+        self.inner[separator + 1] = val;
+    }
+
+    /// for REQ packets
+    ///
+    /// the checksum follows the flags, None if the REQ extension is too short
+    pub fn resume_checksum(&self) -> Option<Checksum> {
+        let start = self.file_name_end() + 2;
+        let mut checksum: Checksum = Default::default();
+        checksum.copy_from_slice(self.inner.get(start..start + size_of::<Checksum>())?);
+        Some(checksum)
+    }
+
+    /// for REQ packets
+    ///
+    /// the flags have to be set first and the buffer must have space for the checksum
+    pub fn set_resume_checksum(&mut self, val: Checksum) {
+        let start = self.file_name_end() + 2;
+        self.inner[start..start + val.len()].copy_from_slice(&val);
     }
 
     pub fn connection_id(&self) -> ConnectionId {
@@ -164,6 +204,83 @@ impl UncheckedPacket {
         c.write_u64::<BigEndian>(val).expect("failed to write field");
     }
 
+    /// for ACK packets
+    ///
+    /// 0 if the packet has no ACK extension
+    pub fn ack_token(&self) -> AckToken {
+        if self.inner.len() < 18 {
+            return 0;
+        }
+        let mut c = Cursor::new(&self.inner);
+        c.set_position(16);
+        c.read_u16::<BigEndian>().expect("failed to read field")
+    }
+
+    /// for ACK packets
+    ///
+    /// the buffer must have space for the ACK extension
+    pub fn set_ack_token(&mut self, val: AckToken) {
+        let mut c = Cursor::new(self.inner.borrow_mut());
+        c.set_position(16);
+        c.write_u16::<BigEndian>(val).expect("failed to write field");
+    }
+
+    /// for ACK packets
+    ///
+    /// 0 if the packet has no ECN counter in the ACK extension
+    pub fn ce_count(&self) -> CeCount {
+        if self.inner.len() < 22 {
+            return 0;
+        }
+        let mut c = Cursor::new(&self.inner);
+        c.set_position(18);
+        c.read_u32::<BigEndian>().expect("failed to read field")
+    }
+
+    /// for ACK packets
+    ///
+    /// the buffer must have space for the ACK extension
+    pub fn set_ce_count(&mut self, val: CeCount) {
+        let mut c = Cursor::new(self.inner.borrow_mut());
+        c.set_position(18);
+        c.write_u32::<BigEndian>(val).expect("failed to write field");
+    }
+
+    /// for ERR packets
+    ///
+    /// 0 if the packet has no ERR extension
+    pub fn retry_after(&self) -> RetryAfter {
+        if self.inner.len() < 12 {
+            return 0;
+        }
+        let mut c = Cursor::new(&self.inner);
+        c.set_position(8);
+        c.read_u32::<BigEndian>().expect("failed to read field")
+    }
+
+    /// for ERR packets
+    ///
+    /// the buffer must have space for the ERR extension
+    pub fn set_retry_after(&mut self, val: RetryAfter) {
+        let mut c = Cursor::new(self.inner.borrow_mut());
+        c.set_position(8);
+        c.write_u32::<BigEndian>(val).expect("failed to write field");
+    }
+
+    /// for DATA packets
+    pub fn echo_token(&self) -> AckToken {
+        let mut c = Cursor::new(&self.inner);
+        c.set_position(2);
+        c.read_u16::<BigEndian>().expect("failed to read field")
+    }
+
+    /// for DATA packets
+    pub fn set_echo_token(&mut self, val: AckToken) {
+        let mut c = Cursor::new(self.inner.borrow_mut());
+        c.set_position(2);
+        c.write_u16::<BigEndian>(val).expect("failed to write field");
+    }
+
     /// for DATA packets
     pub fn data(&self) -> &[u8] {
         return &self.inner[16..];
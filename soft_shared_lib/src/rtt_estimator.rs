@@ -0,0 +1,158 @@
+use std::time::Duration;
+use std::cmp::{max, min};
+use crate::times::INITIAL_RTT;
+
+/// lower bound of the retransmission timeout
+pub const MIN_RTO: Duration = Duration::from_millis(200);
+/// upper bound of the retransmission timeout, also after back off
+pub const MAX_RTO: Duration = Duration::from_secs(60);
+/// weight of a new sample for the smoothed RTT
+const ALPHA: f64 = 1.0 / 8.0;
+/// weight of a new sample for the RTT variance
+const BETA: f64 = 1.0 / 4.0;
+/// weight of the RTT variance for the retransmission timeout
+const K: u32 = 4;
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(1);
+/// 2^MAX_BACKOFF * MIN_RTO exceeds MAX_RTO
+const MAX_BACKOFF: u32 = 10;
+
+/// # RTT Estimator
+///
+/// calculates the smoothed RTT and the retransmission timeout (RTO) like RFC 6298
+///
+/// samples of retransmitted packets must not be applied (Karn's algorithm)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RttEstimator {
+    /// None if no sample has been applied yet
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    /// number of consecutive retransmission timeouts
+    backoff: u32,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RttEstimator {
+
+    pub fn new() -> Self {
+        Self {
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            backoff: 0,
+        }
+    }
+
+    /// update SRTT and RTTVAR
+    ///
+    /// resets the back off, because the path is responsive again
+    pub fn apply_sample(&mut self, rtt_sample: Duration) {
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt_sample);
+                self.rtt_variance = rtt_sample / 2;
+            }
+            Some(smoothed_rtt) => {
+                let deviation = smoothed_rtt.abs_diff(rtt_sample);
+                self.rtt_variance = self.rtt_variance.mul_f64(1.0 - BETA) + deviation.mul_f64(BETA);
+                self.smoothed_rtt = Some(smoothed_rtt.mul_f64(1.0 - ALPHA) + rtt_sample.mul_f64(ALPHA));
+            }
+        }
+        self.backoff = 0;
+    }
+
+    /// INITIAL_RTT if no sample has been applied yet
+    pub fn smoothed_rtt(&self) -> Duration {
+        self.smoothed_rtt.unwrap_or(INITIAL_RTT)
+    }
+
+    pub fn rtt_variance(&self) -> Duration {
+        self.rtt_variance
+    }
+
+    /// true if at least one sample has been applied
+    pub fn has_samples(&self) -> bool {
+        self.smoothed_rtt.is_some()
+    }
+
+    /// SRTT + max(G, K * RTTVAR), doubled for every consecutive timeout
+    ///
+    /// INITIAL_RTT if no sample has been applied yet
+    pub fn rto(&self) -> Duration {
+        let rto = match self.smoothed_rtt {
+            None => INITIAL_RTT,
+            Some(smoothed_rtt) => smoothed_rtt + max(CLOCK_GRANULARITY, self.rtt_variance * K),
+        };
+        min(max(rto, MIN_RTO) * 2u32.pow(self.backoff), MAX_RTO)
+    }
+
+    /// double the RTO
+    ///
+    /// should be called on retransmission timeouts
+    pub fn on_timeout(&mut self) {
+        self.backoff = min(self.backoff + 1, MAX_BACKOFF);
+    }
+
+    /// forget all samples
+    ///
+    /// e.g. when the path has changed
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtt_estimator::{RttEstimator, MIN_RTO, MAX_RTO};
+    use crate::times::INITIAL_RTT;
+    use std::time::Duration;
+
+    #[test]
+    fn first_sample() {
+        let mut estimator = RttEstimator::new();
+        assert_eq!(estimator.rto(), INITIAL_RTT);
+        estimator.apply_sample(Duration::from_millis(400));
+        assert_eq!(estimator.smoothed_rtt(), Duration::from_millis(400));
+        assert_eq!(estimator.rtt_variance(), Duration::from_millis(200));
+        assert_eq!(estimator.rto(), Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn variance() {
+        let mut estimator = RttEstimator::new();
+        estimator.apply_sample(Duration::from_millis(400));
+        estimator.apply_sample(Duration::from_millis(800));
+        assert_eq!(estimator.smoothed_rtt(), Duration::from_millis(450));
+        assert_eq!(estimator.rtt_variance(), Duration::from_millis(250));
+        assert_eq!(estimator.rto(), Duration::from_millis(1450));
+    }
+
+    #[test]
+    fn bounds() {
+        let mut estimator = RttEstimator::new();
+        estimator.apply_sample(Duration::from_micros(10));
+        assert_eq!(estimator.rto(), MIN_RTO);
+        estimator.apply_sample(Duration::from_secs(1000));
+        assert_eq!(estimator.rto(), MAX_RTO);
+    }
+
+    #[test]
+    fn backoff() {
+        let mut estimator = RttEstimator::new();
+        estimator.apply_sample(Duration::from_millis(100));
+        let rto = estimator.rto();
+        estimator.on_timeout();
+        assert_eq!(estimator.rto(), rto * 2);
+        estimator.on_timeout();
+        assert_eq!(estimator.rto(), rto * 4);
+        for _ in 0..20 {
+            estimator.on_timeout();
+        }
+        assert_eq!(estimator.rto(), MAX_RTO);
+        estimator.apply_sample(Duration::from_millis(100));
+        assert!(estimator.rto() < rto * 2);
+    }
+}
@@ -11,4 +11,6 @@ pub enum SoftErrorCode {
     InvalidOffset = 5,
     UnsupportedVersion = 6,
     FileChanged = 7,
+    AccessDenied = 8,
+    ServerUnavailable = 9,
 }
@@ -1,25 +1,52 @@
 use std::time::Duration;
-use std::cmp::max;
+use std::cmp::{max, min};
+use crate::field_types::RetryAfter;
 
 pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
 pub const INITIAL_RTT: Duration = Duration::from_secs(3);
 pub const MIN_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
 pub const MIN_PATH_CACHE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_PERSIST_TIMEOUT: Duration = Duration::from_secs(60);
+/// if the server does not estimate when the checksum is ready
+pub const DEFAULT_CHECKSUM_RETRY_DELAY: Duration = Duration::from_secs(5);
+pub const MIN_CHECKSUM_RETRY_DELAY: Duration = Duration::from_millis(10);
+/// the estimate of the server is refined with every retry
+pub const MAX_CHECKSUM_RETRY_DELAY: Duration = Duration::from_secs(30);
 
 pub fn ack_packet_retransmission_timeout(rtt: Duration) -> Duration {
     return max(rtt * 3, Duration::from_millis(100));
 }
 
-pub fn data_packet_retransmission_timeout(rtt: Duration) -> Duration {
-    return rtt * 2;
+pub fn path_cache_timeout(rtt: Duration) -> Duration {
+    path_cache_timeout_with_min(rtt, MIN_PATH_CACHE_TIMEOUT)
 }
 
-pub fn path_cache_timeout(rtt: Duration) -> Duration {
-    return max(rtt * 20, MIN_PATH_CACHE_TIMEOUT);
+/// with a configured minimum instead of MIN_PATH_CACHE_TIMEOUT
+pub fn path_cache_timeout_with_min(rtt: Duration, min_timeout: Duration) -> Duration {
+    max(rtt * 20, min_timeout)
+}
+
+pub fn connection_timeout(rtt: Duration) -> Duration { connection_timeout_with_min(rtt, MIN_CONNECTION_TIMEOUT) }
+
+/// with a configured minimum instead of MIN_CONNECTION_TIMEOUT
+pub fn connection_timeout_with_min(rtt: Duration, min_timeout: Duration) -> Duration {
+    max(rtt * 20, min_timeout)
+}
+
+/// doubled for every window probe that has not reopened the receive window
+pub fn persist_timeout(rto: Duration, window_probes: u32) -> Duration {
+    persist_timeout_with_max(rto, window_probes, MAX_PERSIST_TIMEOUT)
 }
 
-pub fn packet_loss_timeout(rtt: Duration) -> Duration {
-    return rtt * 2;
+/// with a configured maximum instead of MAX_PERSIST_TIMEOUT
+pub fn persist_timeout_with_max(rto: Duration, window_probes: u32, max_timeout: Duration) -> Duration {
+    min(rto.checked_mul(2u32.saturating_pow(window_probes)).unwrap_or(max_timeout), max_timeout)
 }
 
-pub fn connection_timeout(rtt: Duration) -> Duration { max(rtt * 20, MIN_CONNECTION_TIMEOUT) }
\ No newline at end of file
+/// delay of a new REQ after a ChecksumNotReady error, retry_after is in milliseconds
+pub fn checksum_retry_delay(retry_after: RetryAfter) -> Duration {
+    if retry_after == 0 {
+        return DEFAULT_CHECKSUM_RETRY_DELAY;
+    }
+    Duration::from_millis(retry_after as u64).clamp(MIN_CHECKSUM_RETRY_DELAY, MAX_CHECKSUM_RETRY_DELAY)
+}